@@ -0,0 +1,50 @@
+-- This file should undo anything in `up.sql`
+PRAGMA foreign_keys=off;
+
+BEGIN TRANSACTION;
+
+DROP INDEX IF EXISTS tasks_uid;
+ALTER TABLE tasks RENAME TO _tasks_old;
+CREATE TABLE tasks (
+  id    INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  what  TEXT NOT NULL DEFAULT '',
+  link  VARCHAR(2083),
+  priority INTEGER NOT NULL DEFAULT 5
+);
+INSERT INTO tasks (id, what, link, priority)
+  SELECT id, what, link, priority
+  FROM _tasks_old;
+DROP TABLE _tasks_old;
+
+DROP INDEX IF EXISTS subtasks_uid;
+ALTER TABLE subtasks RENAME TO _subtasks_old;
+CREATE TABLE subtasks (
+  id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  what TEXT NOT NULL DEFAULT '',
+  link VARCHAR(2083),
+  subtask_rank int NOT NULL,
+  task_id int NOT NULL,
+  FOREIGN KEY (task_id) REFERENCES tasks(id)
+);
+INSERT INTO subtasks (id, what, link, subtask_rank, task_id)
+  SELECT id, what, link, subtask_rank, task_id
+  FROM _subtasks_old;
+DROP TABLE _subtasks_old;
+
+DROP INDEX IF EXISTS histories_uid;
+ALTER TABLE histories RENAME TO _histories_old;
+CREATE TABLE histories (
+  id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  what TEXT NOT NULL DEFAULT '',
+  link VARCHAR(2083),
+  finish_timestamp INTEGER NOT NULL
+);
+INSERT INTO histories (id, what, link, finish_timestamp)
+  SELECT id, what, link, finish_timestamp
+  FROM _histories_old;
+DROP TABLE _histories_old;
+CREATE INDEX IF NOT EXISTS finish_ts_descend ON histories(finish_timestamp);
+
+COMMIT;
+
+PRAGMA foreign_keys=on;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+-- stable ids which, unlike the integer ids, survive tidy and are shared between devices;
+-- a finished task keeps its uid in histories
+ALTER TABLE tasks ADD uid TEXT NOT NULL DEFAULT '';
+UPDATE tasks SET uid = lower(hex(randomblob(16))) WHERE uid = '';
+CREATE UNIQUE INDEX IF NOT EXISTS tasks_uid ON tasks(uid);
+
+ALTER TABLE subtasks ADD uid TEXT NOT NULL DEFAULT '';
+UPDATE subtasks SET uid = lower(hex(randomblob(16))) WHERE uid = '';
+CREATE UNIQUE INDEX IF NOT EXISTS subtasks_uid ON subtasks(uid);
+
+ALTER TABLE histories ADD uid TEXT NOT NULL DEFAULT '';
+UPDATE histories SET uid = lower(hex(randomblob(16))) WHERE uid = '';
+CREATE UNIQUE INDEX IF NOT EXISTS histories_uid ON histories(uid);
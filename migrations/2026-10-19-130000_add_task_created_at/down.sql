@@ -0,0 +1,23 @@
+-- This file should undo anything in `up.sql`
+PRAGMA foreign_keys=off;
+
+BEGIN TRANSACTION;
+
+DROP INDEX IF EXISTS tasks_uid;
+ALTER TABLE tasks RENAME TO _tasks_old;
+CREATE TABLE tasks (
+  id    INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  what  TEXT NOT NULL DEFAULT '',
+  link  VARCHAR(2083),
+  priority INTEGER NOT NULL DEFAULT 5,
+  uid TEXT NOT NULL DEFAULT ''
+);
+INSERT INTO tasks (id, what, link, priority, uid)
+  SELECT id, what, link, priority, uid
+  FROM _tasks_old;
+DROP TABLE _tasks_old;
+CREATE UNIQUE INDEX IF NOT EXISTS tasks_uid ON tasks(uid);
+
+COMMIT;
+
+PRAGMA foreign_keys=on;
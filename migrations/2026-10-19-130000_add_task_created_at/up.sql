@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- unix time the task was added; tasks from before this migration get its time
+ALTER TABLE tasks ADD created_at INTEGER NOT NULL DEFAULT 0;
+UPDATE tasks SET created_at = CAST(strftime('%s', 'now') AS INTEGER) WHERE created_at = 0;
@@ -0,0 +1,25 @@
+-- This file should undo anything in `up.sql`
+PRAGMA foreign_keys=off;
+
+BEGIN TRANSACTION;
+
+DROP INDEX IF EXISTS subtasks_uid;
+ALTER TABLE subtasks RENAME TO _subtasks_old;
+CREATE TABLE subtasks (
+  id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  what TEXT NOT NULL DEFAULT '',
+  link VARCHAR(2083),
+  subtask_rank int NOT NULL,
+  task_id int NOT NULL,
+  uid TEXT NOT NULL DEFAULT '',
+  FOREIGN KEY (task_id) REFERENCES tasks(id)
+);
+INSERT INTO subtasks (id, what, link, subtask_rank, task_id, uid)
+  SELECT id, what, link, subtask_rank, task_id, uid
+  FROM _subtasks_old;
+DROP TABLE _subtasks_old;
+CREATE UNIQUE INDEX IF NOT EXISTS subtasks_uid ON subtasks(uid);
+
+COMMIT;
+
+PRAGMA foreign_keys=on;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- finished subtasks stay with their task, so that its progress can be shown
+ALTER TABLE subtasks ADD done BOOLEAN NOT NULL DEFAULT 0;
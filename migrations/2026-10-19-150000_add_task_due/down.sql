@@ -0,0 +1,24 @@
+-- This file should undo anything in `up.sql`
+PRAGMA foreign_keys=off;
+
+BEGIN TRANSACTION;
+
+DROP INDEX IF EXISTS tasks_uid;
+ALTER TABLE tasks RENAME TO _tasks_old;
+CREATE TABLE tasks (
+  id    INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  what  TEXT NOT NULL DEFAULT '',
+  link  VARCHAR(2083),
+  priority INTEGER NOT NULL DEFAULT 5,
+  uid TEXT NOT NULL DEFAULT '',
+  created_at INTEGER NOT NULL DEFAULT 0
+);
+INSERT INTO tasks (id, what, link, priority, uid, created_at)
+  SELECT id, what, link, priority, uid, created_at
+  FROM _tasks_old;
+DROP TABLE _tasks_old;
+CREATE UNIQUE INDEX IF NOT EXISTS tasks_uid ON tasks(uid);
+
+COMMIT;
+
+PRAGMA foreign_keys=on;
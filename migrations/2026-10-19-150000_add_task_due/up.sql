@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- the day a task is due as yyyy-mm-dd, which sorts like the dates
+ALTER TABLE tasks ADD due TEXT;
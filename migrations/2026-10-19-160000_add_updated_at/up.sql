@@ -0,0 +1,7 @@
+-- Your SQL goes here
+-- unix time a task was last changed; existing tasks count as changed when added
+ALTER TABLE tasks ADD updated_at INTEGER NOT NULL DEFAULT 0;
+UPDATE tasks SET updated_at = created_at;
+
+-- a finished task keeps the time it was added, 0 for history from before this
+ALTER TABLE histories ADD created_at INTEGER NOT NULL DEFAULT 0;
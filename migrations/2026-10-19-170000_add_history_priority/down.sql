@@ -0,0 +1,26 @@
+-- This file should undo anything in `up.sql`
+PRAGMA foreign_keys=off;
+
+BEGIN TRANSACTION;
+
+DROP INDEX IF EXISTS histories_uid;
+DROP INDEX IF EXISTS finish_ts_descend;
+ALTER TABLE histories RENAME TO _histories_old;
+CREATE TABLE histories (
+  id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+  what TEXT NOT NULL DEFAULT '',
+  link VARCHAR(2083),
+  finish_timestamp INTEGER NOT NULL,
+  uid TEXT NOT NULL DEFAULT '',
+  created_at INTEGER NOT NULL DEFAULT 0
+);
+INSERT INTO histories (id, what, link, finish_timestamp, uid, created_at)
+  SELECT id, what, link, finish_timestamp, uid, created_at
+  FROM _histories_old;
+DROP TABLE _histories_old;
+CREATE INDEX IF NOT EXISTS finish_ts_descend ON histories(finish_timestamp);
+CREATE UNIQUE INDEX IF NOT EXISTS histories_uid ON histories(uid);
+
+COMMIT;
+
+PRAGMA foreign_keys=on;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- the priority a task had when it was finished, NULL for history from before this
+ALTER TABLE histories ADD priority INTEGER;
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+-- unix time each field of a task or subtask was last changed, by the uid of
+-- the record, so that two copies of the db merge field by field; a field
+-- without a row hasn't changed since it was added
+CREATE TABLE field_times (
+  uid TEXT NOT NULL,
+  field TEXT NOT NULL,
+  changed_at INTEGER NOT NULL,
+  PRIMARY KEY (uid, field)
+);
+
+-- before this only the task as a whole was known to have changed
+INSERT INTO field_times (uid, field, changed_at)
+  SELECT tasks.uid, fields.field, tasks.updated_at
+  FROM tasks,
+    (SELECT 'what' AS field UNION ALL SELECT 'link' UNION ALL SELECT 'priority' UNION ALL SELECT 'due') AS fields;
+INSERT INTO field_times (uid, field, changed_at)
+  SELECT subtasks.uid, fields.field, tasks.updated_at
+  FROM subtasks JOIN tasks ON subtasks.task_id = tasks.id,
+    (SELECT 'what' AS field UNION ALL SELECT 'link') AS fields;
@@ -0,0 +1,251 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use crate::error::{storage, TodoError, TodoResult};
+use crate::models::{History, NewTask, SubTask, Task};
+use crate::query::{Page, Sort, TaskFilter};
+use crate::taskdb::{TaskDB, TaskSqlite};
+
+type IDType = i32;
+
+/// The changes to run in `AsyncTaskDB::transaction`.
+pub type Changes = Box<dyn FnMut(&mut dyn TaskDB) -> TodoResult<()> + Send>;
+
+/// The async counterpart of `TaskDB`, for services that share one store between
+/// concurrent requests. The methods behave like the ones of `TaskDB`; anything
+/// else, like putting records, goes through `transaction`.
+#[async_trait]
+pub trait AsyncTaskDB: Send + Sync {
+    async fn add_task(&self, task: NewTask) -> TodoResult<IDType>;
+    async fn add_subtask(&self, id: IDType, what: String, link: Option<String>) -> TodoResult<()>;
+    async fn get_task(&self, id: IDType) -> TodoResult<Option<Task>>;
+    async fn get_tasks(&self, pattern: Option<String>) -> TodoResult<Vec<Task>>;
+    async fn get_subtasks(&self, id: IDType) -> TodoResult<Vec<SubTask>>;
+    async fn get_all_subtasks(&self) -> TodoResult<Vec<SubTask>>;
+    async fn get_finished(&self, last_n: u32) -> TodoResult<Vec<History>>;
+    async fn get_finished_within(&self, start_ts: u32, end_ts: u32) -> TodoResult<Vec<History>>;
+    async fn query_tasks(
+        &self,
+        filter: TaskFilter,
+        sort: Sort,
+        page: Page,
+    ) -> TodoResult<Vec<Task>>;
+    async fn query_finished(
+        &self,
+        start_ts: u32,
+        end_ts: u32,
+        sort: Sort,
+        page: Page,
+    ) -> TodoResult<Vec<History>>;
+    async fn update_task_desc(&self, id: IDType, desc: String) -> TodoResult<usize>;
+    async fn update_task_priority(&self, id: IDType, pri: i32) -> TodoResult<usize>;
+    async fn remove_task(&self, id: IDType) -> TodoResult<usize>;
+    async fn remove_subtask(&self, id: IDType, subtask_rank: i32) -> TodoResult<usize>;
+    async fn finish_subtask(&self, id: IDType, subtask_rank: i32) -> TodoResult<usize>;
+    async fn finish_task(&self, id: IDType) -> TodoResult<History>;
+    async fn finish_tasks(&self, ids: Vec<IDType>) -> TodoResult<Vec<History>>;
+    async fn remove_tasks(&self, ids: Vec<IDType>) -> TodoResult<usize>;
+    async fn update_priorities(&self, priorities: Vec<(IDType, i32)>) -> TodoResult<usize>;
+    async fn schema_version(&self) -> TodoResult<Option<String>>;
+
+    /// Run `f` on the store so that either all of its changes are kept or none are.
+    async fn transaction(&self, f: Changes) -> TodoResult<()>;
+}
+
+/// `TaskSqlite` for async callers.
+///
+/// Queries run on tokio's blocking pool. Reads take a connection from a pool,
+/// so they run concurrently, while every write goes through one connection and
+/// waits for the previous one. The db is switched to WAL mode so that readers
+/// don't wait for the writer. Cloning is cheap and shares the connections.
+#[derive(Clone)]
+pub struct AsyncTaskSqlite {
+    readers: r2d2::Pool<Readers>,
+    writer: Arc<Mutex<TaskSqlite>>,
+}
+
+// how long a connection waits for a lock held by another one before failing
+const BUSY_TIMEOUT_MS: u32 = 5000;
+
+impl AsyncTaskSqlite {
+    /// Open the sqlite db at `db_url` with up to `max_readers` reading connections,
+    /// bringing it up to the current schema. This blocks while connecting.
+    pub fn open(db_url: &str, max_readers: u32) -> TodoResult<Self> {
+        let writer = TaskSqlite::open(db_url)?;
+        writer.batch_execute(&format!(
+            "PRAGMA journal_mode = WAL; PRAGMA busy_timeout = {};",
+            BUSY_TIMEOUT_MS
+        ))?;
+        let readers = r2d2::Pool::builder()
+            .max_size(max_readers)
+            .build(Readers {
+                db_url: db_url.to_owned(),
+            })
+            .map_err(storage(format!("fail to open sqlite db {}", db_url)))?;
+        Ok(AsyncTaskSqlite {
+            readers,
+            writer: Arc::new(Mutex::new(writer)),
+        })
+    }
+
+    async fn read<T, F>(&self, f: F) -> TodoResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&TaskSqlite) -> TodoResult<T> + Send + 'static,
+    {
+        let readers = self.readers.clone();
+        tokio::task::spawn_blocking(move || {
+            let db = readers.get().map_err(storage("fail to get a connection"))?;
+            f(&db)
+        })
+        .await
+        .map_err(storage("the query didn't finish"))?
+    }
+
+    async fn write<T, F>(&self, f: F) -> TodoResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut TaskSqlite) -> TodoResult<T> + Send + 'static,
+    {
+        let writer = self.writer.clone();
+        tokio::task::spawn_blocking(move || {
+            // a write that panicked may have left a transaction open
+            let mut db = writer
+                .lock()
+                .map_err(|_| TodoError::Storage("a previous write failed midway".to_owned()))?;
+            f(&mut db)
+        })
+        .await
+        .map_err(storage("the query didn't finish"))?
+    }
+}
+
+#[async_trait]
+impl AsyncTaskDB for AsyncTaskSqlite {
+    async fn add_task(&self, task: NewTask) -> TodoResult<IDType> {
+        self.write(move |db| db.add_task(task)).await
+    }
+
+    async fn add_subtask(&self, id: IDType, what: String, link: Option<String>) -> TodoResult<()> {
+        self.write(move |db| db.add_subtask(id, what, link)).await
+    }
+
+    async fn get_task(&self, id: IDType) -> TodoResult<Option<Task>> {
+        self.read(move |db| db.get_task(id)).await
+    }
+
+    async fn get_tasks(&self, pattern: Option<String>) -> TodoResult<Vec<Task>> {
+        self.read(move |db| db.get_tasks(pattern)).await
+    }
+
+    async fn get_subtasks(&self, id: IDType) -> TodoResult<Vec<SubTask>> {
+        self.read(move |db| db.get_subtasks(id)).await
+    }
+
+    async fn get_all_subtasks(&self) -> TodoResult<Vec<SubTask>> {
+        self.read(|db| db.get_all_subtasks()).await
+    }
+
+    async fn get_finished(&self, last_n: u32) -> TodoResult<Vec<History>> {
+        self.read(move |db| db.get_finished(last_n)).await
+    }
+
+    async fn get_finished_within(&self, start_ts: u32, end_ts: u32) -> TodoResult<Vec<History>> {
+        self.read(move |db| db.get_finished_within(start_ts, end_ts))
+            .await
+    }
+
+    async fn query_tasks(
+        &self,
+        filter: TaskFilter,
+        sort: Sort,
+        page: Page,
+    ) -> TodoResult<Vec<Task>> {
+        self.read(move |db| db.query_tasks(&filter, &sort, page))
+            .await
+    }
+
+    async fn query_finished(
+        &self,
+        start_ts: u32,
+        end_ts: u32,
+        sort: Sort,
+        page: Page,
+    ) -> TodoResult<Vec<History>> {
+        self.read(move |db| db.query_finished(start_ts, end_ts, &sort, page))
+            .await
+    }
+
+    async fn update_task_desc(&self, id: IDType, desc: String) -> TodoResult<usize> {
+        self.write(move |db| db.update_task_desc(id, desc)).await
+    }
+
+    async fn update_task_priority(&self, id: IDType, pri: i32) -> TodoResult<usize> {
+        self.write(move |db| db.update_task_priority(id, pri)).await
+    }
+
+    async fn remove_task(&self, id: IDType) -> TodoResult<usize> {
+        self.write(move |db| db.remove_task(id)).await
+    }
+
+    async fn remove_subtask(&self, id: IDType, subtask_rank: i32) -> TodoResult<usize> {
+        self.write(move |db| db.remove_subtask(id, subtask_rank))
+            .await
+    }
+
+    async fn finish_subtask(&self, id: IDType, subtask_rank: i32) -> TodoResult<usize> {
+        self.write(move |db| db.finish_subtask(id, subtask_rank))
+            .await
+    }
+
+    async fn finish_task(&self, id: IDType) -> TodoResult<History> {
+        self.write(move |db| db.finish_task(id)).await
+    }
+
+    async fn finish_tasks(&self, ids: Vec<IDType>) -> TodoResult<Vec<History>> {
+        self.write(move |db| db.finish_tasks(&ids)).await
+    }
+
+    async fn remove_tasks(&self, ids: Vec<IDType>) -> TodoResult<usize> {
+        self.write(move |db| db.remove_tasks(&ids)).await
+    }
+
+    async fn update_priorities(&self, priorities: Vec<(IDType, i32)>) -> TodoResult<usize> {
+        self.write(move |db| db.update_priorities(&priorities))
+            .await
+    }
+
+    async fn schema_version(&self) -> TodoResult<Option<String>> {
+        self.read(|db| db.schema_version()).await
+    }
+
+    async fn transaction(&self, mut f: Changes) -> TodoResult<()> {
+        self.write(move |db| db.transaction(&mut *f)).await
+    }
+}
+
+// hands out reading connections to the pool; the writer has already
+// migrated the db and turned on WAL, which sticks to the file
+struct Readers {
+    db_url: String,
+}
+
+impl r2d2::ManageConnection for Readers {
+    type Connection = TaskSqlite;
+    type Error = TodoError;
+
+    fn connect(&self) -> TodoResult<TaskSqlite> {
+        let db = TaskSqlite::connect(&self.db_url)?;
+        db.batch_execute(&format!("PRAGMA busy_timeout = {};", BUSY_TIMEOUT_MS))?;
+        Ok(db)
+    }
+
+    fn is_valid(&self, db: &mut TaskSqlite) -> TodoResult<()> {
+        db.batch_execute("SELECT 1;")
+    }
+
+    fn has_broken(&self, _db: &mut TaskSqlite) -> bool {
+        false
+    }
+}
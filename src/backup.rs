@@ -0,0 +1,248 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::models::{History, SubTask, Task};
+use crate::taskdb::TaskDB;
+
+const BACKUP_FORMAT: &str = "todo-backup";
+// bump when the layout of `Backup` changes
+pub const BACKUP_VERSION: u32 = 1;
+
+/// A snapshot of every table of the store.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Backup {
+    pub format: String,
+    pub version: u32,
+    pub schema_version: Option<String>,
+    pub created_at: i64,
+    pub tasks: Vec<Task>,
+    pub subtasks: Vec<SubTask>,
+    pub histories: Vec<History>,
+}
+
+/// What to do with a record whose id is already taken by a different record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    Fail,
+    Skip,
+    Replace,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "fail" => Ok(ConflictPolicy::Fail),
+            "skip" => Ok(ConflictPolicy::Skip),
+            "replace" => Ok(ConflictPolicy::Replace),
+            _ => bail!(
+                "unknown conflict policy `{}`, expect fail, skip or replace",
+                s
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RestoreSummary {
+    pub inserted: usize,
+    pub replaced: usize,
+    pub skipped: usize,
+    pub unchanged: usize,
+}
+
+impl Display for RestoreSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} inserted, {} replaced, {} skipped, {} unchanged",
+            self.inserted, self.replaced, self.skipped, self.unchanged
+        )
+    }
+}
+
+pub fn take_backup(db: &dyn TaskDB) -> Result<Backup> {
+    Ok(Backup {
+        format: BACKUP_FORMAT.to_owned(),
+        version: BACKUP_VERSION,
+        schema_version: db.schema_version()?,
+        created_at: chrono::Utc::now().timestamp(),
+        tasks: db.get_tasks(None)?,
+        subtasks: db.get_all_subtasks()?,
+        histories: db.get_finished_within(0, i32::MAX as u32)?,
+    })
+}
+
+pub fn write_backup<W: Write>(out: W, backup: &Backup) -> Result<()> {
+    serde_json::to_writer_pretty(out, backup)?;
+    Ok(())
+}
+
+pub fn read_backup<R: Read>(input: R) -> Result<Backup> {
+    let backup: Backup = serde_json::from_reader(input).context("malformed backup file")?;
+    if backup.format != BACKUP_FORMAT {
+        bail!("not a todo backup (format `{}`)", backup.format);
+    }
+    if backup.version != BACKUP_VERSION {
+        bail!(
+            "unsupported backup version {}, expect {}",
+            backup.version,
+            BACKUP_VERSION
+        );
+    }
+    Ok(backup)
+}
+
+/// Check the backup against itself and against the store it is about to be loaded into.
+pub fn validate(db: &dyn TaskDB, backup: &Backup) -> Result<()> {
+    let schema_version = db.schema_version()?;
+    if backup.schema_version != schema_version {
+        bail!(
+            "backup schema version {} does not match store schema version {}",
+            backup.schema_version.as_deref().unwrap_or("none"),
+            schema_version.as_deref().unwrap_or("none")
+        );
+    }
+    check_unique_ids("task", backup.tasks.iter().map(|t| t.id))?;
+    check_unique_ids("subtask", backup.subtasks.iter().map(|st| st.id))?;
+    check_unique_ids("history", backup.histories.iter().map(|h| h.id))?;
+
+    let mut task_ids: HashSet<i32> = backup.tasks.iter().map(|t| t.id).collect();
+    task_ids.extend(db.get_tasks(None)?.iter().map(|t| t.id));
+    if let Some(st) = backup
+        .subtasks
+        .iter()
+        .find(|st| !task_ids.contains(&st.task_id))
+    {
+        bail!("subtask {} belongs to unknown task {}", st.id, st.task_id);
+    }
+    Ok(())
+}
+
+/// Load a validated backup into the store.
+/// Records identical to the stored ones are left alone; the others are
+/// handled by `policy` when their id is taken.
+pub fn restore(
+    db: &mut dyn TaskDB,
+    backup: &Backup,
+    policy: ConflictPolicy,
+) -> Result<RestoreSummary> {
+    validate(db, backup)?;
+
+    let tasks: HashMap<i32, Task> = db.get_tasks(None)?.into_iter().map(|t| (t.id, t)).collect();
+    let subtasks: HashMap<i32, SubTask> = db
+        .get_all_subtasks()?
+        .into_iter()
+        .map(|st| (st.id, st))
+        .collect();
+    let histories: HashMap<i32, History> = db
+        .get_finished_within(0, i32::MAX as u32)?
+        .into_iter()
+        .map(|h| (h.id, h))
+        .collect();
+
+    if policy == ConflictPolicy::Fail {
+        let mut conflicts = vec![];
+        conflicts.extend(conflicting_ids("task", &backup.tasks, &tasks, |t| t.id));
+        conflicts.extend(conflicting_ids(
+            "subtask",
+            &backup.subtasks,
+            &subtasks,
+            |st| st.id,
+        ));
+        conflicts.extend(conflicting_ids(
+            "history",
+            &backup.histories,
+            &histories,
+            |h| h.id,
+        ));
+        if !conflicts.is_empty() {
+            bail!(
+                "conflicting records: {}; use --on-conflict skip or replace",
+                conflicts.join(", ")
+            );
+        }
+    }
+
+    let mut summary = RestoreSummary::default();
+    let put_tasks: Vec<&Task> = backup
+        .tasks
+        .iter()
+        .filter(|t| summary.record(policy, tasks.get(&t.id), *t))
+        .collect();
+    let put_subtasks: Vec<&SubTask> = backup
+        .subtasks
+        .iter()
+        .filter(|st| summary.record(policy, subtasks.get(&st.id), *st))
+        .collect();
+    let put_histories: Vec<&History> = backup
+        .histories
+        .iter()
+        .filter(|h| summary.record(policy, histories.get(&h.id), *h))
+        .collect();
+    // a restore that fails halfway leaves the store as it was
+    db.transaction(&mut |db| {
+        put_tasks.iter().try_for_each(|t| db.put_task(t))?;
+        put_subtasks.iter().try_for_each(|st| db.put_subtask(st))?;
+        put_histories.iter().try_for_each(|h| db.put_history(h))
+    })?;
+    Ok(summary)
+}
+
+impl RestoreSummary {
+    // count the record and tell whether it should be written
+    fn record<T: PartialEq>(
+        &mut self,
+        policy: ConflictPolicy,
+        current: Option<&T>,
+        new: &T,
+    ) -> bool {
+        match current {
+            None => {
+                self.inserted += 1;
+                true
+            }
+            Some(c) if c == new => {
+                self.unchanged += 1;
+                false
+            }
+            Some(_) if policy == ConflictPolicy::Skip => {
+                self.skipped += 1;
+                false
+            }
+            Some(_) => {
+                self.replaced += 1;
+                true
+            }
+        }
+    }
+}
+
+fn check_unique_ids(kind: &str, ids: impl Iterator<Item = i32>) -> Result<()> {
+    let mut seen = HashSet::new();
+    for id in ids {
+        if !seen.insert(id) {
+            bail!("duplicate {} id {} in backup", kind, id);
+        }
+    }
+    Ok(())
+}
+
+fn conflicting_ids<T: PartialEq>(
+    kind: &str,
+    records: &[T],
+    current: &HashMap<i32, T>,
+    id_of: impl Fn(&T) -> i32,
+) -> Vec<String> {
+    records
+        .iter()
+        .filter(|r| matches!(current.get(&id_of(r)), Some(c) if c != *r))
+        .map(|r| format!("{} {}", kind, id_of(r)))
+        .collect()
+}
@@ -0,0 +1,123 @@
+use std::str::FromStr;
+
+use chrono::{Datelike, Duration, NaiveDate, TimeZone};
+
+use crate::error::{TodoError, TodoResult};
+use crate::models::{History, Task};
+use crate::stats::{local_day, Period};
+
+/// The open tasks due soon, by day.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Agenda {
+    pub today: NaiveDate,
+    /// The first day after the agenda.
+    pub end: NaiveDate,
+    /// The tasks due before `today`, the most overdue first.
+    pub overdue: Vec<Task>,
+    /// Each of the days from `today` on that has tasks due, with them, the
+    /// highest priority first.
+    pub days: Vec<(NaiveDate, Vec<Task>)>,
+}
+
+impl Agenda {
+    /// The agenda of the `days` days starting with `today`. Fails with
+    /// `InvalidInput` when they run past the last date chrono has.
+    pub fn new(tasks: &[Task], today: NaiveDate, days: u32) -> TodoResult<Self> {
+        let end = today
+            .checked_add_signed(Duration::days(days as i64))
+            .ok_or_else(|| {
+                TodoError::InvalidInput(format!("{} days from {} is too far ahead", days, today))
+            })?;
+        let mut overdue: Vec<&Task> = tasks.iter().filter(|t| t.is_overdue(today)).collect();
+        overdue.sort_by_key(|t| (t.due_date(), t.id));
+
+        let mut due: Vec<(NaiveDate, &Task)> = tasks
+            .iter()
+            .filter_map(|t| Some((t.due_date()?, t)))
+            .filter(|(day, _)| today <= *day && *day < end)
+            .collect();
+        due.sort_by_key(|(day, t)| (*day, -t.priority, t.id));
+        let mut by_day: Vec<(NaiveDate, Vec<Task>)> = vec![];
+        for (day, task) in due {
+            match by_day.last_mut() {
+                Some((last, tasks)) if *last == day => tasks.push(task.clone()),
+                _ => by_day.push((day, vec![task.clone()])),
+            }
+        }
+
+        Ok(Agenda {
+            today,
+            end,
+            overdue: overdue.into_iter().cloned().collect(),
+            days: by_day,
+        })
+    }
+}
+
+/// A month of a year, `2026-10` on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Month {
+    /// The first day of the month.
+    pub first: NaiveDate,
+}
+
+impl Month {
+    /// The month `date` is in.
+    pub fn of(date: NaiveDate) -> Self {
+        Month {
+            first: Period::Month.start(date),
+        }
+    }
+
+    /// The days of the month, the first one first.
+    pub fn days(self) -> Vec<NaiveDate> {
+        self.first
+            .iter_days()
+            .take_while(|day| Month::of(*day) == self)
+            .collect()
+    }
+}
+
+impl FromStr for Month {
+    type Err = TodoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        NaiveDate::parse_from_str(&format!("{}-01", s), "%Y-%m-%d")
+            .map(|first| Month { first })
+            .map_err(|_| TodoError::InvalidInput(format!("`{}` is not a month, expect yyyy-mm", s)))
+    }
+}
+
+/// How many open tasks are due and how many were finished on each day of a
+/// month.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Calendar {
+    pub month: Month,
+    /// Per day of the month, the first day first.
+    pub due: Vec<usize>,
+    pub finished: Vec<usize>,
+}
+
+impl Calendar {
+    /// The calendar of `month`, finish days being those of the timezone `tz`.
+    pub fn new<Tz: TimeZone>(month: Month, tasks: &[Task], histories: &[History], tz: &Tz) -> Self {
+        let days = month.days().len();
+        let index = |day: NaiveDate| (Month::of(day) == month).then(|| day.day0() as usize);
+        let mut due = vec![0; days];
+        for i in tasks.iter().filter_map(|t| index(t.due_date()?)) {
+            due[i] += 1;
+        }
+        let mut finished = vec![0; days];
+        for i in histories
+            .iter()
+            .filter_map(|h| index(local_day(h.finish_timestamp, tz)))
+        {
+            finished[i] += 1;
+        }
+        Calendar {
+            month,
+            due,
+            finished,
+        }
+    }
+}
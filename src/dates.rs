@@ -0,0 +1,204 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Weekday};
+
+use crate::error::{TodoError, TodoResult};
+use crate::stats::Period;
+
+/// Which way a date without one points: `monday` or `2w` is the coming one
+/// for a due date, the last one for a history range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tense {
+    Past,
+    Future,
+}
+
+/// A day, and maybe a time of it, as typed on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct When {
+    pub date: NaiveDate,
+    pub time: Option<NaiveTime>,
+}
+
+impl When {
+    /// Reads `input` relative to `now`, local time:
+    ///
+    /// - a day: `2026-10-19`, `today`, `yesterday`, `tomorrow`, a weekday like
+    ///   `monday` or `mon`, `last`/`next` followed by a weekday, `week` or
+    ///   `month`, `this week`, `this month`, or the end of them: `eow`, `eom`
+    ///   and `eoy`
+    /// - an offset from today: `+3d`, `-2w`, or `3d` and `2w` towards `tense`;
+    ///   `+3h` and `-3h` from now
+    /// - `now`
+    ///
+    /// Days may be followed by a time, `14:30`, `2pm` or `2:30pm`, with an
+    /// optional `at` in between. A time alone is today.
+    pub fn parse(input: &str, now: NaiveDateTime, tense: Tense) -> TodoResult<When> {
+        let invalid = || {
+            TodoError::InvalidInput(format!(
+                "`{}` is not a date, expected yyyy-mm-dd, today, yesterday, tomorrow, \
+                 a weekday, last/this/next week or month, eow, eom, eoy, +3d, -2w, 12h \
+                 or now, maybe followed by a time like 14:30 or 2pm",
+                input
+            ))
+        };
+        let lower = input.trim().to_lowercase();
+        let mut words: Vec<&str> = lower.split_whitespace().collect();
+        let time = match words.last().and_then(|w| parse_time(w)) {
+            Some(time) => {
+                words.pop();
+                if words.last() == Some(&"at") {
+                    words.pop();
+                }
+                Some(time)
+            }
+            None => None,
+        };
+        let today = now.date();
+        let when = match words.as_slice() {
+            [] if time.is_some() => When { date: today, time },
+            [word] => match parse_moment(word, now, tense) {
+                Some(_) if time.is_some() => {
+                    return Err(TodoError::InvalidInput(format!(
+                        "`{}` is already a time of day, `{}` can't be added",
+                        word,
+                        input.trim()
+                    )))
+                }
+                Some(moment) => When {
+                    date: moment.date(),
+                    time: Some(moment.time()),
+                },
+                None => When {
+                    date: parse_day(word, today, tense).ok_or_else(invalid)?,
+                    time,
+                },
+            },
+            [which, what] => When {
+                date: parse_relative(which, what, today).ok_or_else(invalid)?,
+                time,
+            },
+            _ => return Err(invalid()),
+        };
+        Ok(when)
+    }
+
+    /// Like `parse`, for a day without a time of day.
+    pub fn parse_date(input: &str, now: NaiveDateTime, tense: Tense) -> TodoResult<NaiveDate> {
+        match When::parse(input, now, tense)? {
+            When { date, time: None } => Ok(date),
+            When { time: Some(_), .. } => Err(TodoError::InvalidInput(format!(
+                "`{}` has a time of day, expected a day only",
+                input
+            ))),
+        }
+    }
+
+    /// The unix time it is in the timezone `tz`, the start of the day
+    /// without a time.
+    pub fn timestamp<Tz: TimeZone>(&self, tz: &Tz) -> i64 {
+        local_timestamp(
+            self.date
+                .and_time(self.time.unwrap_or_else(|| NaiveTime::from_hms(0, 0, 0))),
+            tz,
+        )
+    }
+}
+
+/// The unix time `at` is in the timezone `tz`.
+pub fn local_timestamp<Tz: TimeZone>(at: NaiveDateTime, tz: &Tz) -> i64 {
+    // in a daylight saving gap tz can't place `at`, take it as utc
+    match tz.from_local_datetime(&at).earliest() {
+        Some(at) => at.timestamp(),
+        None => tz.from_utc_datetime(&at).timestamp(),
+    }
+}
+
+// `now` and offsets in hours from it
+fn parse_moment(word: &str, now: NaiveDateTime, tense: Tense) -> Option<NaiveDateTime> {
+    if word == "now" {
+        return Some(now);
+    }
+    let hours = parse_offset(word, 'h', tense)?;
+    now.checked_add_signed(Duration::hours(hours))
+}
+
+fn parse_day(word: &str, today: NaiveDate, tense: Tense) -> Option<NaiveDate> {
+    let day = match word {
+        "today" => today,
+        "yesterday" => today.pred(),
+        "tomorrow" => today.succ(),
+        "eow" => Period::Week.next(Period::Week.start(today))?.pred(),
+        "eom" => Period::Month.next(Period::Month.start(today))?.pred(),
+        "eoy" => NaiveDate::from_ymd(today.year(), 12, 31),
+        _ => {
+            if let Ok(date) = NaiveDate::parse_from_str(word, "%Y-%m-%d") {
+                return Some(date);
+            }
+            if let Ok(weekday) = word.parse::<Weekday>() {
+                // today, or the closest one towards tense
+                let ahead = weekday.num_days_from_monday() as i64
+                    - today.weekday().num_days_from_monday() as i64;
+                return Some(match tense {
+                    Tense::Future => today + Duration::days(ahead.rem_euclid(7)),
+                    Tense::Past => today - Duration::days((-ahead).rem_euclid(7)),
+                });
+            }
+            // offsets past the dates chrono has are no date either
+            if let Some(weeks) = parse_offset(word, 'w', tense) {
+                return today.checked_add_signed(Duration::weeks(weeks));
+            }
+            today.checked_add_signed(Duration::days(parse_offset(word, 'd', tense)?))?
+        }
+    };
+    Some(day)
+}
+
+// `last monday`, `next week`, `this month` and the like; weeks and months
+// start on their first day
+fn parse_relative(which: &str, what: &str, today: NaiveDate) -> Option<NaiveDate> {
+    let period = match what {
+        "week" => Some(Period::Week),
+        "month" => Some(Period::Month),
+        _ => None,
+    };
+    if let Some(period) = period {
+        let start = period.start(today);
+        return match which {
+            "last" => period.previous(start),
+            "this" => Some(start),
+            "next" => period.next(start),
+            _ => None,
+        };
+    }
+    // the closest weekday before or after today, a week away at most
+    what.parse::<Weekday>().ok()?;
+    match which {
+        "last" => parse_day(what, today.pred(), Tense::Past),
+        "next" => parse_day(what, today.succ(), Tense::Future),
+        _ => None,
+    }
+}
+
+// a count of `unit`, like `+3d`, `-2w` or `3d` towards tense
+fn parse_offset(word: &str, unit: char, tense: Tense) -> Option<i64> {
+    let word = word.strip_suffix(unit)?;
+    let (sign, count) = match word.as_bytes().first()? {
+        b'+' => (1, &word[1..]),
+        b'-' => (-1, &word[1..]),
+        _ if tense == Tense::Past => (-1, word),
+        _ => (1, word),
+    };
+    let count: u32 = count.parse().ok()?;
+    Some(sign * count as i64)
+}
+
+// `14:30`, `2pm` or `2:30pm`
+fn parse_time(word: &str) -> Option<NaiveTime> {
+    let (clock, offset) = match (word.strip_suffix("am"), word.strip_suffix("pm")) {
+        (Some(clock), _) => (clock, 0),
+        (_, Some(clock)) => (clock, 12),
+        _ => return NaiveTime::parse_from_str(word, "%H:%M").ok(),
+    };
+    let (hour, minute) = clock.split_once(':').unwrap_or((clock, "0"));
+    let hour: u32 = hour.parse().ok().filter(|h| (1..=12).contains(h))?;
+    NaiveTime::from_hms_opt(hour % 12 + offset, minute.parse().ok()?, 0)
+}
@@ -1,48 +1,69 @@
 use std::fmt::Display;
+use std::io::{IsTerminal, Write};
+use std::str::FromStr;
 
-use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime, Utc};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
+use super::calendar::{Agenda, Calendar};
+use super::error::TodoError;
 use super::models::{History, SubTask, Task};
+use super::stats::Stats;
 
+// the description columns are `pad`ded beforehand, `{: <50}` would count chars
+// instead of how wide they show
 macro_rules! my_format {
     (task) => {
-        "{0: <10} {1: <50} {2: <10}"
+        "{0: <10} {1} {2: <10}"
     };
     (task_multiline) => {
-        "{0: <10} {1: <50} {2: <10}\n"
+        "{0: <10} {1} {2: <10}\n"
     };
     (subtask) => {
-        "{0: <10} {1: <50} {2: <10}"
+        "{0: <10} {1} {2: <10}"
     };
     (indent_subtask) => {
-        "{0}{1: <10} {2: <30} {3: <10}"
+        "{0}{1: <10} {2} {3: <10}"
+    };
+    (tree_task) => {
+        "{0: <10} {1} {2: <6} {3}"
     };
     (history) => {
-        "{: <15} {: <50} {}"
+        "{: <15} {: <6} {} {}"
     };
     (id_history) => {
-        "{: <10} {: <15} {: <50} {}"
+        "{: <10} {: <15} {: <6} {} {}"
     };
 }
 
+// the width of the description column of the tables above
+const DESC_WIDTH: usize = 50;
+const SUBTASK_DESC_WIDTH: usize = 30;
+
 impl Display for Task {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let desc_lines: Vec<&str> = self.what.split('\n').collect();
         let num_lines = desc_lines.len();
         if num_lines <= 1 {
             match &self.link {
-                Some(l) => write!(f, my_format!(task), self.id, self.what, l),
-                None => write!(f, my_format!(task), self.id, self.what, ""),
+                Some(l) => write!(f, my_format!(task), self.id, pad(&self.what, DESC_WIDTH), l),
+                None => write!(
+                    f,
+                    my_format!(task),
+                    self.id,
+                    pad(&self.what, DESC_WIDTH),
+                    ""
+                ),
             }
         } else {
             let mut desc_iter = desc_lines.into_iter();
-            let first_line = desc_iter.next().unwrap();
+            let first_line = pad(desc_iter.next().unwrap(), DESC_WIDTH);
             match &self.link {
                 Some(l) => write!(f, my_format!(task_multiline), self.id, first_line, l),
                 None => write!(f, my_format!(task_multiline), self.id, first_line, ""),
             }?;
             for line in desc_iter {
-                write!(f, my_format!(task_multiline), "", line, "")?;
+                write!(f, my_format!(task_multiline), "", pad(line, DESC_WIDTH), "")?;
             }
             Ok(())
         }
@@ -51,44 +72,81 @@ impl Display for Task {
 
 impl Display for SubTask {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let what = pad(&subtask_what(self), DESC_WIDTH);
         match &self.link {
-            Some(l) => write!(f, my_format!(subtask), self.subtask_rank, self.what, l),
-            None => write!(f, my_format!(subtask), self.subtask_rank, self.what, ""),
+            Some(l) => write!(f, my_format!(subtask), self.subtask_rank, what, l),
+            None => write!(f, my_format!(subtask), self.subtask_rank, what, ""),
         }
     }
 }
 
+// the description of a subtask, checked off once it is done
+fn subtask_what(subtask: &SubTask) -> String {
+    if subtask.done {
+        format!("[x] {}", subtask.what)
+    } else {
+        subtask.what.clone()
+    }
+}
+
+/// A number of seconds in its largest whole unit, e.g. `40m`, `5h` or `12d`.
+pub fn span(seconds: i32) -> String {
+    match seconds.max(0) {
+        s if s < 3600 => format!("{}m", s / 60),
+        s if s < 24 * 3600 => format!("{}h", s / 3600),
+        s => format!("{}d", s / (24 * 3600)),
+    }
+}
+
+// a unix timestamp in the local timezone, as `format` says
+fn local_time(timestamp: i32, format: &str) -> String {
+    let date = NaiveDateTime::from_timestamp(timestamp as i64, 0);
+    let date: DateTime<Utc> = DateTime::from_utc(date, Utc);
+    let date: DateTime<Local> = DateTime::from(date);
+    date.format(format).to_string()
+}
+
 impl Display for History {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let date = NaiveDateTime::from_timestamp(self.finish_timestamp as i64, 0);
-        let date: DateTime<Utc> = DateTime::from_utc(date, Utc);
-        let date: DateTime<Local> = DateTime::from(date);
-        // let date = date.format("%Y-%m-%d %H:%M:%S");
-        let date = date.format("%Y-%m-%d");
+        let date = local_time(self.finish_timestamp, "%Y-%m-%d");
+        let lead = self.lead_time().map_or("-".to_owned(), span);
+        let what = pad(&self.what, DESC_WIDTH);
         if let Some(l) = &self.link {
-            write!(f, my_format!(history), date, self.what, l)
+            write!(f, my_format!(history), date, lead, what, l)
         } else {
-            write!(f, my_format!(history), date, self.what, "")
+            write!(f, my_format!(history), date, lead, what, "")
         }
     }
 }
 
-pub fn prompt_finished_task() {
-    println!(
+/// The header line of `history`.
+pub fn finished_task_header() -> String {
+    format!(
         my_format!(id_history),
-        "task_id", "date", "description", "link(optional)"
-    );
+        "task_id",
+        "date",
+        "lead",
+        pad("description", DESC_WIDTH),
+        "link(optional)"
+    )
 }
 
 pub fn prompt_task() {
-    println!(my_format!(task), "task_id", "description", "link(optional)");
+    println!(
+        my_format!(task),
+        "task_id",
+        pad("description", DESC_WIDTH),
+        "link(optional)"
+    );
 }
 
 pub fn prompt_subtask(id: i32) {
     println!("subtask of {}", &id);
     println!(
         my_format!(subtask),
-        "subtask_rank", "description", "link(optional)"
+        "subtask_rank",
+        pad("description", DESC_WIDTH),
+        "link(optional)"
     );
 }
 
@@ -100,14 +158,797 @@ pub fn print_subtasks(subtasks_to_print: Vec<SubTask>, indent_level: usize) -> V
             if let Some(l) = &st.link {
                 format!(
                     my_format!(indent_subtask),
-                    indent, st.subtask_rank, st.what, l,
+                    indent,
+                    st.subtask_rank,
+                    pad(&subtask_what(st), SUBTASK_DESC_WIDTH),
+                    l,
                 )
             } else {
                 format!(
                     my_format!(indent_subtask),
-                    indent, st.subtask_rank, st.what, ""
+                    indent,
+                    st.subtask_rank,
+                    pad(&subtask_what(st), SUBTASK_DESC_WIDTH),
+                    ""
                 )
             }
         })
         .collect()
 }
+
+/// How to fold the subtasks in `print_tree`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fold {
+    // only the task lines with their progress
+    pub collapse: bool,
+    // leave out the subtasks that are done
+    pub hide_done: bool,
+}
+
+/// Every task with its subtasks nested underneath, and how many of them are
+/// done, e.g. `2/5`. A multi-line description shows its first line.
+pub fn print_tree(tasks: &[Task], subtasks: &[SubTask], fold: Fold) -> Vec<String> {
+    let mut lines = vec![];
+    for task in tasks {
+        let own: Vec<SubTask> = subtasks
+            .iter()
+            .filter(|st| st.task_id == task.id)
+            .cloned()
+            .collect();
+        let progress = if own.is_empty() {
+            String::new()
+        } else {
+            let done = own.iter().filter(|st| st.done).count();
+            format!("{}/{}", done, own.len())
+        };
+        let first_line = task.what.lines().next().unwrap_or_default();
+        let line = format!(
+            my_format!(tree_task),
+            task.id,
+            pad(first_line, DESC_WIDTH),
+            progress,
+            task.link.as_deref().unwrap_or_default()
+        );
+        lines.push(line.trim_end().to_owned());
+        if fold.collapse {
+            continue;
+        }
+        let shown = own.into_iter().filter(|st| !(fold.hide_done && st.done));
+        for line in print_subtasks(shown.collect(), 1) {
+            lines.push(line.trim_end().to_owned());
+        }
+    }
+    lines
+}
+
+/// A task as one line for pickers like fzf: the id, the priority, the description
+/// and the link, separated by tabs. `fzf --delimiter '\t' --with-nth 2..` hides the id.
+pub fn picker_line(task: &Task) -> String {
+    format!(
+        "{}\t{}\t{}\t{}",
+        task.id,
+        task.priority,
+        one_line(&task.what),
+        one_line(task.link.as_deref().unwrap_or(""))
+    )
+}
+
+/// A subtask as one line for pickers: the rank, the description and the link.
+pub fn subtask_picker_line(subtask: &SubTask) -> String {
+    format!(
+        "{}\t{}\t{}",
+        subtask.subtask_rank,
+        one_line(&subtask.what),
+        one_line(subtask.link.as_deref().unwrap_or(""))
+    )
+}
+
+// tabs and line breaks would split the fields and lines of a picker
+fn one_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Everything about a task, as `show` prints it: the metadata, the whole
+/// description, the subtasks and the related history. On a terminal `width`
+/// columns wide the description is wrapped and the other lines are cut.
+pub fn task_details(
+    task: &Task,
+    subtasks: &[SubTask],
+    history: &[History],
+    width: Option<usize>,
+    theme: &Theme,
+) -> String {
+    let label = theme.paint(
+        theme.priority_label(task.priority),
+        theme.priority_color(task.priority),
+    );
+    let mut details = format!("task {}, priority {} {}\n", task.id, task.priority, label);
+    if task.created_at > 0 {
+        details.push_str(&format!(
+            "created: {}\n",
+            local_time(task.created_at, "%Y-%m-%d %H:%M")
+        ));
+    }
+    if let Some(due) = &task.due {
+        let due = if task.is_overdue(Local::today().naive_local()) {
+            theme.paint(&format!("{} (overdue)", due), theme.overdue_color())
+        } else {
+            due.clone()
+        };
+        details.push_str(&fit_line(&format!("due: {}", due), width));
+        details.push('\n');
+    }
+    if let Some(link) = &task.link {
+        let link = format!("link: {}", theme.paint(link, theme.link_color()));
+        details.push_str(&fit_line(&link, width));
+        details.push('\n');
+    }
+    details.push_str(&format!("uid: {}\n\n", task.uid));
+    match width {
+        Some(width) => wrap(&task.what, width).iter().for_each(|line| {
+            details.push_str(line);
+            details.push('\n');
+        }),
+        None => details.push_str(&format!("{}\n", task.what)),
+    }
+    if !subtasks.is_empty() {
+        details.push_str("\nsubtasks:\n");
+        for line in print_subtasks(subtasks.to_vec(), 1) {
+            details.push_str(&fit_line(line.trim_end(), width));
+            details.push('\n');
+        }
+    }
+    if !history.is_empty() {
+        details.push_str("\nfinished before:\n");
+        for h in history {
+            let line = format!("  {}", h.to_string().trim_end());
+            details.push_str(&fit_line(&line, width));
+            details.push('\n');
+        }
+    }
+    details
+}
+
+// the widest bar of the `stats` charts
+const BAR_WIDTH: usize = 40;
+
+/// The `stats` of what was finished: a bar per period, a sparkline of them,
+/// the streaks and lead time, and bars per priority band and per tag. On a
+/// terminal `width` columns wide the bars are shortened to fit.
+pub fn print_stats(stats: &Stats, width: Option<usize>, theme: &Theme) -> String {
+    let mut out = format!("finished per {}\n", stats.period.name());
+    let rows: Vec<(String, usize)> = stats
+        .counts
+        .iter()
+        .map(|(start, count)| (stats.period.label(*start), *count))
+        .collect();
+    out.push_str(&bar_chart(&rows, width));
+    let counts: Vec<usize> = stats.counts.iter().map(|(_, count)| *count).collect();
+    out.push_str(&format!("trend: {}\n\n", sparkline(&counts)));
+
+    let plural = |n: usize, what: &str| format!("{} {}{}", n, what, if n == 1 { "" } else { "s" });
+    out.push_str(&format!(
+        "total: {} in {}\n",
+        plural(stats.total(), "task"),
+        plural(stats.counts.len(), stats.period.name())
+    ));
+    out.push_str(&format!(
+        "streak: {}, longest {}\n",
+        plural(stats.current_streak, "day"),
+        plural(stats.longest_streak, "day")
+    ));
+    if let Some(lead) = stats.average_lead_time {
+        out.push_str(&format!("average lead time: {}\n", span(lead)));
+    }
+
+    let mut bands: Vec<(String, usize)> = (0..4)
+        .rev()
+        .map(|band| {
+            let label = &theme.labels[band];
+            (
+                theme.paint(label, theme.colors[band].filter(|_| theme.color)),
+                stats.bands[band],
+            )
+        })
+        .collect();
+    if stats.unknown_priority > 0 {
+        bands.push(("?".to_owned(), stats.unknown_priority));
+    }
+    out.push_str("\nby priority\n");
+    out.push_str(&bar_chart(&bands, width));
+    if !stats.tags.is_empty() {
+        let tags: Vec<(String, usize)> = stats
+            .tags
+            .iter()
+            .map(|(tag, count)| (format!("#{}", tag), *count))
+            .collect();
+        out.push_str("\nby tag\n");
+        out.push_str(&bar_chart(&tags, width));
+    }
+    out
+}
+
+/// The `agenda`: the overdue tasks, then the tasks due on each day, a line
+/// per task cut to a terminal `width` columns wide.
+pub fn print_agenda(agenda: &Agenda, width: Option<usize>, theme: &Theme) -> String {
+    let line = |task: &Task, due: Option<&str>| {
+        let id = format!("  {} ", task.id);
+        let what = format!(" {}", one_line(&task.what));
+        let mut parts = vec![
+            (id.as_str(), None),
+            (
+                theme.priority_label(task.priority),
+                theme.priority_color(task.priority),
+            ),
+            (what.as_str(), None),
+        ];
+        if let Some(due) = due {
+            parts.extend([(", ", None), (due, theme.overdue_color())]);
+        }
+        format!("{}\n", fit_painted(&parts, width, theme))
+    };
+    let mut out = String::new();
+    if !agenda.overdue.is_empty() {
+        out.push_str("overdue\n");
+        for task in &agenda.overdue {
+            let due = format!("due {}", task.due.as_deref().unwrap_or_default());
+            out.push_str(&line(task, Some(&due)));
+        }
+    }
+    for (day, tasks) in &agenda.days {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        let name = day.format("%a %Y-%m-%d");
+        match (*day - agenda.today).num_days() {
+            0 => out.push_str(&format!("today, {}\n", name)),
+            1 => out.push_str(&format!("tomorrow, {}\n", name)),
+            _ => out.push_str(&format!("{}\n", name)),
+        }
+        for task in tasks {
+            out.push_str(&line(task, None));
+        }
+    }
+    if out.is_empty() {
+        let days = (agenda.end - agenda.today).num_days();
+        out.push_str(&format!("nothing due in the next {} days\n", days));
+    }
+    out
+}
+
+// how wide a day of the `calendar` grid is
+const DAY_WIDTH: usize = 7;
+
+/// The `calendar` of a month: a row per week, Monday first, each day with
+/// `due/finished` under it when either is not zero. `today` is in brackets
+/// and the open tasks due before it are colored as overdue.
+pub fn print_calendar(calendar: &Calendar, today: NaiveDate, theme: &Theme) -> String {
+    let first = calendar.month.first;
+    let mut out = format!("{}\n", first.format("%B %Y"));
+    for weekday in ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"] {
+        out.push_str(&format!("{: >DAY_WIDTH$}", weekday));
+    }
+    out.push('\n');
+    let lead = first.weekday().num_days_from_monday() as usize;
+    let days = calendar.month.days();
+    let mut cells: Vec<Option<usize>> = vec![None; lead];
+    cells.extend((0..days.len()).map(Some));
+    for week in cells.chunks(7) {
+        let mut numbers = String::new();
+        let mut counts = String::new();
+        for cell in week {
+            let Some(i) = *cell else {
+                numbers.push_str(&" ".repeat(DAY_WIDTH));
+                counts.push_str(&" ".repeat(DAY_WIDTH));
+                continue;
+            };
+            let number = if days[i] == today {
+                format!("[{}]", i + 1)
+            } else {
+                (i + 1).to_string()
+            };
+            numbers.push_str(&format!("{: >DAY_WIDTH$}", number));
+            let (due, finished) = (calendar.due[i], calendar.finished[i]);
+            let count = match (due, finished) {
+                (0, 0) => String::new(),
+                (due, finished) => format!("{}/{}", due, finished),
+            };
+            let count = format!("{: >DAY_WIDTH$}", count);
+            let overdue = theme.overdue_color().filter(|_| due > 0 && days[i] < today);
+            counts.push_str(&theme.paint(&count, overdue));
+        }
+        out.push_str(numbers.trim_end());
+        out.push('\n');
+        out.push_str(counts.trim_end());
+        out.push('\n');
+    }
+    let due: usize = calendar.due.iter().sum();
+    let finished: usize = calendar.finished.iter().sum();
+    out.push_str(&format!(
+        "\ndue/finished per day; {} due, {} finished this month\n",
+        due, finished
+    ));
+    out
+}
+
+// a row per label: the label, a bar of `#` as long as the count is large, and the count
+fn bar_chart(rows: &[(String, usize)], width: Option<usize>) -> String {
+    let label_width = rows
+        .iter()
+        .map(|(l, _)| text_width(&strip_ansi(l)))
+        .max()
+        .unwrap_or(0);
+    let max = rows.iter().map(|(_, count)| *count).max().unwrap_or(0);
+    let count_width = max.to_string().len();
+    let bar_width = match width {
+        Some(width) => BAR_WIDTH.min(width.saturating_sub(label_width + count_width + 3)),
+        None => BAR_WIDTH,
+    };
+    let mut chart = String::new();
+    for (label, count) in rows {
+        // any count shows, however short the bar gets
+        let bar = match (count, max) {
+            (0, _) => 0,
+            (count, max) => (count * bar_width).div_ceil(max),
+        };
+        let padding = label_width - text_width(&strip_ansi(label));
+        let row = format!(
+            "{}{} {: >count_width$} {}",
+            label,
+            " ".repeat(padding),
+            count,
+            "#".repeat(bar),
+        );
+        chart.push_str(row.trim_end());
+        chart.push('\n');
+    }
+    chart
+}
+
+/// The counts as a line of block characters, `▁` for none up to `█` for the
+/// largest.
+pub fn sparkline(counts: &[usize]) -> String {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let max = counts.iter().copied().max().unwrap_or(0);
+    counts
+        .iter()
+        .map(|count| match count {
+            0 => BLOCKS[0],
+            count => BLOCKS[1 + count * (BLOCKS.len() - 2) / max],
+        })
+        .collect()
+}
+
+// `text` without the escapes `Theme::paint` puts around it
+fn strip_ansi(text: &str) -> String {
+    let mut plain = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|c| *c == 'm');
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+// `text` followed by the spaces that make it `width` columns wide on a terminal
+fn pad(text: &str, width: usize) -> String {
+    let mut padded = text.to_owned();
+    padded.push_str(&" ".repeat(width.saturating_sub(text_width(text))));
+    padded
+}
+
+/// How many columns `text` takes on a terminal: wide characters like CJK and
+/// emoji take two, combining marks none. For several lines, the widest one.
+pub fn text_width(text: &str) -> usize {
+    text.lines().map(UnicodeWidthStr::width).max().unwrap_or(0)
+}
+
+/// `text` cut to `width` columns, ending with `…` when something was cut.
+/// The escapes `Theme::paint` puts in take no room, and a cut painted text
+/// is reset before the `…`.
+pub fn ellipsize(text: &str, width: usize) -> String {
+    if UnicodeWidthStr::width(strip_ansi(text).as_str()) <= width {
+        return text.to_owned();
+    }
+    let mut cut = String::new();
+    let mut used = 0;
+    let mut painted = false;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            cut.push(c);
+            cut.extend(chars.by_ref().take_while(|c| *c != 'm'));
+            cut.push('m');
+            painted = true;
+            continue;
+        }
+        let w = c.width().unwrap_or(0);
+        if used + w + 1 > width {
+            break;
+        }
+        cut.push(c);
+        used += w;
+    }
+    if painted {
+        cut.push_str("\x1b[0m");
+    }
+    if width > 0 {
+        cut.push('…');
+    }
+    cut
+}
+
+/// `text` broken into lines of at most `width` columns, between words where
+/// possible. Line breaks in `text` are kept.
+pub fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = vec![];
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        let mut used = 0;
+        for word in paragraph.split_whitespace() {
+            let word_width = UnicodeWidthStr::width(word);
+            if used > 0 && used + 1 + word_width <= width {
+                line.push(' ');
+                used += 1;
+            } else if used > 0 {
+                lines.push(std::mem::take(&mut line));
+                used = 0;
+            }
+            if used + word_width <= width {
+                line.push_str(word);
+                used += word_width;
+                continue;
+            }
+            // longer than a line, e.g. a url: split it anywhere
+            for c in word.chars() {
+                let w = c.width().unwrap_or(0);
+                if used + w > width {
+                    lines.push(std::mem::take(&mut line));
+                    used = 0;
+                }
+                line.push(c);
+                used += w;
+            }
+        }
+        lines.push(line);
+    }
+    if lines.is_empty() {
+        lines.push(String::new());
+    }
+    lines
+}
+
+/// The width of the terminal stdout shows on, or None when it is piped or
+/// redirected, in which case output stays plain: nothing is wrapped or cut.
+/// `COLUMNS` is used when the terminal doesn't tell its size.
+pub fn terminal_width() -> Option<usize> {
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+    match terminal_size::terminal_size() {
+        Some((terminal_size::Width(width), _)) => Some(width as usize),
+        None => std::env::var("COLUMNS").ok()?.parse().ok(),
+    }
+}
+
+/// Write `text` to stdout, through `$PAGER` (`less -FRX` when it is unset)
+/// when stdout is a terminal and the text is taller than it. An empty
+/// `PAGER` turns paging off; a pager that can't be run is skipped.
+pub fn page(text: &str) -> std::io::Result<()> {
+    let height = terminal_size::terminal_size()
+        .map(|(_, terminal_size::Height(height))| height as usize)
+        .filter(|height| *height > 0)
+        .or_else(|| std::env::var("LINES").ok()?.parse().ok());
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| "less -FRX".to_owned());
+    let tall = height.is_some_and(|height| text.lines().count() >= height);
+    if std::io::stdout().is_terminal()
+        && tall
+        && !pager.trim().is_empty()
+        && run_pager(&pager, text)
+    {
+        return Ok(());
+    }
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(text.as_bytes())?;
+    stdout.flush()
+}
+
+// whether the pager showed the text; 127 is the shell's command not found
+fn run_pager(pager: &str, text: &str) -> bool {
+    let child = std::process::Command::new("sh")
+        .args(["-c", pager])
+        .stdin(std::process::Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(_) => return false,
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // quitting the pager early closes the pipe, which is fine
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    child.wait().is_ok_and(|status| status.code() != Some(127))
+}
+
+/// The widths the description and link columns of the `list` table get on a
+/// terminal `width` columns wide.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskColumns {
+    pub what: usize,
+    pub link: usize,
+}
+
+impl TaskColumns {
+    // the table has an id, a priority, a description and a link column,
+    // with `| ` and ` |` around each
+    const BORDERS: usize = 13;
+    // and a due column when any task has a due date
+    const DUE: usize = " yyyy-mm-dd |".len();
+    const MIN_WHAT: usize = "description".len();
+    const MIN_LINK: usize = "link".len();
+
+    pub fn fit(tasks: &[Task], theme: &Theme, width: usize) -> Self {
+        let id = tasks
+            .iter()
+            .map(|t| t.id.to_string().len())
+            .chain(["id".len()])
+            .max()
+            .unwrap_or_default();
+        let priority = tasks
+            .iter()
+            .map(|t| text_width(theme.priority_label(t.priority)))
+            .chain(["pri".len()])
+            .max()
+            .unwrap_or_default();
+        let mut fixed = Self::BORDERS + id + priority;
+        if tasks.iter().any(|t| t.due.is_some()) {
+            fixed += Self::DUE;
+        }
+        let available = width.saturating_sub(fixed);
+        let longest_link = tasks
+            .iter()
+            .filter_map(|t| t.link.as_deref())
+            .map(text_width)
+            .chain([Self::MIN_LINK])
+            .max()
+            .unwrap_or_default();
+        let longest_what = tasks
+            .iter()
+            .map(|t| text_width(&t.what))
+            .chain([Self::MIN_WHAT])
+            .max()
+            .unwrap_or_default();
+        // links give way to descriptions, down to a third of the room
+        let room = available
+            .saturating_sub(longest_what)
+            .max(available / 3)
+            .max(Self::MIN_LINK);
+        let link = longest_link.min(room);
+        let what = available.saturating_sub(link).max(Self::MIN_WHAT);
+        TaskColumns { what, link }
+    }
+}
+
+/// `line` cut to the terminal width, when there is one.
+pub fn fit_line(line: &str, width: Option<usize>) -> String {
+    match width {
+        Some(width) => ellipsize(line.trim_end(), width),
+        None => line.to_owned(),
+    }
+}
+
+// the parts of a line, cut to the terminal width as plain text and then
+// painted, so that the escapes neither count nor get cut
+fn fit_painted(parts: &[(&str, Option<Color>)], width: Option<usize>, theme: &Theme) -> String {
+    let plain: String = parts.iter().map(|(text, _)| *text).collect();
+    let fitted = fit_line(&plain, width);
+    let (mut kept, ellipsis) = match fitted.strip_suffix('…') {
+        Some(kept) if !plain.starts_with(&fitted) => (kept, "…"),
+        _ => (fitted.as_str(), ""),
+    };
+    let mut line = String::new();
+    for (text, color) in parts {
+        let end = text.len().min(kept.len());
+        line.push_str(&theme.paint(&kept[..end], *color));
+        kept = &kept[end..];
+    }
+    line.push_str(ellipsis);
+    line
+}
+
+/// When to color the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    // on a terminal, unless NO_COLOR is set
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Auto => {
+                std::io::stdout().is_terminal()
+                    && std::env::var_os("NO_COLOR")
+                        .filter(|v| !v.is_empty())
+                        .is_none()
+            }
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = TodoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(TodoError::InvalidInput(format!(
+                "unknown color choice `{}`, expect auto, always or never",
+                s
+            ))),
+        }
+    }
+}
+
+/// The terminal colors a theme picks from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+}
+
+impl Color {
+    fn ansi(self) -> u8 {
+        match self {
+            Color::Red => 31,
+            Color::Green => 32,
+            Color::Yellow => 33,
+            Color::Blue => 34,
+            Color::Magenta => 35,
+            Color::Cyan => 36,
+        }
+    }
+}
+
+/// How priorities, links and overdue tasks look. Priorities fall in four
+/// bands, 0-3, 4-6, 7-8 and 9 up, each with its own label and color.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+    pub labels: [String; 4],
+    pub colors: [Option<Color>; 4],
+    pub link: Option<Color>,
+    pub overdue: Option<Color>,
+    // off when colors are not wanted, see `ColorChoice`
+    pub color: bool,
+}
+
+impl Theme {
+    pub const NAMES: [&'static str; 3] = ["emoji", "ascii", "plain"];
+
+    /// One of `NAMES`: `emoji`, the default, `ascii` with words for the
+    /// priorities, and `plain`, which is `ascii` without colors, for logs.
+    pub fn named(name: &str) -> Option<Theme> {
+        let colors = [
+            Some(Color::Blue),
+            Some(Color::Green),
+            Some(Color::Yellow),
+            Some(Color::Red),
+        ];
+        let ascii = ["low", "mid", "high", "urgent"];
+        let theme = match name {
+            "emoji" => Theme {
+                labels: ["🥶", "🤡", "😅", "🥵"].map(str::to_owned),
+                colors,
+                link: Some(Color::Cyan),
+                overdue: Some(Color::Red),
+                color: true,
+            },
+            "ascii" => Theme {
+                labels: ascii.map(str::to_owned),
+                colors,
+                link: Some(Color::Cyan),
+                overdue: Some(Color::Red),
+                color: true,
+            },
+            "plain" => Theme {
+                labels: ascii.map(str::to_owned),
+                colors: [None; 4],
+                link: None,
+                overdue: None,
+                color: false,
+            },
+            _ => return None,
+        };
+        Some(theme)
+    }
+
+    /// Replace the labels of the bands with the comma-separated `labels`,
+    /// e.g. `.,-,+,!`.
+    pub fn with_labels(mut self, labels: &str) -> Result<Theme, TodoError> {
+        let labels: Vec<&str> = labels.split(',').map(str::trim).collect();
+        match <[&str; 4]>::try_from(labels.as_slice()) {
+            Ok(labels) => {
+                self.labels = labels.map(str::to_owned);
+                Ok(self)
+            }
+            Err(_) => Err(TodoError::InvalidInput(format!(
+                "expect 4 comma-separated priority labels, found {}",
+                labels.len()
+            ))),
+        }
+    }
+
+    /// The band of `priority`: 0 for 0-3, 1 for 4-6, 2 for 7-8 and 3 above.
+    pub fn band(priority: i32) -> usize {
+        match priority {
+            i32::MIN..=3 => 0,
+            4..=6 => 1,
+            7..=8 => 2,
+            _ => 3,
+        }
+    }
+
+    pub fn priority_label(&self, priority: i32) -> &str {
+        &self.labels[Self::band(priority)]
+    }
+
+    /// The color of the band of `priority`, None when colors are off.
+    pub fn priority_color(&self, priority: i32) -> Option<Color> {
+        self.colors[Self::band(priority)].filter(|_| self.color)
+    }
+
+    pub fn link_color(&self) -> Option<Color> {
+        self.link.filter(|_| self.color)
+    }
+
+    pub fn overdue_color(&self) -> Option<Color> {
+        self.overdue.filter(|_| self.color)
+    }
+
+    /// `text` in `color`, or as it is without one. Each line is colored on its
+    /// own so that table borders between them keep their color.
+    pub fn paint(&self, text: &str, color: Option<Color>) -> String {
+        match color {
+            Some(color) if self.color && !text.is_empty() => text
+                .split('\n')
+                .map(|line| format!("\x1b[{}m{}\x1b[0m", color.ansi(), line))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => text.to_owned(),
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::named("emoji").unwrap()
+    }
+}
+
+impl FromStr for Theme {
+    type Err = TodoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Theme::named(s).ok_or_else(|| {
+            TodoError::InvalidInput(format!(
+                "unknown theme `{}`, expect one of {}",
+                s,
+                Theme::NAMES.join(", ")
+            ))
+        })
+    }
+}
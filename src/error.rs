@@ -0,0 +1,78 @@
+use std::fmt::Display;
+use std::path::PathBuf;
+
+pub type TodoResult<T> = std::result::Result<T, TodoError>;
+
+/// What went wrong in a `TaskDB` call.
+#[derive(Debug)]
+pub enum TodoError {
+    /// There is no `kind` (task, subtask, ...) with this id.
+    NotFound { kind: &'static str, id: i32 },
+    /// Some of the ids of a batch have no `kind`; nothing was changed.
+    Missing { kind: &'static str, ids: Vec<i32> },
+    /// The arguments were rejected before touching the store.
+    InvalidInput(String),
+    /// The store failed to read or write records.
+    Storage(String),
+    /// The store couldn't be brought up to the current schema.
+    Migration(String),
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+impl TodoError {
+    /// The process exit code for this error; 1 is left for anything else
+    /// and 2 for usage errors reported by clap.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            TodoError::NotFound { .. } | TodoError::Missing { .. } => 3,
+            TodoError::InvalidInput(_) => 4,
+            TodoError::Storage(_) => 5,
+            TodoError::Migration(_) => 6,
+            TodoError::Io { .. } => 7,
+        }
+    }
+
+    pub(crate) fn io(path: impl Into<PathBuf>) -> impl FnOnce(std::io::Error) -> TodoError {
+        let path = path.into();
+        move |source| TodoError::Io { path, source }
+    }
+}
+
+/// Turn a backend error into `TodoError::Storage`, prefixed by what was being done.
+pub(crate) fn storage<E: Display>(context: impl Display) -> impl FnOnce(E) -> TodoError {
+    move |e| TodoError::Storage(format!("{}: {}", context, e))
+}
+
+impl Display for TodoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TodoError::NotFound { kind, id } => write!(f, "{} {} not found", kind, id),
+            TodoError::Missing { kind, ids } => {
+                let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+                write!(f, "no {} with id {}, nothing changed", kind, ids.join(", "))
+            }
+            TodoError::InvalidInput(message) => write!(f, "invalid input: {}", message),
+            TodoError::Storage(message) => write!(f, "storage error: {}", message),
+            TodoError::Migration(message) => write!(f, "migration failed: {}", message),
+            TodoError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl std::error::Error for TodoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TodoError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<diesel::result::Error> for TodoError {
+    fn from(e: diesel::result::Error) -> Self {
+        TodoError::Storage(e.to_string())
+    }
+}
@@ -0,0 +1,313 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+
+use crate::error::{storage, TodoError, TodoResult};
+use crate::memdb::TaskMemory;
+use crate::models::{FieldTime, History, NewTask, SubTask, Task};
+use crate::taskdb::{TaskDB, SCHEMA_VERSION, SUBTASK_FIELDS, TASK_FIELDS};
+
+type IDType = i32;
+
+// the schema version that started keeping field times
+const FIELD_TIMES_VERSION: &str = "20261020090000";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    Json,
+    Toml,
+}
+
+// what the file holds, every table ordered by id; empty tables are left out
+// since toml can't write an empty array after an array of tables
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Contents {
+    // the schema the records follow, as `SCHEMA_VERSION`; None in files written
+    // before it was kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    schema_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tasks: Vec<Task>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    subtasks: Vec<SubTask>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    histories: Vec<History>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    field_times: Vec<FieldTime>,
+}
+
+/// A `TaskDB` kept in a plain json or toml file.
+///
+/// The whole file is loaded on open and rewritten after every change, by writing
+/// a temporary file next to it and renaming it over the old one. An exclusive
+/// lock on `<file>.lock` is held while the store is open, so concurrent
+/// invocations wait for each other instead of losing writes. Inside a
+/// transaction the file is only written once, when it commits.
+pub struct TaskFile {
+    path: PathBuf,
+    format: FileFormat,
+    memory: TaskMemory,
+    // how many transactions are open
+    depth: usize,
+    _lock: fs::File,
+}
+
+impl TaskFile {
+    /// Open the store at `path`, creating an empty one if it doesn't exist.
+    pub fn open(path: &Path, format: FileFormat) -> TodoResult<Self> {
+        let lock_path = PathBuf::from(format!("{}.lock", path.display()));
+        let lock = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&lock_path)
+            .map_err(TodoError::io(&lock_path))?;
+        lock.lock_exclusive().map_err(TodoError::io(&lock_path))?;
+
+        let mut store = TaskFile {
+            path: path.to_owned(),
+            format,
+            memory: TaskMemory::new(),
+            depth: 0,
+            _lock: lock,
+        };
+        if path.exists() {
+            let text = fs::read_to_string(path).map_err(TodoError::io(path))?;
+            let context = format!("fail to parse {}", path.display());
+            let contents: Contents = match format {
+                FileFormat::Json => serde_json::from_str(&text).map_err(storage(context))?,
+                FileFormat::Toml => toml::from_str(&text).map_err(storage(context))?,
+            };
+            // older files load through the serde defaults, newer ones may hold
+            // what this version would drop on the next write
+            if let Some(version) = contents.schema_version.as_deref() {
+                if version > SCHEMA_VERSION {
+                    return Err(TodoError::Migration(format!(
+                        "{} has schema version {}, newer than {} which this todo knows",
+                        path.display(),
+                        version,
+                        SCHEMA_VERSION
+                    )));
+                }
+            }
+            let field_times = match contents.schema_version.as_deref() {
+                Some(version) if version >= FIELD_TIMES_VERSION => contents.field_times,
+                // like the migration, take every field as changed when its
+                // record last was
+                _ => backfill_field_times(&contents.tasks, &contents.subtasks),
+            };
+            store.memory =
+                TaskMemory::from_records(contents.tasks, contents.subtasks, contents.histories);
+            for time in &field_times {
+                store.memory.put_field_time(time)?;
+            }
+        } else {
+            store.save()?;
+        }
+        Ok(store)
+    }
+
+    fn save(&self) -> TodoResult<()> {
+        if self.depth > 0 {
+            return Ok(());
+        }
+        let (tasks, subtasks, histories) = self.memory.records();
+        let contents = Contents {
+            schema_version: Some(SCHEMA_VERSION.to_owned()),
+            tasks,
+            subtasks,
+            histories,
+            field_times: self.memory.get_field_times()?,
+        };
+        let context = format!("fail to write {}", self.path.display());
+        let mut text = match self.format {
+            FileFormat::Json => {
+                serde_json::to_string_pretty(&contents).map_err(storage(context))?
+            }
+            FileFormat::Toml => toml::to_string(&contents).map_err(storage(context))?,
+        };
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut tmp = NamedTempFile::new_in(dir).map_err(TodoError::io(dir))?;
+        // the temporary file is private, the store keeps the permissions it had
+        if let Ok(metadata) = fs::metadata(&self.path) {
+            tmp.as_file()
+                .set_permissions(metadata.permissions())
+                .map_err(TodoError::io(tmp.path()))?;
+        }
+        tmp.write_all(text.as_bytes())
+            .and_then(|_| tmp.as_file().sync_all())
+            .map_err(TodoError::io(tmp.path()))?;
+        tmp.persist(&self.path)
+            .map_err(|e| TodoError::io(&self.path)(e.error))?;
+        Ok(())
+    }
+}
+
+impl TaskDB for TaskFile {
+    fn add_task(&mut self, new_task: NewTask) -> TodoResult<IDType> {
+        let id = self.memory.add_task(new_task)?;
+        self.save()?;
+        Ok(id)
+    }
+
+    fn add_subtask(
+        &mut self,
+        task_id: IDType,
+        what: String,
+        link: Option<String>,
+    ) -> TodoResult<()> {
+        self.memory.add_subtask(task_id, what, link)?;
+        self.save()
+    }
+
+    fn get_task(&self, id: IDType) -> TodoResult<Option<Task>> {
+        self.memory.get_task(id)
+    }
+
+    fn get_tasks(&self, pattern: Option<String>) -> TodoResult<Vec<Task>> {
+        self.memory.get_tasks(pattern)
+    }
+
+    fn get_subtasks(&self, id: IDType) -> TodoResult<Vec<SubTask>> {
+        self.memory.get_subtasks(id)
+    }
+
+    fn get_finished(&self, last_n: u32) -> TodoResult<Vec<History>> {
+        self.memory.get_finished(last_n)
+    }
+
+    fn get_finished_within(&self, start_ts: u32, end_ts: u32) -> TodoResult<Vec<History>> {
+        self.memory.get_finished_within(start_ts, end_ts)
+    }
+
+    fn update_task_desc(&mut self, id: IDType, desc: String) -> TodoResult<usize> {
+        let outcome = self.memory.update_task_desc(id, desc)?;
+        self.save()?;
+        Ok(outcome)
+    }
+
+    fn update_task_priority(&mut self, task_id: IDType, pri: i32) -> TodoResult<usize> {
+        let outcome = self.memory.update_task_priority(task_id, pri)?;
+        self.save()?;
+        Ok(outcome)
+    }
+
+    fn remove_task(&mut self, id: IDType) -> TodoResult<usize> {
+        let outcome = self.memory.remove_task(id)?;
+        self.save()?;
+        Ok(outcome)
+    }
+
+    fn update_subtask_belongings(
+        &mut self,
+        task_id: IDType,
+        new_task_id: IDType,
+    ) -> TodoResult<usize> {
+        let outcome = self
+            .memory
+            .update_subtask_belongings(task_id, new_task_id)?;
+        self.save()?;
+        Ok(outcome)
+    }
+
+    fn remove_subtask(&mut self, id: IDType, subtask_rank: i32) -> TodoResult<usize> {
+        let outcome = self.memory.remove_subtask(id, subtask_rank)?;
+        self.save()?;
+        Ok(outcome)
+    }
+
+    fn finish_subtask(&mut self, id: IDType, subtask_rank: i32) -> TodoResult<usize> {
+        let outcome = self.memory.finish_subtask(id, subtask_rank)?;
+        self.save()?;
+        Ok(outcome)
+    }
+
+    fn finish_task(&mut self, id: IDType) -> TodoResult<History> {
+        let outcome = self.memory.finish_task(id)?;
+        self.save()?;
+        Ok(outcome)
+    }
+
+    fn get_all_subtasks(&self) -> TodoResult<Vec<SubTask>> {
+        self.memory.get_all_subtasks()
+    }
+
+    fn put_task(&mut self, task: &Task) -> TodoResult<()> {
+        self.memory.put_task(task)?;
+        self.save()
+    }
+
+    fn put_subtask(&mut self, subtask: &SubTask) -> TodoResult<()> {
+        self.memory.put_subtask(subtask)?;
+        self.save()
+    }
+
+    fn put_history(&mut self, history: &History) -> TodoResult<()> {
+        self.memory.put_history(history)?;
+        self.save()
+    }
+
+    fn get_field_times(&self) -> TodoResult<Vec<FieldTime>> {
+        self.memory.get_field_times()
+    }
+
+    fn put_field_time(&mut self, time: &FieldTime) -> TodoResult<()> {
+        self.memory.put_field_time(time)?;
+        self.save()
+    }
+
+    fn schema_version(&self) -> TodoResult<Option<String>> {
+        self.memory.schema_version()
+    }
+
+    fn transaction(
+        &mut self,
+        f: &mut dyn FnMut(&mut dyn TaskDB) -> TodoResult<()>,
+    ) -> TodoResult<()> {
+        let before = self.memory.clone();
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        match result {
+            Ok(()) => self.save(),
+            Err(e) => {
+                self.memory = before;
+                Err(e)
+            }
+        }
+    }
+}
+
+fn backfill_field_times(tasks: &[Task], subtasks: &[SubTask]) -> Vec<FieldTime> {
+    let stamp = |uid: &str, fields: &[&str], at: i32| {
+        fields
+            .iter()
+            .map(|field| FieldTime {
+                uid: uid.to_owned(),
+                field: (*field).to_owned(),
+                changed_at: at,
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut times = Vec::new();
+    for task in tasks {
+        times.extend(stamp(&task.uid, &TASK_FIELDS, task.updated_at));
+    }
+    for subtask in subtasks {
+        if let Some(task) = tasks.iter().find(|t| t.id == subtask.task_id) {
+            times.extend(stamp(&subtask.uid, &SUBTASK_FIELDS, task.updated_at));
+        }
+    }
+    times
+}
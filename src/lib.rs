@@ -4,14 +4,39 @@ extern crate diesel;
 #[macro_use]
 extern crate diesel_migrations;
 
+#[cfg(feature = "async")]
+pub mod asyncdb;
+pub mod backup;
+pub mod calendar;
+pub mod dates;
 pub mod display;
+pub mod error;
+pub mod filedb;
+pub mod memdb;
+pub mod merge;
+// the diesel 1.4 derives and table macros impl inside const blocks
+#[allow(non_local_definitions)]
 pub mod models;
+pub mod query;
+pub mod report;
+pub mod rpc;
+#[allow(non_local_definitions)]
 pub mod schema;
+pub mod serve;
+pub mod snapshot;
+pub mod stats;
+pub mod store;
+pub mod sync;
 pub mod taskdb;
+pub mod transfer;
+#[cfg(feature = "tui")]
+pub mod tui;
 
 use diesel::prelude::*;
 
-pub fn create_connection(db_url: String) -> SqliteConnection {
+use crate::error::{storage, TodoResult};
+
+pub fn create_connection(db_url: String) -> TodoResult<SqliteConnection> {
     SqliteConnection::establish(&db_url)
-        .unwrap_or_else(|_| panic!("fail to open sqlite db: {}", db_url))
+        .map_err(storage(format!("fail to open sqlite db {}", db_url)))
 }
@@ -1,15 +1,35 @@
-use std::io::Write;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::str::FromStr;
 use std::{env, fs};
 
-use anyhow::Context;
-use chrono::NaiveDate;
-use clap::{Parser, Subcommand};
-use prettytable::{row, Table};
+use anyhow::{anyhow, bail, Context};
+use chrono::{Datelike, Local, NaiveDate};
+use clap::{ArgEnum, Parser, Subcommand};
+use prettytable::Table;
 use tempfile::NamedTempFile;
-use todo::display::{prompt_finished_task, prompt_subtask};
-use todo::models::NewTask;
-use todo::taskdb::open;
+use todo::backup::{read_backup, restore, take_backup, write_backup, ConflictPolicy};
+use todo::calendar::{Agenda, Calendar, Month};
+use todo::dates::{Tense, When};
+use todo::display::{
+    ellipsize, finished_task_header, fit_line, page, picker_line, print_agenda, print_calendar,
+    print_stats, print_tree, prompt_subtask, subtask_picker_line, task_details, terminal_width,
+    wrap, ColorChoice, Fold, TaskColumns, Theme,
+};
+use todo::error::{TodoError, TodoResult};
+use todo::merge;
+use todo::models::{due_field, History, NewTask, Task};
+use todo::query::{Page, Sort, Span, TaskFilter};
+use todo::report::{Report, ReportFormat};
+use todo::rpc;
+use todo::serve;
+use todo::snapshot::Snapshots;
+use todo::stats::{day_start, Period, Stats};
+use todo::store::{migrate, Store};
+use todo::sync::{Side, SyncRepo};
+use todo::taskdb::{now, TaskDB};
+use todo::transfer::{read_tasks_csv, write_csv, ColumnMap};
 
 #[derive(Parser, Debug)]
 struct Opts {
@@ -19,6 +39,14 @@ struct Opts {
     #[clap(short, long)]
     task_id: Option<i32>, // the task id
 
+    // when to color the output: auto, always or never; NO_COLOR turns auto off
+    #[clap(long, default_value = "auto")]
+    color: ColorChoice,
+
+    // how priorities look: emoji, ascii or plain (default: TODO_THEME, or emoji)
+    #[clap(long)]
+    theme: Option<Theme>,
+
     #[clap(subcommand)]
     subcmd: SubCommand,
 }
@@ -27,12 +55,53 @@ struct Opts {
 enum SubCommand {
     List {
         pattern: Option<String>,
+
+        // one tab-separated line per task, id first, for fzf and other pickers
+        #[clap(long)]
+        picker: bool,
+
+        // every task with its subtasks and how many of them are done
+        #[clap(long, conflicts_with = "picker")]
+        tree: bool,
+
+        // show the tree without subtasks, only the progress
+        #[clap(long, requires = "tree")]
+        collapse: bool,
+
+        // leave the done subtasks out of the tree
+        #[clap(long, requires = "tree")]
+        hide_done: bool,
+
+        // comma separated keys out of priority, id, due, created and
+        // alphabetical, each optionally followed by :asc or :desc
+        #[clap(long, default_value = "priority")]
+        sort: Sort,
+
+        #[clap(long)]
+        limit: Option<u32>,
+
+        // skip this many tasks, e.g. to page with --limit
+        #[clap(long, default_value = "0")]
+        offset: u32,
+
+        // only the tasks left unchanged for this long, e.g. 14d, 12h or 2w
+        #[clap(long)]
+        stale: Option<Span>,
     },
+    // everything about a task, e.g. for `fzf --preview 'todo show {1}'`
+    Show {
+        id: i32,
+
+        // print the task, its subtasks and related history as json
+        #[clap(long)]
+        json: bool,
+    },
+    // `-` reads the ids from stdin, one per line, as printed by `list --picker`
     Del {
-        id_or_order: i32,
+        id_or_order: Vec<IdArg>,
     },
     Fin {
-        id_or_order: Vec<i32>,
+        id_or_order: Vec<IdArg>,
     },
     Add {
         what: String,
@@ -41,12 +110,20 @@ enum SubCommand {
 
         #[clap(short, long)]
         priority: Option<u32>,
+
+        // the day the task is due, yyyy-mm-dd, `friday`, `+3d`, `eom`...
+        #[clap(long)]
+        due: Option<String>,
     },
     Update {
-        id_or_order: i32,
+        id_or_order: IdArg,
 
         #[clap(short, long)]
         priority: Option<u32>,
+
+        // the day the task is due, yyyy-mm-dd, `friday`, `+3d`..., or `none` to remove it
+        #[clap(long)]
+        due: Option<String>,
     },
     Tidy,
     Note {
@@ -59,16 +136,167 @@ enum SubCommand {
         n: Option<u32>,
 
         // to search tasks finished with a date range
-        // date format: 2012-1-3, 2021-12-29, etc.
+        // date format: 2012-1-3, 2021-12-29, yesterday, monday, last week, 2w, 2022-1-3 14:00, etc.
         #[clap(short, long)]
         start_date: Option<String>,
 
         #[clap(short, long)]
         end_date: Option<String>,
+
+        // comma separated keys out of finished, id and alphabetical, each
+        // optionally followed by :asc or :desc
+        #[clap(long, default_value = "finished:desc")]
+        sort: Sort,
+
+        #[clap(long, conflicts_with = "n")]
+        limit: Option<u32>,
+
+        #[clap(long, default_value = "0")]
+        offset: u32,
+    },
+    // tasks finished per day, week or month, with streaks and breakdowns
+    Stats {
+        #[clap(long, default_value = "day")]
+        by: Period,
+
+        // how many days, weeks or months to show, up to the current one
+        #[clap(long, default_value = "14")]
+        last: usize,
+    },
+    // a summary for a status update: what was finished, by tag, and where
+    // the open tasks stand
+    Report {
+        // covers the last day, week or month, up to today
+        #[clap(long, default_value = "week")]
+        period: Period,
+
+        // the first and the last day covered instead, yyyy-mm-dd, `yesterday`, `last week`...
+        #[clap(long)]
+        since: Option<String>,
+
+        #[clap(long)]
+        until: Option<String>,
+
+        // markdown or text
+        #[clap(long, default_value = "markdown")]
+        format: ReportFormat,
+    },
+    // the open tasks due over the next days, by day, and the overdue ones
+    Agenda {
+        #[clap(long, default_value = "7")]
+        days: u32,
+    },
+    // a month grid with how many tasks are due and finished each day
+    Calendar {
+        // yyyy-mm, this month by default
+        #[clap(long)]
+        month: Option<Month>,
     },
+    Export {
+        #[clap(long, arg_enum, default_value = "csv")]
+        format: ExportFormat,
+
+        #[clap(long, arg_enum, default_value = "tasks")]
+        table: ExportTable,
+
+        // write to a file instead of stdout
+        #[clap(short, long)]
+        output: Option<String>,
+    },
+    Import {
+        file: String,
+
+        // csv column for each task field, e.g. what=Title,link=URL,priority=Prio
+        #[clap(short, long)]
+        map: Option<String>,
+    },
+    Backup {
+        file: String,
+    },
+    Restore {
+        file: String,
+
+        // what to do with records whose id is taken: fail, skip or replace
+        #[clap(long, default_value = "fail")]
+        on_conflict: ConflictPolicy,
+    },
+    // sqlite snapshots taken before destructive commands
+    Backups {
+        #[clap(subcommand)]
+        cmd: BackupsCommand,
+    },
+
+    // sync the db with a git repository, TODO_SYNC_DIR (default: `sync` next to TODO_DB)
+    Sync {
+        // url of the git remote to sync with, remembered for later syncs
+        #[clap(short, long)]
+        remote: Option<String>,
+
+        // resolve conflicting edits by taking the local or the remote side
+        #[clap(short, long)]
+        prefer: Option<Side>,
+    },
+    // merge another db file, e.g. a sync-conflict copy, into this one
+    Merge {
+        other: String,
+
+        // only print what would change
+        #[clap(long)]
+        dry_run: bool,
+    },
+    // copy every record into another, empty store, e.g. json:///home/me/todo.json
+    MigrateStore {
+        to: Store,
+    },
+    // answer a REST API on the tasks, for dashboards and editor plugins
+    Serve {
+        #[clap(long, default_value = "127.0.0.1:7878")]
+        bind: String,
+
+        // where the bearer token is kept (default: `serve.token` next to TODO_DB)
+        #[clap(long)]
+        token_file: Option<PathBuf>,
+    },
+    // answer JSON-RPC 2.0 on stdin and stdout, one message per line, for editor plugins
+    Rpc,
+    // browse and triage the tasks in an interactive terminal ui
+    #[cfg(feature = "tui")]
+    Tui,
+}
+
+#[derive(Subcommand, Debug)]
+enum BackupsCommand {
+    List,
+    Restore { name: String },
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+#[derive(ArgEnum, Clone, Copy, Debug)]
+enum ExportFormat {
+    Csv,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+enum ExportTable {
+    Tasks,
+    Subtasks,
+    History,
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        std::process::exit(exit_code(&e));
+    }
+}
+
+// the exit code of the first TodoError in the chain, 1 for any other error
+fn exit_code(e: &anyhow::Error) -> i32 {
+    e.chain()
+        .find_map(|e| e.downcast_ref::<TodoError>())
+        .map_or(1, TodoError::exit_code)
+}
+
+fn run() -> anyhow::Result<()> {
     // let env = Env::default()
     //     .filter_or("MY_LOG_LEVEL", "info")
     //     .write_style_or("MY_LOG_STYLE", "always");
@@ -76,55 +304,74 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // env_logger::init_from_env(env);
 
     let opts: Opts = Opts::parse();
+    let theme = theme(opts.theme, opts.color)?;
+
+    let store: Store = env::var("TODO_DB")
+        .context("please define environment variable TODO_DB")?
+        .parse()?;
+    let db_path = store.path().to_owned();
+    let mut db = store.open()?;
 
-    let db_path = env::var("TODO_DB").context("please define environment variable TODO_DB")?;
-    let mut db = open(&db_path)?;
+    let mut snapshots = snapshots(&db_path)?;
+    if let SubCommand::Backups {
+        cmd: BackupsCommand::Restore { name },
+    } = &opts.subcmd
+    {
+        // the snapshot taken first must not prune the one to restore
+        snapshots.spare(name);
+    }
+    if let Some(reason) = destructive(&opts.subcmd) {
+        snapshots.take(&db_path, reason)?;
+    } else {
+        snapshots.take_daily(&db_path)?;
+    }
 
     match opts.subcmd {
         SubCommand::Add {
             what,
             link,
             priority,
+            due,
         } => {
             // add a new task
             if let Some(id) = opts.task_id {
+                if due.is_some() {
+                    bail!("subtasks have no due date");
+                }
                 db.add_subtask(id, what, link)?;
             } else {
                 db.add_task(NewTask {
                     what,
                     link,
                     priority: priority.unwrap_or(5) as i32,
+                    due: due.as_deref().map(parse_due).transpose()?.flatten(),
                 })?;
             }
         }
         SubCommand::Update {
             id_or_order,
-            priority: Some(p),
-        } => {
-            db.update_task_priority(id_or_order, p as i32)?;
-        }
-        SubCommand::Update {
-            id_or_order,
-            priority: None,
+            priority,
+            due,
         } => {
-            // create a tempfile with current desc as the content
-            // spawn vi to edit the tempfile
-            // and update the current desc with the final file content
-            if let Some(task) = db.get_task(id_or_order)? {
-                let mut current_desc = NamedTempFile::new()?;
-                current_desc.write_all(task.what.as_bytes())?;
-                let path = current_desc.path();
-                Command::new("vi")
-                    .arg(path)
-                    .status()
-                    .expect("fail to use vi to update desc");
-                let new_desc: String = fs::read_to_string(path)
-                    .expect("fail to read new desc")
-                    .trim()
-                    .to_string();
-                db.update_task_desc(id_or_order, new_desc)?;
-            } else {
-                println!("no such task {id_or_order}!");
+            let id = match ids(&[id_or_order])?[..] {
+                [id] => id,
+                _ => bail!("update takes exactly one id"),
+            };
+            if priority.is_none() && due.is_none() {
+                edit_desc(db.as_mut(), id, id_or_order == IdArg::Stdin)?;
+            }
+            if let Some(p) = priority {
+                if db.update_task_priority(id, p as i32)? == 0 {
+                    return Err(TodoError::NotFound { kind: "task", id }.into());
+                }
+            }
+            if let Some(due) = due {
+                let mut task = db
+                    .get_task(id)?
+                    .ok_or(TodoError::NotFound { kind: "task", id })?;
+                task.due = parse_due(&due)?;
+                task.updated_at = now();
+                db.put_task(&task)?;
             }
         }
         SubCommand::Note { what, link } => {
@@ -132,107 +379,646 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 what,
                 link,
                 priority: 5,
+                due: None,
             })?;
             db.finish_task(task_id)?;
         }
-        SubCommand::List { pattern } => {
+        SubCommand::List {
+            pattern,
+            tree: true,
+            collapse,
+            hide_done,
+            sort,
+            limit,
+            offset,
+            stale,
+            ..
+        } => {
+            let tasks = listed(db.as_ref(), pattern, stale, &sort, Page { limit, offset })?;
+            let fold = Fold {
+                collapse,
+                hide_done,
+            };
+            let width = terminal_width();
+            let mut out = String::new();
+            for line in print_tree(&tasks, &db.get_all_subtasks()?, fold) {
+                out += &format!("{}\n", fit_line(&line, width));
+            }
+            page(&out)?;
+        }
+        SubCommand::List {
+            pattern,
+            picker: true,
+            sort,
+            limit,
+            offset,
+            stale,
+            ..
+        } => {
+            if let Some(id) = opts.task_id {
+                for subtask in db.get_subtasks(id)? {
+                    println!("{}", subtask_picker_line(&subtask));
+                }
+            } else {
+                for task in listed(db.as_ref(), pattern, stale, &sort, Page { limit, offset })? {
+                    println!("{}", picker_line(&task));
+                }
+            }
+        }
+        SubCommand::List {
+            pattern,
+            picker: false,
+            sort,
+            limit,
+            offset,
+            stale,
+            ..
+        } => {
             if let Some(id) = opts.task_id {
                 let subtasks = db.get_subtasks(id)?;
                 prompt_subtask(id);
-                subtasks.iter().for_each(|subtask| println!("{}", subtask));
+                let width = terminal_width();
+                for subtask in subtasks {
+                    println!("{}", fit_line(&subtask.to_string(), width));
+                }
                 // log output
             } else {
-                let mut tasks = db.get_tasks(pattern)?;
-                tasks.sort_by(|a, b| a.priority.cmp(&b.priority));
+                let tasks = listed(db.as_ref(), pattern, stale, &sort, Page { limit, offset })?;
+                // on a terminal, descriptions wrap and links are cut to fit
+                let columns = terminal_width().map(|width| TaskColumns::fit(&tasks, &theme, width));
+                let show_due = tasks.iter().any(|t| t.due.is_some());
+                let today = Local::today().naive_local();
                 let mut table = Table::new();
-                table.add_row(row!["id", "pri", "description", "link"]);
+                let mut header = vec!["id", "pri", "description"];
+                if show_due {
+                    header.push("due");
+                }
+                header.push("link");
+                table.add_row(header.into_iter().collect());
                 for task in tasks {
-                    let priority = match task.priority as u32 {
-                        _p @ 0..=3 => "🥶",
-                        _p @ 4..=6 => "🤡",
-                        _p @ 7..=8 => "😅",
-                        _p @ 9.. => "🥵",
+                    let priority = theme.paint(
+                        theme.priority_label(task.priority),
+                        theme.priority_color(task.priority),
+                    );
+                    let link = task.link.clone().unwrap_or_else(|| "".to_owned());
+                    let (what, link) = match columns {
+                        Some(c) => (
+                            wrap(&task.what, c.what).join("\n"),
+                            ellipsize(&link, c.link),
+                        ),
+                        None => (task.what.clone(), link),
                     };
-                    table.add_row(row![
-                        task.id,
-                        priority,
-                        task.what,
-                        task.link.unwrap_or_else(|| "".to_owned())
-                    ]);
+                    let overdue = theme.overdue_color().filter(|_| task.is_overdue(today));
+                    let mut cells =
+                        vec![task.id.to_string(), priority, theme.paint(&what, overdue)];
+                    if show_due {
+                        let due = task.due.as_deref().unwrap_or_default();
+                        cells.push(theme.paint(due, overdue));
+                    }
+                    cells.push(theme.paint(&link, theme.link_color()));
+                    table.add_row(cells.into_iter().collect());
                 }
-                table.printstd();
+                // colors are in the text already, and escapes don't count to the width
+                let mut out = vec![];
+                table.print(&mut out)?;
+                page(&String::from_utf8(out)?)?;
+            }
+        }
+        SubCommand::Show { id, json } => {
+            let task = db
+                .get_task(id)?
+                .ok_or(TodoError::NotFound { kind: "task", id })?;
+            let subtasks = db.get_subtasks(id)?;
+            let history = related_history(db.as_ref(), &task)?;
+            if json {
+                let details = serde_json::json!({
+                    "task": task,
+                    "subtasks": subtasks,
+                    "history": history,
+                });
+                println!("{}", serde_json::to_string_pretty(&details)?);
+            } else {
+                print!(
+                    "{}",
+                    task_details(&task, &subtasks, &history, terminal_width(), &theme)
+                );
             }
         }
         SubCommand::Tidy => {
             let tasks = db.get_tasks(None)?;
-            for t in tasks.iter() {
-                db.remove_task(t.id)?;
-            }
-            for t in tasks {
-                let new_task_id = db.add_task(NewTask {
-                    what: t.what,
-                    link: t.link,
-                    priority: t.priority,
-                })?;
-                db.update_subtask_belongings(t.id, new_task_id)?;
-            }
+            db.transaction(&mut |db| {
+                for t in tasks.iter() {
+                    db.remove_task(t.id)?;
+                }
+                // renumber from 1, keeping everything but the id
+                for (new_task_id, t) in (1..).zip(tasks.iter()) {
+                    db.put_task(&Task {
+                        id: new_task_id,
+                        ..t.clone()
+                    })?;
+                    db.update_subtask_belongings(t.id, new_task_id)?;
+                }
+                Ok(())
+            })?;
         }
         SubCommand::History {
             n: last_n,
             start_date,
             end_date,
+            sort,
+            limit,
+            offset,
         } => {
-            let finished_tasks = if let Some(last_n) = last_n {
-                db.get_finished(last_n)?
+            let start_ts = if let Some(start_date) = start_date {
+                unix_time(parse_when(&start_date, Tense::Past)?.timestamp(&Local))
             } else {
-                // let start_ts = start_date.map(|sd| NaiveDate::parse_from_str(&sd, "%Y-%m-%d")?);
-                let start_ts = if let Some(start_date) = start_date {
-                    let date = NaiveDate::parse_from_str(&start_date, "%Y-%m-%d")
-                        .expect("fail to parse start time");
-                    date.and_hms(0, 0, 0).timestamp() as u32
-                } else {
-                    0
-                };
-                let end_ts = if let Some(end_date) = end_date {
-                    let date = NaiveDate::parse_from_str(&end_date, "%Y-%m-%d")
-                        .expect("fail to parse end time");
-                    date.and_hms(0, 0, 0).timestamp() as u32
-                } else {
-                    chrono::Utc::now().timestamp() as u32
-                };
-                db.get_finished_within(start_ts, end_ts)?
+                0
             };
-            prompt_finished_task();
-            finished_tasks
-                .into_iter()
-                .enumerate()
-                .for_each(|(i, finished_task)| {
-                    println!("{: <10} {}", i, finished_task);
-                });
+            let end_ts = if let Some(end_date) = end_date {
+                unix_time(parse_when(&end_date, Tense::Past)?.timestamp(&Local))
+            } else {
+                // up to now, including what was finished this very second
+                i32::MAX as u32
+            };
+            let limit = limit.or(last_n);
+            let finished_tasks =
+                db.query_finished(start_ts, end_ts, &sort, Page { limit, offset })?;
+            let width = terminal_width();
+            let mut out = format!("{}\n", finished_task_header());
+            for (i, finished_task) in (offset..).zip(finished_tasks) {
+                let line = format!("{: <10} {}", i, finished_task);
+                out += &format!("{}\n", fit_line(&line, width));
+            }
+            page(&out)?;
+        }
+        SubCommand::Stats { by, last } => {
+            let histories = db.get_finished_within(0, i32::MAX as u32)?;
+            let today = Local::today().naive_local();
+            let stats = Stats::new(&histories, &Local, today, by, last)?;
+            page(&print_stats(&stats, terminal_width(), &theme))?;
+        }
+        SubCommand::Report {
+            period,
+            since,
+            until,
+            format,
+        } => {
+            let today = Local::today().naive_local();
+            let until = match until {
+                Some(until) => parse_day(&until, Tense::Past)?,
+                None => today,
+            };
+            let since = match since {
+                Some(since) => parse_day(&since, Tense::Past)?,
+                None => report_start(period, until).ok_or_else(|| {
+                    TodoError::InvalidInput(format!("a report can't end on {}", until))
+                })?,
+            };
+            if since > until {
+                bail!(
+                    "the report would start on {} after it ends on {}",
+                    since,
+                    until
+                );
+            }
+            let start_ts = unix_time(day_start(since, &Local));
+            let end_ts = unix_time(day_start(until.succ(), &Local));
+            let report = Report::new(
+                since,
+                until,
+                today,
+                &db.get_finished_within(start_ts, end_ts)?,
+                &db.get_tasks(None)?,
+                &db.get_all_subtasks()?,
+            );
+            print!("{}", report.render(format));
         }
-        SubCommand::Del { id_or_order } => {
+        SubCommand::Agenda { days } => {
+            let today = Local::today().naive_local();
+            let agenda = Agenda::new(&db.get_tasks(None)?, today, days)?;
+            page(&print_agenda(&agenda, terminal_width(), &theme))?;
+        }
+        SubCommand::Calendar { month } => {
+            let today = Local::today().naive_local();
+            let month = month.unwrap_or_else(|| Month::of(today));
+            let start_ts = unix_time(day_start(month.first, &Local));
+            let end_ts = Period::Month
+                .next(month.first)
+                .map_or(i32::MAX as u32, |end| unix_time(day_start(end, &Local)));
+            let calendar = Calendar::new(
+                month,
+                &db.get_tasks(None)?,
+                &db.get_finished_within(start_ts, end_ts)?,
+                &Local,
+            );
+            print!("{}", print_calendar(&calendar, today, &theme));
+        }
+        SubCommand::Del {
+            id_or_order: id_or_orders,
+        } => {
+            let id_or_orders = ids(&id_or_orders)?;
             if let Some(t) = opts.task_id {
-                let order = id_or_order;
-                db.remove_subtask(t, order)?;
+                change_subtasks(db.as_mut(), t, &id_or_orders, &mut |db, t, rank| {
+                    db.remove_subtask(t, rank)
+                })?;
             } else {
-                let id = id_or_order;
-                db.remove_task(id)?;
+                db.remove_tasks(&id_or_orders)?;
             }
         }
         SubCommand::Fin {
             id_or_order: id_or_orders,
         } => {
+            let id_or_orders = ids(&id_or_orders)?;
             if let Some(t) = opts.task_id {
-                // a finish of subtask would not be added into history
-                for id_or_order in id_or_orders.into_iter() {
-                    db.remove_subtask(t, id_or_order)?;
-                }
+                // a finished subtask is checked off and stays with its task,
+                // it would not be added into history
+                change_subtasks(db.as_mut(), t, &id_or_orders, &mut |db, t, rank| {
+                    db.finish_subtask(t, rank)
+                })?;
             } else {
-                for id_or_order in id_or_orders.into_iter() {
-                    db.finish_task(id_or_order)?;
+                db.finish_tasks(&id_or_orders)?;
+            }
+        }
+        SubCommand::Export {
+            format: ExportFormat::Csv,
+            table,
+            output,
+        } => {
+            let out: Box<dyn Write> = match output {
+                Some(path) => Box::new(
+                    fs::File::create(&path).with_context(|| format!("fail to create {}", path))?,
+                ),
+                None => Box::new(std::io::stdout()),
+            };
+            match table {
+                ExportTable::Tasks => write_csv(out, &db.get_tasks(None)?)?,
+                ExportTable::Subtasks => {
+                    let mut subtasks = vec![];
+                    for task in db.get_tasks(None)? {
+                        subtasks.extend(db.get_subtasks(task.id)?);
+                    }
+                    write_csv(out, &subtasks)?
+                }
+                ExportTable::History => {
+                    write_csv(out, &db.get_finished_within(0, i32::MAX as u32)?)?
                 }
             }
         }
+        SubCommand::Import { file, map } => {
+            let map: ColumnMap = map.as_deref().unwrap_or_default().parse()?;
+            let input = fs::File::open(&file).with_context(|| format!("fail to open {}", file))?;
+            let parsed = read_tasks_csv(input, &map)?;
+            if !parsed.errors.is_empty() {
+                for e in parsed.errors.iter() {
+                    eprintln!("{}: {}", file, e);
+                }
+                return Err(TodoError::InvalidInput(format!(
+                    "{} invalid line(s), nothing imported",
+                    parsed.errors.len()
+                ))
+                .into());
+            }
+            let count = parsed.tasks.len();
+            let mut pending = parsed.tasks;
+            db.transaction(&mut |db| {
+                pending
+                    .drain(..)
+                    .try_for_each(|task| db.add_task(task).map(|_| ()))
+            })?;
+            println!("imported {} task(s)", count);
+        }
+        SubCommand::Backup { file } => {
+            let backup = take_backup(db.as_ref())?;
+            let out =
+                fs::File::create(&file).with_context(|| format!("fail to create {}", file))?;
+            write_backup(out, &backup)?;
+            println!(
+                "backed up {} task(s), {} subtask(s), {} history record(s) to {}",
+                backup.tasks.len(),
+                backup.subtasks.len(),
+                backup.histories.len(),
+                file
+            );
+        }
+        SubCommand::Restore { file, on_conflict } => {
+            let input = fs::File::open(&file).with_context(|| format!("fail to open {}", file))?;
+            let backup = read_backup(input)?;
+            let summary = restore(db.as_mut(), &backup, on_conflict)?;
+            println!("restored from {}: {}", file, summary);
+        }
+        SubCommand::Backups {
+            cmd: BackupsCommand::List,
+        } => {
+            let names = snapshots.list()?;
+            if names.is_empty() {
+                println!("no backups in {}", snapshots.dir().display());
+            }
+            for name in names {
+                println!("{}", name);
+            }
+        }
+        SubCommand::Backups {
+            cmd: BackupsCommand::Restore { name },
+        } => {
+            snapshots.restore(&name, &db_path)?;
+            println!("restored {} from {}", db_path.display(), name);
+        }
+        SubCommand::Sync { remote, prefer } => {
+            let dir = match env::var("TODO_SYNC_DIR") {
+                Ok(dir) => PathBuf::from(dir),
+                Err(_) => sibling(&db_path, "sync"),
+            };
+            let repo = SyncRepo::open(dir)?;
+            if let Some(url) = remote {
+                repo.set_remote(&url)?;
+            }
+            let report = repo.sync(db.as_mut(), prefer)?;
+            if !report.conflicts.is_empty() {
+                for conflict in report.conflicts.iter() {
+                    eprintln!("{}", conflict);
+                }
+                bail!(
+                    "{} conflict(s), nothing merged; edit the tasks or rerun with --prefer local|remote",
+                    report.conflicts.len()
+                );
+            }
+            if report.pulled {
+                println!("merged remote changes");
+            }
+            if report.pushed {
+                println!("pushed");
+            } else if report.committed {
+                println!("committed local changes, no remote to push to");
+            } else {
+                println!("nothing to sync");
+            }
+        }
+        SubCommand::Merge { other, dry_run } => {
+            // read it as it is, plan refuses it when its schema differs
+            let theirs = other.parse::<Store>()?.open_existing()?;
+            let changes = merge::plan(db.as_ref(), theirs.as_ref())?;
+            for change in changes.iter() {
+                println!("{}", change);
+            }
+            let summary = merge::summarize(&changes);
+            if dry_run {
+                println!("dry run, {}", summary);
+            } else {
+                merge::apply(db.as_mut(), changes)?;
+                println!("merged {}: {}", other, summary);
+            }
+        }
+        SubCommand::MigrateStore { to } => {
+            if to == store {
+                return Err(TodoError::InvalidInput(format!("{} is the current store", to)).into());
+            }
+            let mut target = to.open()?;
+            let summary = migrate(db.as_ref(), target.as_mut())?;
+            println!(
+                "migrated {} to {}, point TODO_DB at it to use it",
+                summary, to
+            );
+        }
+        SubCommand::Serve { bind, token_file } => {
+            // each request opens the store itself
+            drop(db);
+            let token_file = token_file.unwrap_or_else(|| sibling(&db_path, "serve.token"));
+            let token = serve::load_token(&token_file)?;
+            let server = tiny_http::Server::http(&bind)
+                .map_err(|e| anyhow!("fail to listen on {}: {}", bind, e))?;
+            let addr = server
+                .server_addr()
+                .to_ip()
+                .map_or(bind.clone(), |addr| addr.to_string());
+            println!(
+                "listening on http://{}, token in {}",
+                addr,
+                token_file.display()
+            );
+            serve::serve(&server, &token, &|| store.open());
+        }
+        SubCommand::Rpc => {
+            // each request opens the store itself
+            drop(db);
+            let stdin = std::io::BufReader::new(std::io::stdin());
+            rpc::serve(stdin, &mut std::io::stdout(), &db_path, &|| store.open())?;
+        }
+        #[cfg(feature = "tui")]
+        SubCommand::Tui => todo::tui::run(db.as_mut())?,
     }
     Ok(())
 }
+
+// remove or finish the subtasks of `task_id` at `ranks`, all of them or none
+fn change_subtasks(
+    db: &mut dyn TaskDB,
+    task_id: i32,
+    ranks: &[i32],
+    change: &mut dyn FnMut(&mut dyn TaskDB, i32, i32) -> TodoResult<usize>,
+) -> TodoResult<()> {
+    db.transaction(&mut |db| {
+        let mut missing = Vec::new();
+        for &rank in ranks {
+            if change(db, task_id, rank)? == 0 {
+                missing.push(rank);
+            }
+        }
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(TodoError::Missing {
+                kind: "subtask",
+                ids: missing,
+            })
+        }
+    })
+}
+
+// the tasks `list` shows, the stale ones being those unchanged for `stale`
+fn listed(
+    db: &dyn TaskDB,
+    pattern: Option<String>,
+    stale: Option<Span>,
+    sort: &Sort,
+    page: Page,
+) -> TodoResult<Vec<Task>> {
+    let filter = TaskFilter {
+        pattern,
+        updated_before: stale.map(|span| (now() as i64 - span.seconds) as i32),
+    };
+    db.query_tasks(&filter, sort, page)
+}
+
+// the finished tasks sharing the uid or the description of `task`, newest first,
+// e.g. earlier rounds of a recurring chore
+fn related_history(db: &dyn TaskDB, task: &Task) -> TodoResult<Vec<History>> {
+    Ok(db
+        .get_finished_within(0, i32::MAX as u32)?
+        .into_iter()
+        .filter(|h| h.uid == task.uid || h.what == task.what)
+        .collect())
+}
+
+// open the description of the task in vi and save what is left when it quits
+fn edit_desc(db: &mut dyn TaskDB, id: i32, stdin_used: bool) -> anyhow::Result<()> {
+    // create a tempfile with current desc as the content
+    // spawn vi to edit the tempfile
+    // and update the current desc with the final file content
+    let task = db
+        .get_task(id)?
+        .ok_or(TodoError::NotFound { kind: "task", id })?;
+    let mut current_desc = NamedTempFile::new()?;
+    current_desc.write_all(task.what.as_bytes())?;
+    let path = current_desc.path();
+    let mut vi = Command::new("vi");
+    vi.arg(path);
+    if stdin_used {
+        // stdin is used up by the id, give vi the terminal
+        vi.stdin(fs::File::open("/dev/tty").context("fail to open the terminal")?);
+    }
+    vi.status().context("fail to use vi to update desc")?;
+    let new_desc: String = fs::read_to_string(path)
+        .context("fail to read new desc")?
+        .trim()
+        .to_string();
+    db.update_task_desc(id, new_desc)?;
+    Ok(())
+}
+
+// a task id or subtask rank argument; `-` stands for the ones on stdin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IdArg {
+    Id(i32),
+    Stdin,
+}
+
+impl FromStr for IdArg {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "-" {
+            Ok(IdArg::Stdin)
+        } else {
+            s.parse().map(IdArg::Id)
+        }
+    }
+}
+
+// the ids of the arguments, `-` replaced by the first tab-separated field
+// of each line on stdin, so that lines of `list --picker` can be piped back
+fn ids(args: &[IdArg]) -> anyhow::Result<Vec<i32>> {
+    let mut ids = vec![];
+    for arg in args {
+        match arg {
+            IdArg::Id(id) => ids.push(*id),
+            IdArg::Stdin => {
+                for line in std::io::stdin().lock().lines() {
+                    let line = line.context("fail to read ids from stdin")?;
+                    let field = line.split('\t').next().unwrap_or_default().trim();
+                    if field.is_empty() {
+                        continue;
+                    }
+                    let id = field
+                        .parse()
+                        .map_err(|_| TodoError::InvalidInput(format!("{} is not an id", field)))?;
+                    ids.push(id);
+                }
+            }
+        }
+    }
+    Ok(ids)
+}
+
+// the first day a report for `period` up to `until` covers: the day before,
+// the six days before or the same day of the month before; None before the
+// first date chrono has
+fn report_start(period: Period, until: NaiveDate) -> Option<NaiveDate> {
+    match period {
+        Period::Day => until.pred_opt(),
+        Period::Week => until.checked_sub_signed(chrono::Duration::days(6)),
+        Period::Month => {
+            let month = Period::Month.previous(Period::Month.start(until))?;
+            let last_day = Period::Month.start(until).pred();
+            Some(month.with_day(until.day()).unwrap_or(last_day))
+        }
+    }
+}
+
+// a date like `2026-10-19`, `monday` or `+3d 14:00`, see `When::parse`
+fn parse_when(input: &str, tense: Tense) -> Result<When, TodoError> {
+    When::parse(input, Local::now().naive_local(), tense)
+}
+
+// a timestamp as the stores keep them, dates before 1970 or after 2038 at the ends
+fn unix_time(ts: i64) -> u32 {
+    ts.clamp(0, i32::MAX as i64) as u32
+}
+
+// a date without a time of day
+fn parse_day(input: &str, tense: Tense) -> Result<NaiveDate, TodoError> {
+    When::parse_date(input, Local::now().naive_local(), tense)
+}
+
+// a due date as it is kept, None for `none`
+fn parse_due(due: &str) -> Result<Option<String>, TodoError> {
+    match due {
+        "none" => Ok(None),
+        date => parse_day(date, Tense::Future).map(|date| Some(due_field(date))),
+    }
+}
+
+// the theme of --theme or TODO_THEME, with the labels of TODO_PRIORITY_LABELS,
+// colored as --color says
+fn theme(theme: Option<Theme>, color: ColorChoice) -> anyhow::Result<Theme> {
+    let mut theme = match (theme, env::var("TODO_THEME")) {
+        (Some(theme), _) => theme,
+        (None, Ok(name)) => name.parse()?,
+        (None, Err(_)) => Theme::default(),
+    };
+    if let Ok(labels) = env::var("TODO_PRIORITY_LABELS") {
+        theme = theme.with_labels(&labels)?;
+    }
+    theme.color = theme.color && color.enabled();
+    Ok(theme)
+}
+
+// where sqlite snapshots go and how many of them are kept,
+// configured by TODO_BACKUP_DIR (default: `backups` next to TODO_DB) and TODO_BACKUP_KEEP
+fn snapshots(db_path: &Path) -> anyhow::Result<Snapshots> {
+    let dir = match env::var("TODO_BACKUP_DIR") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => sibling(db_path, "backups"),
+    };
+    let keep = match env::var("TODO_BACKUP_KEEP") {
+        Ok(keep) => keep
+            .parse()
+            .context("TODO_BACKUP_KEEP should be a non-negative integer")?,
+        Err(_) => 10,
+    };
+    Ok(Snapshots::new(dir, keep))
+}
+
+// a path next to the db file
+fn sibling(db_path: &Path, name: &str) -> PathBuf {
+    db_path.parent().unwrap_or_else(|| Path::new("")).join(name)
+}
+
+// the snapshot reason of commands that remove or rewrite data in place
+fn destructive(subcmd: &SubCommand) -> Option<&'static str> {
+    match subcmd {
+        SubCommand::Tidy => Some("tidy"),
+        SubCommand::Del { .. } => Some("del"),
+        SubCommand::Fin { id_or_order }
+            if id_or_order.len() > 1 || id_or_order.contains(&IdArg::Stdin) =>
+        {
+            Some("fin")
+        }
+        SubCommand::Restore { .. } => Some("restore"),
+        SubCommand::Sync { .. } => Some("sync"),
+        SubCommand::Merge { dry_run: false, .. } => Some("merge"),
+        SubCommand::Backups {
+            cmd: BackupsCommand::Restore { .. },
+        } => Some("restore"),
+        _ => None,
+    }
+}
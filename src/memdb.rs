@@ -0,0 +1,369 @@
+use std::collections::BTreeMap;
+
+use crate::error::{TodoError, TodoResult};
+use crate::models::{new_uid, FieldTime, History, NewTask, SubTask, Task};
+use crate::taskdb::{
+    changed_subtask_fields, changed_task_fields, check_desc, now, TaskDB, SCHEMA_VERSION,
+};
+
+type IDType = i32;
+
+/// A `TaskDB` kept in memory, for tests and for embedding.
+///
+/// It behaves like `TaskSqlite`: ids come from a per-table sequence that is
+/// reset to the largest remaining id whenever tasks or subtasks are removed,
+/// subtask ranks are one more than the number of subtasks of the task, and
+/// history is ordered by finish time, newest first.
+#[derive(Debug, Default, Clone)]
+pub struct TaskMemory {
+    tasks: BTreeMap<IDType, Task>,
+    subtasks: BTreeMap<IDType, SubTask>,
+    histories: BTreeMap<IDType, History>,
+    // when each (uid, field) last changed
+    field_times: BTreeMap<(String, String), i32>,
+    // the last id handed out for each table, like sqlite_sequence
+    task_seq: IDType,
+    subtask_seq: IDType,
+    history_seq: IDType,
+}
+
+impl TaskMemory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A store holding the given records, as if each of them had been put.
+    pub fn from_records(tasks: Vec<Task>, subtasks: Vec<SubTask>, histories: Vec<History>) -> Self {
+        let mut memory = Self::new();
+        for t in tasks {
+            memory.task_seq = memory.task_seq.max(t.id);
+            memory.tasks.insert(t.id, t);
+        }
+        for st in subtasks {
+            memory.subtask_seq = memory.subtask_seq.max(st.id);
+            memory.subtasks.insert(st.id, st);
+        }
+        for h in histories {
+            memory.history_seq = memory.history_seq.max(h.id);
+            memory.histories.insert(h.id, h);
+        }
+        memory
+    }
+
+    /// Every record of each table, ordered by id.
+    pub fn records(&self) -> (Vec<Task>, Vec<SubTask>, Vec<History>) {
+        (
+            self.tasks.values().cloned().collect(),
+            self.subtasks.values().cloned().collect(),
+            self.histories.values().cloned().collect(),
+        )
+    }
+
+    // `fields` of the task or subtask `uid` changed `at` then
+    fn stamp(&mut self, uid: &str, fields: &[&str], at: i32) {
+        for field in fields {
+            self.field_times
+                .insert((uid.to_owned(), (*field).to_owned()), at);
+        }
+    }
+
+    // a change to the subtasks of a task counts as a change to the task
+    fn touch(&mut self, task_id: IDType) {
+        if let Some(t) = self.tasks.get_mut(&task_id) {
+            t.updated_at = now();
+        }
+    }
+
+    fn histories_newest_first(&self) -> impl Iterator<Item = &History> {
+        let mut histories: Vec<&History> = self.histories.values().collect();
+        histories.sort_by_key(|h| std::cmp::Reverse((h.finish_timestamp, h.id)));
+        histories.into_iter()
+    }
+}
+
+impl TaskDB for TaskMemory {
+    fn add_task(&mut self, new_task: NewTask) -> TodoResult<IDType> {
+        check_desc(&new_task.what)?;
+        self.task_seq += 1;
+        let id = self.task_seq;
+        self.tasks.insert(
+            id,
+            Task {
+                id,
+                what: new_task.what,
+                link: new_task.link,
+                priority: new_task.priority,
+                uid: new_uid(),
+                created_at: now(),
+                due: new_task.due,
+                updated_at: now(),
+            },
+        );
+        Ok(id)
+    }
+
+    fn add_subtask(
+        &mut self,
+        task_id: IDType,
+        what: String,
+        link: Option<String>,
+    ) -> TodoResult<()> {
+        check_desc(&what)?;
+        if !self.tasks.contains_key(&task_id) {
+            return Err(TodoError::NotFound {
+                kind: "task",
+                id: task_id,
+            });
+        }
+        let rank = self
+            .subtasks
+            .values()
+            .filter(|st| st.task_id == task_id)
+            .count();
+        self.subtask_seq += 1;
+        let id = self.subtask_seq;
+        self.subtasks.insert(
+            id,
+            SubTask {
+                id,
+                what,
+                link,
+                subtask_rank: 1 + rank as i32,
+                task_id,
+                uid: new_uid(),
+                done: false,
+            },
+        );
+        self.touch(task_id);
+        Ok(())
+    }
+
+    fn get_task(&self, id: IDType) -> TodoResult<Option<Task>> {
+        Ok(self.tasks.get(&id).cloned())
+    }
+
+    fn get_tasks(&self, pattern: Option<String>) -> TodoResult<Vec<Task>> {
+        Ok(self
+            .tasks
+            .values()
+            .filter(|t| match &pattern {
+                Some(p) => like(&t.what, &format!("%{}%", p)),
+                None => true,
+            })
+            .cloned()
+            .collect())
+    }
+
+    fn get_subtasks(&self, id: IDType) -> TodoResult<Vec<SubTask>> {
+        if !self.tasks.contains_key(&id) {
+            return Err(TodoError::NotFound { kind: "task", id });
+        }
+        Ok(self
+            .subtasks
+            .values()
+            .filter(|st| st.task_id == id)
+            .cloned()
+            .collect())
+    }
+
+    fn get_finished(&self, last_n: u32) -> TodoResult<Vec<History>> {
+        Ok(self
+            .histories_newest_first()
+            .take(last_n as usize)
+            .cloned()
+            .collect())
+    }
+
+    fn get_finished_within(&self, start_ts: u32, end_ts: u32) -> TodoResult<Vec<History>> {
+        let (start_ts, end_ts) = (start_ts as i32, end_ts as i32);
+        Ok(self
+            .histories_newest_first()
+            .filter(|h| h.finish_timestamp >= start_ts && h.finish_timestamp < end_ts)
+            .cloned()
+            .collect())
+    }
+
+    fn update_task_desc(&mut self, id: IDType, desc: String) -> TodoResult<usize> {
+        check_desc(&desc)?;
+        Ok(match self.tasks.get_mut(&id) {
+            Some(t) => {
+                t.what = desc;
+                t.updated_at = now();
+                let uid = t.uid.clone();
+                self.stamp(&uid, &["what"], now());
+                1
+            }
+            None => 0,
+        })
+    }
+
+    fn update_task_priority(&mut self, task_id: IDType, pri: i32) -> TodoResult<usize> {
+        Ok(match self.tasks.get_mut(&task_id) {
+            Some(t) => {
+                t.priority = pri;
+                t.updated_at = now();
+                let uid = t.uid.clone();
+                self.stamp(&uid, &["priority"], now());
+                1
+            }
+            None => 0,
+        })
+    }
+
+    fn remove_task(&mut self, id: IDType) -> TodoResult<usize> {
+        let removed = self.tasks.remove(&id).is_some();
+        self.task_seq = self.tasks.keys().max().copied().unwrap_or(0);
+        Ok(removed as usize)
+    }
+
+    fn update_subtask_belongings(
+        &mut self,
+        task_id: IDType,
+        new_task_id: IDType,
+    ) -> TodoResult<usize> {
+        let mut updated = 0;
+        self.subtasks
+            .values_mut()
+            .filter(|st| st.task_id == task_id)
+            .for_each(|st| {
+                st.task_id = new_task_id;
+                updated += 1;
+            });
+        Ok(updated)
+    }
+
+    fn remove_subtask(&mut self, id: IDType, subtask_rank: i32) -> TodoResult<usize> {
+        let before = self.subtasks.len();
+        self.subtasks
+            .retain(|_, st| !(st.task_id == id && st.subtask_rank == subtask_rank));
+        self.subtask_seq = self.subtasks.keys().max().copied().unwrap_or(0);
+        let removed = before - self.subtasks.len();
+        if removed > 0 {
+            self.touch(id);
+        }
+        Ok(removed)
+    }
+
+    fn finish_subtask(&mut self, id: IDType, subtask_rank: i32) -> TodoResult<usize> {
+        let mut updated = 0;
+        self.subtasks
+            .values_mut()
+            .filter(|st| st.task_id == id && st.subtask_rank == subtask_rank)
+            .for_each(|st| {
+                st.done = true;
+                updated += 1;
+            });
+        if updated > 0 {
+            self.touch(id);
+        }
+        Ok(updated)
+    }
+
+    fn finish_task(&mut self, id: IDType) -> TodoResult<History> {
+        let task = self
+            .get_task(id)?
+            .ok_or(TodoError::NotFound { kind: "task", id })?;
+        self.remove_task(id)?;
+        self.history_seq += 1;
+        let history = History {
+            id: self.history_seq,
+            what: task.what,
+            link: task.link,
+            finish_timestamp: now(),
+            uid: task.uid,
+            created_at: task.created_at,
+            priority: Some(task.priority),
+        };
+        self.histories.insert(history.id, history.clone());
+        Ok(history)
+    }
+
+    fn get_all_subtasks(&self) -> TodoResult<Vec<SubTask>> {
+        let mut subtasks: Vec<SubTask> = self.subtasks.values().cloned().collect();
+        subtasks.sort_by_key(|st| (st.task_id, st.subtask_rank));
+        Ok(subtasks)
+    }
+
+    fn put_task(&mut self, task: &Task) -> TodoResult<()> {
+        self.task_seq = self.task_seq.max(task.id);
+        if let Some(old) = self.tasks.insert(task.id, task.clone()) {
+            self.stamp(&task.uid, &changed_task_fields(&old, task), task.updated_at);
+        }
+        Ok(())
+    }
+
+    fn put_subtask(&mut self, subtask: &SubTask) -> TodoResult<()> {
+        self.subtask_seq = self.subtask_seq.max(subtask.id);
+        if let Some(old) = self.subtasks.insert(subtask.id, subtask.clone()) {
+            self.stamp(&subtask.uid, &changed_subtask_fields(&old, subtask), now());
+        }
+        Ok(())
+    }
+
+    fn put_history(&mut self, history: &History) -> TodoResult<()> {
+        self.history_seq = self.history_seq.max(history.id);
+        self.histories.insert(history.id, history.clone());
+        Ok(())
+    }
+
+    fn get_field_times(&self) -> TodoResult<Vec<FieldTime>> {
+        Ok(self
+            .field_times
+            .iter()
+            .map(|((uid, field), changed_at)| FieldTime {
+                uid: uid.clone(),
+                field: field.clone(),
+                changed_at: *changed_at,
+            })
+            .collect())
+    }
+
+    fn put_field_time(&mut self, time: &FieldTime) -> TodoResult<()> {
+        self.field_times
+            .insert((time.uid.clone(), time.field.clone()), time.changed_at);
+        Ok(())
+    }
+
+    fn schema_version(&self) -> TodoResult<Option<String>> {
+        Ok(Some(SCHEMA_VERSION.to_owned()))
+    }
+
+    fn transaction(
+        &mut self,
+        f: &mut dyn FnMut(&mut dyn TaskDB) -> TodoResult<()>,
+    ) -> TodoResult<()> {
+        let before = self.clone();
+        let result = f(self);
+        if result.is_err() {
+            *self = before;
+        }
+        result
+    }
+}
+
+// sqlite's LIKE: `%` matches any run of characters, `_` a single one,
+// and ascii letters match regardless of case
+fn like(text: &str, pattern: &str) -> bool {
+    let text: Vec<char> = text.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    // matches[j]: whether text[..i] matches pattern[..j]
+    let mut matches = vec![false; pattern.len() + 1];
+    matches[0] = true;
+    for j in 1..=pattern.len() {
+        matches[j] = matches[j - 1] && pattern[j - 1] == '%';
+    }
+    for c in text {
+        let mut prev = matches[0];
+        matches[0] = false;
+        for j in 1..=pattern.len() {
+            let current = matches[j];
+            matches[j] = match pattern[j - 1] {
+                '%' => matches[j - 1] || current,
+                '_' => prev,
+                p => prev && p.eq_ignore_ascii_case(&c),
+            };
+            prev = current;
+        }
+    }
+    matches[pattern.len()]
+}
@@ -0,0 +1,469 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+
+use anyhow::Result;
+
+use crate::error::{TodoError, TodoResult};
+use crate::models::{new_uid, FieldTime, History, SubTask, Task};
+use crate::taskdb::{changed_subtask_fields, changed_task_fields, TaskDB};
+
+/// One change to be made to our store.
+///
+/// The field times are those of the fields taken from their store, to be kept
+/// with them so a later merge compares against when they really changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    AddTask(Task, Vec<SubTask>, Vec<FieldTime>),
+    EditTask {
+        old: Task,
+        new: Task,
+        times: Vec<FieldTime>,
+    },
+    FinishTask(Task),
+    AddSubTask(SubTask, Vec<FieldTime>),
+    EditSubTask {
+        old: SubTask,
+        new: SubTask,
+        times: Vec<FieldTime>,
+    },
+    AddHistory(History),
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::AddTask(t, subtasks, _) => {
+                write!(f, "+ task {:?}", t.what)?;
+                if !subtasks.is_empty() {
+                    write!(f, " with {} subtask(s)", subtasks.len())?;
+                }
+                Ok(())
+            }
+            Change::EditTask { old, new, .. } => {
+                write!(f, "~ task {}:", old.id)?;
+                if old.what != new.what {
+                    write!(f, " what {:?} -> {:?}", old.what, new.what)?;
+                }
+                if old.link != new.link {
+                    write!(f, " link {:?} -> {:?}", old.link, new.link)?;
+                }
+                if old.priority != new.priority {
+                    write!(f, " priority {} -> {}", old.priority, new.priority)?;
+                }
+                if old.due != new.due {
+                    write!(f, " due {:?} -> {:?}", old.due, new.due)?;
+                }
+                Ok(())
+            }
+            Change::FinishTask(t) => write!(f, "✓ task {} {:?}", t.id, t.what),
+            Change::AddSubTask(st, _) => {
+                write!(f, "+ subtask {:?} of task {}", st.what, st.task_id)
+            }
+            Change::EditSubTask { old, new, .. } => {
+                write!(f, "~ subtask {} of task {}:", old.subtask_rank, old.task_id)?;
+                if old.what != new.what {
+                    write!(f, " what {:?} -> {:?}", old.what, new.what)?;
+                }
+                if old.link != new.link {
+                    write!(f, " link {:?} -> {:?}", old.link, new.link)?;
+                }
+                Ok(())
+            }
+            Change::AddHistory(h) => write!(f, "+ history {:?}", h.what),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct MergeSummary {
+    pub tasks_added: usize,
+    pub tasks_edited: usize,
+    pub tasks_finished: usize,
+    pub subtasks_added: usize,
+    pub subtasks_edited: usize,
+    pub histories_added: usize,
+}
+
+impl Display for MergeSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "tasks: {} added, {} edited, {} finished; subtasks: {} added, {} edited; history: {} added",
+            self.tasks_added,
+            self.tasks_edited,
+            self.tasks_finished,
+            self.subtasks_added,
+            self.subtasks_edited,
+            self.histories_added
+        )
+    }
+}
+
+pub fn summarize(changes: &[Change]) -> MergeSummary {
+    let mut summary = MergeSummary::default();
+    for change in changes {
+        match change {
+            Change::AddTask(_, subtasks, _) => {
+                summary.tasks_added += 1;
+                summary.subtasks_added += subtasks.len();
+            }
+            Change::EditTask { .. } => summary.tasks_edited += 1,
+            Change::FinishTask(_) => summary.tasks_finished += 1,
+            Change::AddSubTask(..) => summary.subtasks_added += 1,
+            Change::EditSubTask { .. } => summary.subtasks_edited += 1,
+            Change::AddHistory(_) => summary.histories_added += 1,
+        }
+    }
+    summary
+}
+
+/// Work out what to change in `ours` so that it also holds what only `theirs` has.
+///
+/// Records are matched by uid first and then by content (description, and
+/// finish time for history), which pairs up copies made before uids existed;
+/// each record is matched at most once. Each field of a task or subtask that
+/// differs is taken from the side that changed that field last, by the field
+/// times each store keeps, so edits to different fields on the two sides are
+/// all kept; a subtask done on either side is done. Nothing is ever removed except tasks that
+/// were finished in `theirs`; a finish is only recognized by uid since
+/// descriptions repeat in history.
+///
+/// Fails with `InvalidInput` when the two stores have different schemas.
+pub fn plan(ours: &dyn TaskDB, theirs: &dyn TaskDB) -> Result<Vec<Change>> {
+    let (our_version, their_version) = (ours.schema_version()?, theirs.schema_version()?);
+    if our_version != their_version {
+        return Err(TodoError::InvalidInput(format!(
+            "the other store has schema version {} and this one {}",
+            their_version.as_deref().unwrap_or("none"),
+            our_version.as_deref().unwrap_or("none")
+        ))
+        .into());
+    }
+    let our_tasks = ours.get_tasks(None)?;
+    let their_tasks = theirs.get_tasks(None)?;
+    let our_histories = ours.get_finished_within(0, i32::MAX as u32)?;
+    let their_histories = theirs.get_finished_within(0, i32::MAX as u32)?;
+    let our_subtasks = ours.get_all_subtasks()?;
+    let their_subtasks = theirs.get_all_subtasks()?;
+    let our_times = field_times(ours)?;
+    let their_times = field_times(theirs)?;
+
+    let finished_here: HashSet<&str> = our_histories.iter().map(|h| h.uid.as_str()).collect();
+    let finished_there: HashSet<&str> = their_histories.iter().map(|h| h.uid.as_str()).collect();
+    let mut changes = vec![];
+
+    // (their task, our task) pairs
+    let mut matched: Vec<(&Task, &Task)> = vec![];
+    let found = pair_up(
+        &our_tasks,
+        &their_tasks,
+        |t| &t.uid,
+        |a, b| a.what == b.what,
+    );
+    for (theirs, found) in their_tasks.iter().zip(found) {
+        match found {
+            Some(ours) => {
+                matched.push((theirs, ours));
+                let mut new = ours.clone();
+                let mut times = vec![];
+                for field in changed_task_fields(ours, theirs) {
+                    // a field never changed since it was added loses to any change
+                    let our_at = our_times.get(&ours.uid, field);
+                    if let Some(their_at) = their_times
+                        .get(&theirs.uid, field)
+                        .filter(|&at| Some(at) > our_at)
+                    {
+                        take_task_field(&mut new, theirs, field);
+                        times.push(FieldTime {
+                            uid: ours.uid.clone(),
+                            field: field.to_owned(),
+                            changed_at: their_at,
+                        });
+                    }
+                }
+                if !times.is_empty() {
+                    new.updated_at = ours.updated_at.max(theirs.updated_at);
+                    changes.push(Change::EditTask {
+                        old: ours.clone(),
+                        new,
+                        times,
+                    });
+                }
+            }
+            // finished here, don't bring it back
+            None if finished_here.contains(theirs.uid.as_str()) => {}
+            None => {
+                let subtasks = their_subtasks
+                    .iter()
+                    .filter(|st| st.task_id == theirs.id)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let times = std::iter::once(theirs.uid.as_str())
+                    .chain(subtasks.iter().map(|st| st.uid.as_str()))
+                    .flat_map(|uid| their_times.of(uid))
+                    .collect();
+                changes.push(Change::AddTask(theirs.clone(), subtasks, times));
+            }
+        }
+    }
+
+    let matched_ours: HashSet<i32> = matched.iter().map(|(_, t)| t.id).collect();
+    for ours in our_tasks.iter().filter(|t| !matched_ours.contains(&t.id)) {
+        if finished_there.contains(ours.uid.as_str()) {
+            changes.push(Change::FinishTask(ours.clone()));
+        }
+    }
+
+    for (their_task, task) in matched {
+        let siblings: Vec<SubTask> = our_subtasks
+            .iter()
+            .filter(|st| st.task_id == task.id)
+            .cloned()
+            .collect();
+        let their_siblings: Vec<SubTask> = their_subtasks
+            .iter()
+            .filter(|st| st.task_id == their_task.id)
+            .cloned()
+            .collect();
+        let found = pair_up(
+            &siblings,
+            &their_siblings,
+            |st| &st.uid,
+            |a, b| a.what == b.what,
+        );
+        for (theirs, found) in their_siblings.iter().zip(found) {
+            match found {
+                Some(ours) => {
+                    let mut new = ours.clone();
+                    let mut times = vec![];
+                    for field in changed_subtask_fields(ours, theirs) {
+                        let our_at = our_times.get(&ours.uid, field);
+                        if let Some(their_at) = their_times
+                            .get(&theirs.uid, field)
+                            .filter(|&at| Some(at) > our_at)
+                        {
+                            take_subtask_field(&mut new, theirs, field);
+                            times.push(FieldTime {
+                                uid: ours.uid.clone(),
+                                field: field.to_owned(),
+                                changed_at: their_at,
+                            });
+                        }
+                    }
+                    // done on either side is done
+                    new.done = ours.done || theirs.done;
+                    if &new != ours {
+                        changes.push(Change::EditSubTask {
+                            old: ours.clone(),
+                            new,
+                            times,
+                        });
+                    }
+                }
+                None => changes.push(Change::AddSubTask(
+                    SubTask {
+                        task_id: task.id,
+                        ..theirs.clone()
+                    },
+                    their_times.of(&theirs.uid),
+                )),
+            }
+        }
+    }
+
+    for theirs in their_histories.iter() {
+        let known = our_histories.iter().any(|h| {
+            h.uid == theirs.uid
+                || (h.what == theirs.what && h.finish_timestamp == theirs.finish_timestamp)
+        });
+        if !known {
+            changes.push(Change::AddHistory(theirs.clone()));
+        }
+    }
+    Ok(changes)
+}
+
+// the field times of a store, by uid and field
+struct FieldTimes(HashMap<(String, String), i32>);
+
+impl FieldTimes {
+    fn get(&self, uid: &str, field: &str) -> Option<i32> {
+        self.0.get(&(uid.to_owned(), field.to_owned())).copied()
+    }
+
+    // those of one record, to be kept with it when it is added
+    fn of(&self, uid: &str) -> Vec<FieldTime> {
+        self.0
+            .iter()
+            .filter(|((of, _), _)| of == uid)
+            .map(|((uid, field), changed_at)| FieldTime {
+                uid: uid.clone(),
+                field: field.clone(),
+                changed_at: *changed_at,
+            })
+            .collect()
+    }
+}
+
+fn field_times(db: &dyn TaskDB) -> TodoResult<FieldTimes> {
+    Ok(FieldTimes(
+        db.get_field_times()?
+            .into_iter()
+            .map(|ft| ((ft.uid, ft.field), ft.changed_at))
+            .collect(),
+    ))
+}
+
+fn take_task_field(task: &mut Task, from: &Task, field: &str) {
+    match field {
+        "what" => task.what = from.what.clone(),
+        "link" => task.link = from.link.clone(),
+        "priority" => task.priority = from.priority,
+        "due" => task.due = from.due.clone(),
+        _ => unreachable!("not a task field: {}", field),
+    }
+}
+
+fn take_subtask_field(subtask: &mut SubTask, from: &SubTask, field: &str) {
+    match field {
+        "what" => subtask.what = from.what.clone(),
+        "link" => subtask.link = from.link.clone(),
+        _ => unreachable!("not a subtask field: {}", field),
+    }
+}
+
+// the record of `ours` each of `theirs` matches, by uid and then by `same`;
+// none is matched twice, and uids are matched before any content
+fn pair_up<'a, T>(
+    ours: &'a [T],
+    theirs: &[T],
+    uid: impl Fn(&T) -> &String,
+    same: impl Fn(&T, &T) -> bool,
+) -> Vec<Option<&'a T>> {
+    let mut found: Vec<Option<usize>> = theirs
+        .iter()
+        .map(|t| ours.iter().position(|o| uid(o) == uid(t)))
+        .collect();
+    let mut taken: HashSet<usize> = found.iter().flatten().copied().collect();
+    for (t, found) in theirs.iter().zip(found.iter_mut()) {
+        if found.is_none() {
+            *found = (0..ours.len()).find(|&i| !taken.contains(&i) && same(&ours[i], t));
+            taken.extend(*found);
+        }
+    }
+    found.into_iter().map(|i| i.map(|i| &ours[i])).collect()
+}
+
+/// Apply planned changes to our store; new records get fresh ids.
+/// Either all of the changes are made or, when one fails, none are.
+pub fn apply(ours: &mut dyn TaskDB, changes: Vec<Change>) -> Result<()> {
+    ours.transaction(&mut |db| apply_changes(db, &changes))?;
+    Ok(())
+}
+
+fn apply_changes(ours: &mut dyn TaskDB, changes: &[Change]) -> TodoResult<()> {
+    let tasks = ours.get_tasks(None)?;
+    let subtasks = ours.get_all_subtasks()?;
+    let histories = ours.get_finished_within(0, i32::MAX as u32)?;
+    let mut next_task_id = tasks.iter().map(|t| t.id).max().unwrap_or(0);
+    let mut next_subtask_id = subtasks.iter().map(|st| st.id).max().unwrap_or(0);
+    let mut next_history_id = histories.iter().map(|h| h.id).max().unwrap_or(0);
+    let mut uids: HashSet<String> = tasks
+        .into_iter()
+        .map(|t| t.uid)
+        .chain(subtasks.iter().map(|st| st.uid.clone()))
+        .chain(histories.into_iter().map(|h| h.uid))
+        .collect();
+    let mut ranks: HashMap<i32, i32> = HashMap::new();
+    for st in subtasks.iter() {
+        let rank = ranks.entry(st.task_id).or_default();
+        *rank = (*rank).max(st.subtask_rank);
+    }
+    // keep their uid unless it is already taken here
+    let mut fresh_uid = |uid: String| {
+        if uids.insert(uid.clone()) {
+            uid
+        } else {
+            new_uid()
+        }
+    };
+
+    for change in changes.iter().cloned() {
+        match change {
+            Change::AddTask(task, task_subtasks, times) => {
+                next_task_id += 1;
+                let task_id = next_task_id;
+                let mut renamed = HashMap::new();
+                let uid = fresh_uid(task.uid.clone());
+                renamed.insert(task.uid.clone(), uid.clone());
+                ours.put_task(&Task {
+                    id: task_id,
+                    uid,
+                    ..task
+                })?;
+                for (rank, st) in task_subtasks.into_iter().enumerate() {
+                    next_subtask_id += 1;
+                    let uid = fresh_uid(st.uid.clone());
+                    renamed.insert(st.uid.clone(), uid.clone());
+                    ours.put_subtask(&SubTask {
+                        id: next_subtask_id,
+                        task_id,
+                        subtask_rank: rank as i32 + 1,
+                        uid,
+                        ..st
+                    })?;
+                }
+                put_field_times(ours, times, &renamed)?;
+            }
+            Change::EditTask { new, times, .. } => {
+                ours.put_task(&new)?;
+                put_field_times(ours, times, &HashMap::new())?;
+            }
+            Change::FinishTask(task) => {
+                ours.remove_task(task.id)?;
+            }
+            Change::AddSubTask(st, times) => {
+                next_subtask_id += 1;
+                let rank = ranks.entry(st.task_id).or_default();
+                *rank += 1;
+                let uid = fresh_uid(st.uid.clone());
+                let renamed = HashMap::from([(st.uid.clone(), uid.clone())]);
+                ours.put_subtask(&SubTask {
+                    id: next_subtask_id,
+                    subtask_rank: *rank,
+                    uid,
+                    ..st
+                })?;
+                put_field_times(ours, times, &renamed)?;
+            }
+            Change::EditSubTask { new, times, .. } => {
+                ours.put_subtask(&new)?;
+                put_field_times(ours, times, &HashMap::new())?;
+            }
+            Change::AddHistory(h) => {
+                next_history_id += 1;
+                ours.put_history(&History {
+                    id: next_history_id,
+                    uid: fresh_uid(h.uid),
+                    ..h
+                })?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// keep their field times, under the uids `renamed` gave their records here;
+// put after the records since putting those stamps the fields changed now
+fn put_field_times(
+    ours: &mut dyn TaskDB,
+    times: Vec<FieldTime>,
+    renamed: &HashMap<String, String>,
+) -> TodoResult<()> {
+    times.into_iter().try_for_each(|ft| {
+        ours.put_field_time(&FieldTime {
+            uid: renamed.get(&ft.uid).cloned().unwrap_or(ft.uid),
+            ..ft
+        })
+    })
+}
@@ -1,34 +1,105 @@
+use chrono::NaiveDate;
 use diesel::Queryable;
+use serde::{Deserialize, Serialize};
 
-use super::schema::{histories, subtasks, tasks};
+use super::schema::{field_times, histories, subtasks, tasks};
 
-#[derive(Identifiable, Debug, Queryable, PartialEq, Eq)]
+#[derive(
+    Identifiable,
+    Debug,
+    Clone,
+    Queryable,
+    Insertable,
+    AsChangeset,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+)]
 #[table_name = "tasks"]
+#[changeset_options(treat_none_as_null = "true")]
 pub struct Task {
     pub id: i32,
     pub what: String,
     pub link: Option<String>,
     pub priority: i32,
+    pub uid: String,
+    // unix time the task was added; absent from stores written before it was kept
+    #[serde(default)]
+    pub created_at: i32,
+    // the day the task is due, yyyy-mm-dd
+    #[serde(default)]
+    pub due: Option<String>,
+    // unix time the task or its subtasks were last changed
+    #[serde(default)]
+    pub updated_at: i32,
 }
 
-#[derive(Insertable)]
+impl Task {
+    /// The day the task is due, None without one or when it is not a date.
+    pub fn due_date(&self) -> Option<NaiveDate> {
+        parse_due(self.due.as_deref()?)
+    }
+
+    /// Whether the task was due before `today`.
+    pub fn is_overdue(&self, today: NaiveDate) -> bool {
+        self.due_date().is_some_and(|due| due < today)
+    }
+}
+
+/// A due date as it is kept, yyyy-mm-dd.
+pub fn due_field(date: NaiveDate) -> String {
+    date.format(DUE_FORMAT).to_string()
+}
+
+fn parse_due(field: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(field, DUE_FORMAT).ok()
+}
+
+const DUE_FORMAT: &str = "%Y-%m-%d";
+
+#[derive(Insertable, Deserialize)]
 #[table_name = "tasks"]
 pub struct NewTask {
     pub what: String,
     pub link: Option<String>,
+    #[serde(default = "default_priority")]
     pub priority: i32,
+    #[serde(default)]
+    pub due: Option<String>,
+}
+
+fn default_priority() -> i32 {
+    5
 }
 
-#[derive(Debug, PartialEq, Eq, Identifiable, Queryable, Associations)]
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    Identifiable,
+    Queryable,
+    Insertable,
+    AsChangeset,
+    Associations,
+    Serialize,
+    Deserialize,
+)]
 #[belongs_to(Task)]
 #[table_name = "subtasks"]
+#[changeset_options(treat_none_as_null = "true")]
 pub struct SubTask {
     pub id: i32,
     pub what: String,
     pub link: Option<String>,
+    pub subtask_rank: i32,
     #[warn(dead_code)]
     pub task_id: i32,
-    pub subtask_rank: i32,
+    pub uid: String,
+    // finished subtasks are kept until their task is finished
+    #[serde(default)]
+    pub done: bool,
 }
 
 #[derive(Insertable)]
@@ -38,15 +109,42 @@ pub struct NewSubTask {
     pub link: Option<String>,
     pub task_id: i32,
     pub subtask_rank: i32,
+    pub uid: String,
 }
 
-#[derive(Debug, PartialEq, Eq, Identifiable, Queryable)]
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    Identifiable,
+    Queryable,
+    Insertable,
+    AsChangeset,
+    Serialize,
+    Deserialize,
+)]
 #[table_name = "histories"]
+#[changeset_options(treat_none_as_null = "true")]
 pub struct History {
     pub id: i32,
     pub what: String,
     pub link: Option<String>,
     pub finish_timestamp: i32,
+    pub uid: String,
+    // unix time the task was added, 0 when that's unknown
+    #[serde(default)]
+    pub created_at: i32,
+    // the priority of the task when it was finished, None when that's unknown
+    #[serde(default)]
+    pub priority: Option<i32>,
+}
+
+impl History {
+    /// How many seconds the task took from being added to being finished.
+    pub fn lead_time(&self) -> Option<i32> {
+        (self.created_at > 0).then(|| self.finish_timestamp - self.created_at)
+    }
 }
 
 #[derive(Insertable)]
@@ -55,4 +153,44 @@ pub struct NewHistory {
     pub what: String,
     pub link: Option<String>,
     pub finish_timestamp: i32,
+    pub uid: String,
+    pub created_at: i32,
+    pub priority: Option<i32>,
+}
+
+/// When a field of a task or subtask was last changed, so that two copies
+/// of the store can be merged field by field.
+#[derive(Debug, Clone, PartialEq, Eq, Queryable, Insertable, Serialize, Deserialize)]
+#[table_name = "field_times"]
+pub struct FieldTime {
+    // the uid of the task or subtask
+    pub uid: String,
+    // what, link, priority or due
+    pub field: String,
+    pub changed_at: i32,
+}
+
+/// The `#tags` of a description, e.g. `work` in "plan the #work week", each
+/// once, in the order they first appear. A tag is made of letters, digits,
+/// `-`, `_` and `/`, and starts a word.
+pub fn tags(what: &str) -> Vec<String> {
+    let mut tags: Vec<String> = vec![];
+    for word in what.split_whitespace() {
+        let tag: String = match word.strip_prefix('#') {
+            Some(rest) => rest
+                .chars()
+                .take_while(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '/'))
+                .collect(),
+            None => continue,
+        };
+        if !tag.is_empty() && !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    tags
+}
+
+/// A random stable id for a new record.
+pub fn new_uid() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
 }
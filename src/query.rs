@@ -0,0 +1,300 @@
+use std::cmp::Ordering;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{TodoError, TodoResult};
+use crate::models::{History, Task};
+
+/// Which tasks a query returns.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskFilter {
+    /// Tasks whose description contains it, ascii letters in any case.
+    #[serde(default)]
+    pub pattern: Option<String>,
+    /// Tasks last changed before this unix time.
+    #[serde(default)]
+    pub updated_before: Option<i32>,
+}
+
+impl TaskFilter {
+    pub fn matches_update(&self, task: &Task) -> bool {
+        match self.updated_before {
+            Some(before) => task.updated_at < before,
+            None => true,
+        }
+    }
+}
+
+/// A length of time like `14d`: a number of hours (h), days (d) or weeks (w).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub seconds: i64,
+}
+
+impl FromStr for Span {
+    type Err = TodoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            TodoError::InvalidInput(format!(
+                "`{}` is not a span of time, expect e.g. 12h, 14d or 2w",
+                s
+            ))
+        };
+        let unit = match s.chars().last() {
+            Some('h') => 3600,
+            Some('d') => 24 * 3600,
+            Some('w') => 7 * 24 * 3600,
+            _ => return Err(invalid()),
+        };
+        let count: u32 = s[..s.len() - 1].parse().map_err(|_| invalid())?;
+        Ok(Span {
+            seconds: count as i64 * unit,
+        })
+    }
+}
+
+/// What tasks and history can be ordered by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    Priority,
+    Id,
+    Due,
+    Created,
+    Updated,
+    Alphabetical,
+    Finished,
+}
+
+impl SortField {
+    const NAMES: [(&'static str, SortField); 7] = [
+        ("priority", SortField::Priority),
+        ("id", SortField::Id),
+        ("due", SortField::Due),
+        ("created", SortField::Created),
+        ("updated", SortField::Updated),
+        ("alphabetical", SortField::Alphabetical),
+        ("finished", SortField::Finished),
+    ];
+
+    pub fn name(self) -> &'static str {
+        Self::NAMES.iter().find(|(_, f)| *f == self).unwrap().0
+    }
+}
+
+/// One key of a `Sort`, e.g. `due` or `priority:desc`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortKey {
+    pub field: SortField,
+    pub desc: bool,
+}
+
+impl FromStr for SortKey {
+    type Err = TodoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, direction) = s.split_once(':').unwrap_or((s, "asc"));
+        let desc = match direction {
+            "asc" => false,
+            "desc" => true,
+            _ => {
+                return Err(TodoError::InvalidInput(format!(
+                    "unknown sort direction `{}`, expect asc or desc",
+                    direction
+                )))
+            }
+        };
+        let field = match name {
+            "alpha" => SortField::Alphabetical,
+            _ => SortField::NAMES
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, f)| *f)
+                .ok_or_else(|| {
+                    let names: Vec<&str> = SortField::NAMES.iter().map(|(n, _)| *n).collect();
+                    TodoError::InvalidInput(format!(
+                        "unknown sort key `{}`, expect one of {}",
+                        name,
+                        names.join(", ")
+                    ))
+                })?,
+        };
+        Ok(SortKey { field, desc })
+    }
+}
+
+impl Display for SortKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let direction = if self.desc { "desc" } else { "asc" };
+        write!(f, "{}:{}", self.field.name(), direction)
+    }
+}
+
+/// The order to list records in: comma separated keys, compared in turn,
+/// e.g. `due,priority:desc`. Tasks without a due date come after the others
+/// whichever the direction, and ties are broken by id in the direction of
+/// the last key, so every store lists the same records the same way.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Sort(pub Vec<SortKey>);
+
+impl Sort {
+    /// Tasks by priority, lowest first, the way `list` shows them.
+    pub fn tasks_default() -> Self {
+        Sort(vec![SortKey {
+            field: SortField::Priority,
+            desc: false,
+        }])
+    }
+
+    /// History by finish time, newest first.
+    pub fn history_default() -> Self {
+        Sort(vec![SortKey {
+            field: SortField::Finished,
+            desc: true,
+        }])
+    }
+
+    /// The keys followed by the id, which tells any two records apart.
+    pub fn keys(&self) -> Vec<SortKey> {
+        let mut keys = self.0.clone();
+        if !keys.iter().any(|k| k.field == SortField::Id) {
+            let desc = keys.last().is_some_and(|k| k.desc);
+            keys.push(SortKey {
+                field: SortField::Id,
+                desc,
+            });
+        }
+        keys
+    }
+
+    /// Fail with `InvalidInput` unless history has every field of the keys.
+    pub fn check_history(&self) -> TodoResult<()> {
+        match self.0.iter().find(|k| {
+            matches!(
+                k.field,
+                SortField::Priority | SortField::Due | SortField::Created | SortField::Updated
+            )
+        }) {
+            Some(k) => Err(TodoError::InvalidInput(format!(
+                "history can't be sorted by {}",
+                k.field.name()
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Fail with `InvalidInput` unless tasks have every field of the keys.
+    pub fn check_tasks(&self) -> TodoResult<()> {
+        match self.0.iter().find(|k| k.field == SortField::Finished) {
+            Some(_) => Err(TodoError::InvalidInput(
+                "tasks can't be sorted by finished".to_owned(),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    pub fn compare_tasks(&self, a: &Task, b: &Task) -> Ordering {
+        self.compare(|field, desc| {
+            let ordering = match field {
+                SortField::Priority => a.priority.cmp(&b.priority),
+                SortField::Id => a.id.cmp(&b.id),
+                SortField::Created => a.created_at.cmp(&b.created_at),
+                SortField::Updated => a.updated_at.cmp(&b.updated_at),
+                SortField::Alphabetical => a.what.cmp(&b.what),
+                SortField::Due => {
+                    // no due date is last either way
+                    let missing = a.due.is_none().cmp(&b.due.is_none());
+                    return missing.then_with(|| direct(a.due.cmp(&b.due), desc));
+                }
+                SortField::Finished => Ordering::Equal,
+            };
+            direct(ordering, desc)
+        })
+    }
+
+    pub fn compare_histories(&self, a: &History, b: &History) -> Ordering {
+        self.compare(|field, desc| {
+            let ordering = match field {
+                SortField::Id => a.id.cmp(&b.id),
+                SortField::Alphabetical => a.what.cmp(&b.what),
+                SortField::Finished => a.finish_timestamp.cmp(&b.finish_timestamp),
+                SortField::Priority | SortField::Due | SortField::Created | SortField::Updated => {
+                    Ordering::Equal
+                }
+            };
+            direct(ordering, desc)
+        })
+    }
+
+    fn compare(&self, by: impl Fn(SortField, bool) -> Ordering) -> Ordering {
+        self.keys()
+            .into_iter()
+            .map(|k| by(k.field, k.desc))
+            .find(|o| o.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+fn direct(ordering: Ordering, desc: bool) -> Ordering {
+    if desc {
+        ordering.reverse()
+    } else {
+        ordering
+    }
+}
+
+impl FromStr for Sort {
+    type Err = TodoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let keys = s
+            .split(',')
+            .map(|k| k.trim().parse())
+            .collect::<TodoResult<Vec<SortKey>>>()?;
+        Ok(Sort(keys))
+    }
+}
+
+impl Display for Sort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let keys: Vec<String> = self.0.iter().map(|k| k.to_string()).collect();
+        write!(f, "{}", keys.join(","))
+    }
+}
+
+impl TryFrom<String> for Sort {
+    type Error = TodoError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Sort> for String {
+    fn from(sort: Sort) -> Self {
+        sort.to_string()
+    }
+}
+
+/// Which slice of the sorted records to return: `offset` are skipped, then
+/// at most `limit` are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Page {
+    #[serde(default)]
+    pub limit: Option<u32>,
+    #[serde(default)]
+    pub offset: u32,
+}
+
+impl Page {
+    pub fn apply<T>(self, records: Vec<T>) -> Vec<T> {
+        let records = records.into_iter().skip(self.offset as usize);
+        match self.limit {
+            Some(limit) => records.take(limit as usize).collect(),
+            None => records.collect(),
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use chrono::NaiveDate;
+
+use crate::error::TodoError;
+use crate::models::{tags, History, SubTask, Task};
+
+// how many of the open tasks the report suggests doing next
+const UPCOMING: usize = 5;
+
+/// How a report is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Markdown,
+    Text,
+}
+
+impl FromStr for ReportFormat {
+    type Err = TodoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "markdown" | "md" => Ok(ReportFormat::Markdown),
+            "text" => Ok(ReportFormat::Text),
+            _ => Err(TodoError::InvalidInput(format!(
+                "unknown report format `{}`, expect markdown or text",
+                s
+            ))),
+        }
+    }
+}
+
+/// A summary for a status update: what was finished from `since` through
+/// `until`, grouped by tag, and where the open tasks stand on `today`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub since: NaiveDate,
+    pub until: NaiveDate,
+    /// The finished tasks under their first `#tag`, the untagged ones last,
+    /// each group oldest first.
+    pub finished: Vec<(Option<String>, Vec<History>)>,
+    /// The open tasks with some of their subtasks done, with how many are
+    /// done out of how many.
+    pub in_progress: Vec<(Task, usize, usize)>,
+    /// The open tasks due before `today`, the most overdue first.
+    pub overdue: Vec<Task>,
+    /// The open tasks of the highest priority that are not overdue, the ones
+    /// due soonest first among equals.
+    pub upcoming: Vec<Task>,
+}
+
+impl Report {
+    pub fn new(
+        since: NaiveDate,
+        until: NaiveDate,
+        today: NaiveDate,
+        finished: &[History],
+        tasks: &[Task],
+        subtasks: &[SubTask],
+    ) -> Self {
+        let mut by_tag: BTreeMap<Option<String>, Vec<History>> = BTreeMap::new();
+        for h in finished {
+            let tag = tags(&h.what).into_iter().next();
+            by_tag.entry(tag).or_default().push(h.clone());
+        }
+        let mut groups: Vec<(Option<String>, Vec<History>)> = by_tag.into_iter().collect();
+        // None sorts first in the map, the untagged go last
+        let untagged = groups.iter().take_while(|(tag, _)| tag.is_none()).count();
+        groups.rotate_left(untagged);
+        for (_, histories) in groups.iter_mut() {
+            histories.sort_by_key(|h| (h.finish_timestamp, h.id));
+        }
+
+        let mut in_progress = vec![];
+        for task in tasks {
+            let own: Vec<&SubTask> = subtasks.iter().filter(|st| st.task_id == task.id).collect();
+            let done = own.iter().filter(|st| st.done).count();
+            if done > 0 {
+                in_progress.push((task.clone(), done, own.len()));
+            }
+        }
+
+        let mut overdue: Vec<Task> = tasks
+            .iter()
+            .filter(|t| t.is_overdue(today))
+            .cloned()
+            .collect();
+        overdue.sort_by(|a, b| a.due.cmp(&b.due).then(a.id.cmp(&b.id)));
+
+        let mut upcoming: Vec<Task> = tasks
+            .iter()
+            .filter(|t| !t.is_overdue(today))
+            .cloned()
+            .collect();
+        upcoming.sort_by(|a, b| {
+            b.priority
+                .cmp(&a.priority)
+                .then(a.due.is_none().cmp(&b.due.is_none()))
+                .then(a.due.cmp(&b.due))
+                .then(a.id.cmp(&b.id))
+        });
+        upcoming.truncate(UPCOMING);
+
+        Report {
+            since,
+            until,
+            finished: groups,
+            in_progress,
+            overdue,
+            upcoming,
+        }
+    }
+
+    pub fn render(&self, format: ReportFormat) -> String {
+        let (title, section, group, item) = match format {
+            ReportFormat::Markdown => ("# ", "## ", "### ", "- "),
+            ReportFormat::Text => ("", "", "  ", "  - "),
+        };
+        // text groups indent their items one more level
+        let group_item = match format {
+            ReportFormat::Markdown => item.to_owned(),
+            ReportFormat::Text => format!("  {}", item),
+        };
+        let mut out = format!("{}Report {} to {}\n", title, self.since, self.until);
+
+        let total: usize = self.finished.iter().map(|(_, hs)| hs.len()).sum();
+        out.push_str(&format!("\n{}Finished ({})\n", section, total));
+        if total == 0 {
+            out.push_str(&format!("{}nothing\n", item));
+        }
+        for (tag, histories) in &self.finished {
+            let name = match tag {
+                Some(tag) => format!("#{}", tag),
+                None => "untagged".to_owned(),
+            };
+            out.push_str(&format!("{}{}\n", group, name));
+            for h in histories {
+                out.push_str(&format!("{}{}\n", group_item, one_line(&h.what)));
+            }
+        }
+
+        out.push_str(&format!(
+            "\n{}In progress ({})\n",
+            section,
+            self.in_progress.len()
+        ));
+        for (task, done, total) in &self.in_progress {
+            out.push_str(&format!(
+                "{}{} {} ({}/{})\n",
+                item,
+                task.id,
+                one_line(&task.what),
+                done,
+                total
+            ));
+        }
+
+        out.push_str(&format!("\n{}Overdue ({})\n", section, self.overdue.len()));
+        for task in &self.overdue {
+            out.push_str(&format!("{}{}\n", item, task_line(task)));
+        }
+
+        out.push_str(&format!("\n{}Up next\n", section));
+        for task in &self.upcoming {
+            out.push_str(&format!("{}{}\n", item, task_line(task)));
+        }
+        out
+    }
+}
+
+// the id, the description, the priority and the due date of an open task
+fn task_line(task: &Task) -> String {
+    let mut line = format!(
+        "{} {} (priority {}",
+        task.id,
+        one_line(&task.what),
+        task.priority
+    );
+    if let Some(due) = &task.due {
+        line.push_str(&format!(", due {}", due));
+    }
+    line.push(')');
+    line
+}
+
+fn one_line(what: &str) -> &str {
+    what.lines().next().unwrap_or("")
+}
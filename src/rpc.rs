@@ -0,0 +1,400 @@
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::error::{TodoError, TodoResult};
+use crate::models::{History, NewTask, SubTask, Task};
+use crate::query::{Page, Sort, TaskFilter};
+use crate::taskdb::TaskDB;
+
+type IDType = i32;
+
+// how often the db file is checked for changes made by other invocations
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Answer JSON-RPC 2.0 requests read from `input`, one message (or batch) per
+/// line, writing one line per response to `output` until `input` ends.
+///
+/// The methods are the ones of `TaskDB`, named alike and taking their arguments
+/// by name, e.g. `{"method": "finish_task", "params": {"id": 1}}`, or by
+/// position; by name the filter and page of the queries are spread into the
+/// params, by position they are objects of their own. A failing
+/// `TaskDB` call is answered with the code `-32000 - exit code` of its error.
+/// Whenever the file at `path` is changed by someone else, a `changed`
+/// notification is sent. The store is opened for each request.
+pub fn serve<R: BufRead + Send + 'static>(
+    input: R,
+    output: &mut dyn Write,
+    path: &Path,
+    open: &dyn Fn() -> TodoResult<Box<dyn TaskDB>>,
+) -> Result<()> {
+    let (lines, incoming) = mpsc::channel();
+    thread::spawn(move || {
+        for line in input.lines() {
+            if lines.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut seen = fingerprint(path);
+    loop {
+        match incoming.recv_timeout(POLL_INTERVAL) {
+            Ok(line) => {
+                let line = line.context("fail to read a request")?;
+                let before = fingerprint(path);
+                if let Some(response) = respond(&line, open) {
+                    send(output, &response)?;
+                }
+                // the requests' own changes are not news to the client, but a
+                // change made by someone else before it still is
+                if before == seen {
+                    seen = fingerprint(path);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                let now = fingerprint(path);
+                if now != seen {
+                    seen = now;
+                    let params = json!({ "path": path.display().to_string() });
+                    send(output, &notification("changed", params))?;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+// when and how the db file was last written, None while it doesn't exist
+fn fingerprint(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+fn send(output: &mut dyn Write, message: &Value) -> Result<()> {
+    writeln!(output, "{}", message)
+        .and_then(|_| output.flush())
+        .context("fail to write a response")
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+// the response to a line, None when it only holds notifications
+fn respond(line: &str, open: &dyn Fn() -> TodoResult<Box<dyn TaskDB>>) -> Option<Value> {
+    let message: Value = match serde_json::from_str(line) {
+        Ok(message) => message,
+        Err(e) => return Some(failure(Value::Null, RpcError::new(PARSE_ERROR, e))),
+    };
+    match message {
+        Value::Array(batch) if batch.is_empty() => Some(failure(
+            Value::Null,
+            RpcError::new(INVALID_REQUEST, "empty batch"),
+        )),
+        Value::Array(batch) => {
+            let responses: Vec<Value> = batch
+                .into_iter()
+                .filter_map(|request| respond_one(request, open))
+                .collect();
+            (!responses.is_empty()).then_some(Value::Array(responses))
+        }
+        request => respond_one(request, open),
+    }
+}
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    params: Value,
+    id: Option<Value>,
+}
+
+fn respond_one(request: Value, open: &dyn Fn() -> TodoResult<Box<dyn TaskDB>>) -> Option<Value> {
+    let request: Request = match serde_json::from_value(request) {
+        Ok(request) => request,
+        Err(e) => return Some(failure(Value::Null, RpcError::new(INVALID_REQUEST, e))),
+    };
+    if request.jsonrpc != "2.0" {
+        let error = RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\"");
+        return Some(failure(request.id.unwrap_or(Value::Null), error));
+    }
+    let result = open()
+        .map_err(RpcError::from)
+        .and_then(|mut db| call(db.as_mut(), &request.method, request.params));
+    // requests without an id are notifications and get no response
+    let id = request.id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+        Err(error) => failure(id, error),
+    })
+}
+
+fn failure(id: Value, error: RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "error": error, "id": id })
+}
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+#[derive(Debug, Serialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl ToString) -> Self {
+        RpcError {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<TodoError> for RpcError {
+    fn from(e: TodoError) -> Self {
+        RpcError::new(-32000 - e.exit_code() as i64, e)
+    }
+}
+
+#[derive(Deserialize)]
+struct Id {
+    id: IDType,
+}
+
+#[derive(Deserialize)]
+struct Pattern {
+    pattern: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct NewSubTaskParams {
+    id: IDType,
+    what: String,
+    link: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LastN {
+    last_n: u32,
+}
+
+#[derive(Deserialize)]
+struct Within {
+    start_ts: u32,
+    end_ts: u32,
+}
+
+#[derive(Deserialize)]
+struct TaskQuery {
+    #[serde(flatten)]
+    filter: TaskFilter,
+    #[serde(default = "Sort::tasks_default")]
+    sort: Sort,
+    #[serde(flatten)]
+    page: Page,
+}
+
+#[derive(Deserialize)]
+struct FinishedQuery {
+    start_ts: u32,
+    end_ts: u32,
+    #[serde(default = "Sort::history_default")]
+    sort: Sort,
+    #[serde(flatten)]
+    page: Page,
+}
+
+// the queries by position: flattened fields can't be, so the filter and the
+// page are objects of their own there, as in the `TaskDB` methods
+#[derive(Deserialize)]
+struct TaskQueryArgs(
+    TaskFilter,
+    #[serde(default = "Sort::tasks_default")] Sort,
+    #[serde(default)] Page,
+);
+
+impl From<TaskQueryArgs> for TaskQuery {
+    fn from(TaskQueryArgs(filter, sort, page): TaskQueryArgs) -> Self {
+        TaskQuery { filter, sort, page }
+    }
+}
+
+#[derive(Deserialize)]
+struct FinishedQueryArgs(
+    u32,
+    u32,
+    #[serde(default = "Sort::history_default")] Sort,
+    #[serde(default)] Page,
+);
+
+impl From<FinishedQueryArgs> for FinishedQuery {
+    fn from(FinishedQueryArgs(start_ts, end_ts, sort, page): FinishedQueryArgs) -> Self {
+        FinishedQuery {
+            start_ts,
+            end_ts,
+            sort,
+            page,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Desc {
+    id: IDType,
+    desc: String,
+}
+
+#[derive(Deserialize)]
+struct Priority {
+    task_id: IDType,
+    pri: i32,
+}
+
+#[derive(Deserialize)]
+struct Belongings {
+    task_id: IDType,
+    new_task_id: IDType,
+}
+
+#[derive(Deserialize)]
+struct Rank {
+    id: IDType,
+    subtask_rank: i32,
+}
+
+#[derive(Deserialize)]
+struct Ids {
+    ids: Vec<IDType>,
+}
+
+#[derive(Deserialize)]
+struct Priorities {
+    priorities: Vec<(IDType, i32)>,
+}
+
+#[derive(Deserialize)]
+struct PutTask {
+    task: Task,
+}
+
+#[derive(Deserialize)]
+struct PutSubTask {
+    subtask: SubTask,
+}
+
+#[derive(Deserialize)]
+struct PutHistory {
+    history: History,
+}
+
+// params given by name or by position; none at all are an empty object
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    let params = match params {
+        Value::Null => json!({}),
+        params => params,
+    };
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e))
+}
+
+// params of a query, by name as `Named` or by position as `Positional`
+fn query_params<Named, Positional>(p: Value) -> Result<Named, RpcError>
+where
+    Named: DeserializeOwned,
+    Positional: DeserializeOwned + Into<Named>,
+{
+    match p {
+        Value::Array(_) => params::<Positional>(p).map(Into::into),
+        p => params(p),
+    }
+}
+
+fn result<T: Serialize>(value: TodoResult<T>) -> Result<Value, RpcError> {
+    let value = value?;
+    serde_json::to_value(value).map_err(|e| RpcError::new(-32000, e))
+}
+
+fn call(db: &mut dyn TaskDB, method: &str, p: Value) -> Result<Value, RpcError> {
+    match method {
+        "add_task" => {
+            let new_task: NewTask = params(p)?;
+            result(db.add_task(new_task))
+        }
+        "add_subtask" => {
+            let NewSubTaskParams { id, what, link } = params(p)?;
+            result(db.add_subtask(id, what, link))
+        }
+        "get_task" => result(db.get_task(params::<Id>(p)?.id)),
+        "get_tasks" => result(db.get_tasks(params::<Pattern>(p)?.pattern)),
+        "get_subtasks" => result(db.get_subtasks(params::<Id>(p)?.id)),
+        "get_finished" => result(db.get_finished(params::<LastN>(p)?.last_n)),
+        "get_finished_within" => {
+            let Within { start_ts, end_ts } = params(p)?;
+            result(db.get_finished_within(start_ts, end_ts))
+        }
+        "query_tasks" => {
+            let TaskQuery { filter, sort, page } = query_params::<_, TaskQueryArgs>(p)?;
+            result(db.query_tasks(&filter, &sort, page))
+        }
+        "query_finished" => {
+            let FinishedQuery {
+                start_ts,
+                end_ts,
+                sort,
+                page,
+            } = query_params::<_, FinishedQueryArgs>(p)?;
+            result(db.query_finished(start_ts, end_ts, &sort, page))
+        }
+        "update_task_desc" => {
+            let Desc { id, desc } = params(p)?;
+            result(db.update_task_desc(id, desc))
+        }
+        "update_task_priority" => {
+            let Priority { task_id, pri } = params(p)?;
+            result(db.update_task_priority(task_id, pri))
+        }
+        "remove_task" => result(db.remove_task(params::<Id>(p)?.id)),
+        "update_subtask_belongings" => {
+            let Belongings {
+                task_id,
+                new_task_id,
+            } = params(p)?;
+            result(db.update_subtask_belongings(task_id, new_task_id))
+        }
+        "remove_subtask" => {
+            let Rank { id, subtask_rank } = params(p)?;
+            result(db.remove_subtask(id, subtask_rank))
+        }
+        "finish_subtask" => {
+            let Rank { id, subtask_rank } = params(p)?;
+            result(db.finish_subtask(id, subtask_rank))
+        }
+        "finish_task" => result(db.finish_task(params::<Id>(p)?.id)),
+        "get_all_subtasks" => result(db.get_all_subtasks()),
+        "put_task" => result(db.put_task(&params::<PutTask>(p)?.task)),
+        "put_subtask" => result(db.put_subtask(&params::<PutSubTask>(p)?.subtask)),
+        "put_history" => result(db.put_history(&params::<PutHistory>(p)?.history)),
+        "schema_version" => result(db.schema_version()),
+        "check_tasks" => result(db.check_tasks(&params::<Ids>(p)?.ids)),
+        "finish_tasks" => result(db.finish_tasks(&params::<Ids>(p)?.ids)),
+        "remove_tasks" => result(db.remove_tasks(&params::<Ids>(p)?.ids)),
+        "update_priorities" => result(db.update_priorities(&params::<Priorities>(p)?.priorities)),
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("no method {}", method),
+        )),
+    }
+}
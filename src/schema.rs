@@ -1,11 +1,22 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    field_times (uid, field) {
+        uid -> Text,
+        field -> Text,
+        changed_at -> Integer,
+    }
+}
+
 diesel::table! {
     histories (id) {
         id -> Integer,
         what -> Text,
         link -> Nullable<Text>,
         finish_timestamp -> Integer,
+        uid -> Text,
+        created_at -> Integer,
+        priority -> Nullable<Integer>,
     }
 }
 
@@ -16,6 +27,8 @@ diesel::table! {
         link -> Nullable<Text>,
         subtask_rank -> Integer,
         task_id -> Integer,
+        uid -> Text,
+        done -> Bool,
     }
 }
 
@@ -25,9 +38,13 @@ diesel::table! {
         what -> Text,
         link -> Nullable<Text>,
         priority -> Integer,
+        uid -> Text,
+        created_at -> Integer,
+        due -> Nullable<Text>,
+        updated_at -> Integer,
     }
 }
 
 diesel::joinable!(subtasks -> tasks (task_id));
 
-diesel::allow_tables_to_appear_in_same_query!(histories, subtasks, tasks,);
+diesel::allow_tables_to_appear_in_same_query!(field_times, histories, subtasks, tasks,);
@@ -0,0 +1,404 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{Cursor, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::dates::{Tense, When};
+use crate::error::{storage, TodoError, TodoResult};
+use crate::models::{due_field, new_uid, NewTask, Task};
+use crate::query::{Page, Sort, TaskFilter};
+use crate::taskdb::{check_desc, now, TaskDB};
+
+type IDType = i32;
+
+/// The token clients send as `Authorization: Bearer <token>`. It is read from
+/// `path`, or created there on first use, readable by the owner only.
+pub fn load_token(path: &Path) -> Result<String> {
+    if path.exists() {
+        let token = fs::read_to_string(path)
+            .with_context(|| format!("fail to read token file {}", path.display()))?;
+        return Ok(token.trim().to_owned());
+    }
+    let token = new_uid();
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{}", token))
+        .with_context(|| format!("fail to write token file {}", path.display()))?;
+    Ok(token)
+}
+
+/// Answer REST requests on `server` until it is closed:
+///
+/// - `GET /tasks?pattern=`, `POST /tasks` with a `NewTask`
+/// - `GET`, `PATCH` (`what`, `link`, `priority`), `DELETE /tasks/<id>`
+/// - `POST /tasks/<id>/finish`, answered with the `History` record
+/// - `GET`, `POST /tasks/<id>/subtasks`, `DELETE /tasks/<id>/subtasks/<rank>`
+/// - `POST /tasks/<id>/subtasks/<rank>/finish`, answered with the subtask
+/// - `GET /subtasks`, `GET /history?n=` or `?start=&end=` (unix timestamps)
+///
+/// Every reply with a body has an `ETag`; a write to a task with an `If-Match`
+/// that is no longer its tag fails with 412, and a `GET` with a matching
+/// `If-None-Match` gets 304. The store is opened for each request, so changes
+/// made meanwhile by other invocations are seen and file stores aren't kept locked.
+pub fn serve(server: &Server, token: &str, open: &dyn Fn() -> TodoResult<Box<dyn TaskDB>>) {
+    let authorization = format!("Bearer {}", token);
+    for mut request in server.incoming_requests() {
+        let reply = if header(&request, "Authorization") != Some(authorization.as_str()) {
+            Reply::message(401, "a bearer token is required")
+        } else {
+            match handle(&mut request, open) {
+                Ok(reply) => reply.not_modified_for(&request),
+                Err(e) => Reply::error(&e),
+            }
+        };
+        // the client may have gone away, there is no one left to tell
+        let _ = request.respond(reply.into_response());
+    }
+}
+
+fn handle(
+    request: &mut Request,
+    open: &dyn Fn() -> TodoResult<Box<dyn TaskDB>>,
+) -> TodoResult<Reply> {
+    let url = request.url().to_owned();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let method = request.method().clone();
+    let mut db = open()?;
+    match (&method, segments.as_slice()) {
+        (Method::Get, ["tasks"]) => {
+            let sort = param(query, "sort").map_or(Ok(Sort::default()), |s| s.parse())?;
+            let filter = TaskFilter {
+                pattern: param(query, "pattern"),
+                updated_before: param(query, "updated_before")
+                    .map(|ts| number(&ts))
+                    .transpose()?,
+            };
+            let tasks = db.query_tasks(&filter, &sort, page(query)?)?;
+            Reply::json(200, &tasks)
+        }
+        (Method::Post, ["tasks"]) => {
+            let mut new_task: NewTask = body(request)?;
+            new_task.due = new_task.due.as_deref().map(due).transpose()?;
+            let id = db.add_task(new_task)?;
+            Reply::json(201, &task(db.as_ref(), id)?)
+        }
+        (Method::Get, ["tasks", id]) => Reply::json(200, &task(db.as_ref(), number(id)?)?),
+        (Method::Patch, ["tasks", id]) => {
+            let id = number(id)?;
+            let patch: TaskPatch = body(request)?;
+            if let Some(what) = &patch.what {
+                check_desc(what)?;
+            }
+            let new_due = match &patch.due {
+                Some(new_due) => Some(new_due.as_deref().map(due).transpose()?),
+                None => None,
+            };
+            guarded_write(db.as_mut(), request, id, &mut |db, mut task| {
+                if let Some(what) = &patch.what {
+                    task.what = what.clone();
+                }
+                if let Some(link) = &patch.link {
+                    task.link = link.clone();
+                }
+                if let Some(priority) = patch.priority {
+                    task.priority = priority;
+                }
+                if let Some(new_due) = &new_due {
+                    task.due = new_due.clone();
+                }
+                task.updated_at = now();
+                db.put_task(&task)?;
+                Reply::json(200, &task)
+            })
+        }
+        (Method::Delete, ["tasks", id]) => {
+            guarded_write(db.as_mut(), request, number(id)?, &mut |db, task| {
+                db.remove_task(task.id)?;
+                Ok(Reply::message(204, ""))
+            })
+        }
+        (Method::Post, ["tasks", id, "finish"]) => {
+            guarded_write(db.as_mut(), request, number(id)?, &mut |db, task| {
+                Reply::json(200, &db.finish_task(task.id)?)
+            })
+        }
+        (Method::Get, ["tasks", id, "subtasks"]) => {
+            Reply::json(200, &db.get_subtasks(number(id)?)?)
+        }
+        (Method::Post, ["tasks", id, "subtasks"]) => {
+            let task_id = number(id)?;
+            let subtask: NewSubTaskBody = body(request)?;
+            db.add_subtask(task_id, subtask.what, subtask.link)?;
+            let created = db.get_subtasks(task_id)?.into_iter().max_by_key(|st| st.id);
+            Reply::json(201, &created)
+        }
+        (Method::Delete, ["tasks", id, "subtasks", rank]) => {
+            let rank = number(rank)?;
+            if db.remove_subtask(number(id)?, rank)? == 0 {
+                return Err(TodoError::NotFound {
+                    kind: "subtask",
+                    id: rank,
+                });
+            }
+            Ok(Reply::message(204, ""))
+        }
+        (Method::Post, ["tasks", id, "subtasks", rank, "finish"]) => {
+            let (task_id, rank) = (number(id)?, number(rank)?);
+            if db.finish_subtask(task_id, rank)? == 0 {
+                return Err(TodoError::NotFound {
+                    kind: "subtask",
+                    id: rank,
+                });
+            }
+            let finished = db
+                .get_subtasks(task_id)?
+                .into_iter()
+                .find(|st| st.subtask_rank == rank);
+            Reply::json(200, &finished)
+        }
+        (Method::Get, ["subtasks"]) => Reply::json(200, &db.get_all_subtasks()?),
+        (Method::Get, ["history"]) => {
+            let sort = param(query, "sort").map_or(Ok(Sort::history_default()), |s| s.parse())?;
+            let mut page = page(query)?;
+            let (start, end) = match (param(query, "start"), param(query, "end")) {
+                (None, None) => {
+                    // the last n, 10 unless a page is asked for
+                    if let Some(n) = param(query, "n") {
+                        page.limit = Some(number(&n)?);
+                    } else if page == Page::default() {
+                        page.limit = Some(10);
+                    }
+                    (0, i32::MAX as u32)
+                }
+                (start, end) => {
+                    let start = start.map_or(Ok(0), |ts| number(&ts))?;
+                    // the end is left out, now + 1 takes in what was just finished
+                    let end = end.map_or(Ok(now() as u32 + 1), |ts| number(&ts))?;
+                    (start, end)
+                }
+            };
+            let histories = db.query_finished(start, end, &sort, page)?;
+            Reply::json(200, &histories)
+        }
+        (_, ["tasks" | "subtasks" | "history", ..]) => Ok(Reply::message(
+            405,
+            &format!("{} is not allowed here", method),
+        )),
+        _ => Ok(Reply::message(404, &format!("no resource at {}", path))),
+    }
+}
+
+// the fields of a task a PATCH may change; `"link": null` removes the link
+// and `"due": null` the due date
+#[derive(Deserialize)]
+struct TaskPatch {
+    what: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    link: Option<Option<String>>,
+    priority: Option<i32>,
+    #[serde(default, deserialize_with = "present")]
+    due: Option<Option<String>>,
+}
+
+// a due date as it is kept, from any day `--due` takes
+fn due(input: &str) -> TodoResult<String> {
+    When::parse_date(input, chrono::Local::now().naive_local(), Tense::Future).map(due_field)
+}
+
+// tells a field set to null apart from a missing one
+fn present<'de, D: Deserializer<'de>, T: Deserialize<'de>>(d: D) -> Result<Option<T>, D::Error> {
+    T::deserialize(d).map(Some)
+}
+
+#[derive(Deserialize)]
+struct NewSubTaskBody {
+    what: String,
+    link: Option<String>,
+}
+
+struct Reply {
+    status: u16,
+    body: String,
+    etag: Option<String>,
+}
+
+impl Reply {
+    fn json<T: Serialize>(status: u16, value: &T) -> TodoResult<Reply> {
+        let body = serde_json::to_string(value).map_err(storage("fail to write json"))?;
+        Ok(Reply {
+            status,
+            etag: Some(etag(&body)),
+            body,
+        })
+    }
+
+    fn message(status: u16, message: &str) -> Reply {
+        let body = if message.is_empty() {
+            String::new()
+        } else {
+            serde_json::json!({ "error": message }).to_string()
+        };
+        Reply {
+            status,
+            body,
+            etag: None,
+        }
+    }
+
+    fn error(e: &TodoError) -> Reply {
+        let status = match e {
+            TodoError::NotFound { .. } | TodoError::Missing { .. } => 404,
+            TodoError::InvalidInput(_) => 400,
+            _ => 500,
+        };
+        Reply::message(status, &e.to_string())
+    }
+
+    fn not_modified_for(self, request: &Request) -> Reply {
+        match (&self.etag, header(request, "If-None-Match")) {
+            (Some(etag), Some(tag)) if request.method() == &Method::Get && etag == tag => Reply {
+                status: 304,
+                body: String::new(),
+                etag: self.etag,
+            },
+            _ => self,
+        }
+    }
+
+    fn into_response(self) -> Response<Cursor<Vec<u8>>> {
+        let mut response = Response::from_string(self.body).with_status_code(self.status);
+        let mut headers = vec![("Content-Type", "application/json".to_owned())];
+        if self.status == 401 {
+            headers.push(("WWW-Authenticate", "Bearer".to_owned()));
+        }
+        if let Some(etag) = self.etag {
+            headers.push(("ETag", etag));
+        }
+        for (field, value) in headers {
+            if let Ok(header) = Header::from_bytes(field.as_bytes(), value.as_bytes()) {
+                response.add_header(header);
+            }
+        }
+        response
+    }
+}
+
+// the same json always gets the same tag
+fn etag(body: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+// the reply to a write whose If-Match is not the current tag of the task
+fn stale(request: &Request, task: &Task) -> TodoResult<Option<Reply>> {
+    let current = Reply::json(200, task)?.etag;
+    Ok(match header(request, "If-Match") {
+        Some(tag) if tag != "*" && Some(tag) != current.as_deref() => Some(Reply {
+            etag: current,
+            ..Reply::message(412, "the task was changed meanwhile")
+        }),
+        _ => None,
+    })
+}
+
+// `write` the task `id` unless the request's If-Match is stale, checking the
+// tag and writing in one transaction so no other write comes in between
+fn guarded_write(
+    db: &mut dyn TaskDB,
+    request: &Request,
+    id: IDType,
+    write: &mut dyn FnMut(&mut dyn TaskDB, Task) -> TodoResult<Reply>,
+) -> TodoResult<Reply> {
+    let mut reply = None;
+    db.transaction(&mut |db| {
+        let task = task(db, id)?;
+        reply = Some(match stale(request, &task)? {
+            Some(stale) => stale,
+            None => write(db, task)?,
+        });
+        Ok(())
+    })?;
+    Ok(reply.expect("a committed transaction has a reply"))
+}
+
+fn task(db: &dyn TaskDB, id: IDType) -> TodoResult<Task> {
+    db.get_task(id)?
+        .ok_or(TodoError::NotFound { kind: "task", id })
+}
+
+fn header<'a>(request: &'a Request, field: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(field))
+        .map(|h| h.value.as_str())
+}
+
+fn body<T: for<'de> Deserialize<'de>>(request: &mut Request) -> TodoResult<T> {
+    let mut text = String::new();
+    request
+        .as_reader()
+        .read_to_string(&mut text)
+        .map_err(|e| TodoError::InvalidInput(format!("fail to read the body: {}", e)))?;
+    serde_json::from_str(&text).map_err(|e| TodoError::InvalidInput(format!("bad json: {}", e)))
+}
+
+fn number<T: FromStr>(text: &str) -> TodoResult<T> {
+    text.parse()
+        .map_err(|_| TodoError::InvalidInput(format!("{} is not a number", text)))
+}
+
+// the `limit` and `offset` of a query string
+fn page(query: &str) -> TodoResult<Page> {
+    Ok(Page {
+        limit: param(query, "limit").map(|n| number(&n)).transpose()?,
+        offset: param(query, "offset").map_or(Ok(0), |n| number(&n))?,
+    })
+}
+
+// the decoded value of `name` in a query string
+fn param(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| decode(value))
+}
+
+fn decode(text: &str) -> String {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        rest = tail;
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => match std::str::from_utf8(&rest[..rest.len().min(2)])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                Some(byte) if rest.len() >= 2 => {
+                    bytes.push(byte);
+                    rest = &rest[2..];
+                }
+                _ => bytes.push(b),
+            },
+            _ => bytes.push(b),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
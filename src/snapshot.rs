@@ -0,0 +1,221 @@
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::io::Read;
+use std::os::raw::c_int;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use anyhow::{bail, Context, Result};
+use chrono::{Local, NaiveDateTime};
+use libsqlite3_sys as ffi;
+
+const SNAPSHOT_PREFIX: &str = "todo-";
+const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+const SNAPSHOT_TIME_FORMAT: &str = "%Y%m%d-%H%M%S";
+// pages copied per backup step, the source is unlocked in between
+const PAGES_PER_STEP: c_int = 64;
+
+/// Rotating copies of the store, named
+/// `todo-<yyyymmdd>-<hhmmss><microseconds>-<reason>.<ext>` where the extension
+/// is the one of the store file, `db` if it has none.
+pub struct Snapshots {
+    dir: PathBuf,
+    keep: usize,
+    // a snapshot that pruning leaves alone
+    spared: Option<String>,
+}
+
+impl Snapshots {
+    pub fn new(dir: PathBuf, keep: usize) -> Self {
+        Snapshots {
+            dir,
+            keep,
+            spared: None,
+        }
+    }
+
+    /// Never prune the snapshot `name`, e.g. the one about to be restored.
+    pub fn spare(&mut self, name: &str) {
+        self.spared = Some(name.to_owned());
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Snapshot names, oldest first.
+    pub fn list(&self) -> Result<Vec<String>> {
+        if !self.dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut names = vec![];
+        for entry in fs::read_dir(&self.dir)
+            .with_context(|| format!("fail to read backups dir {}", self.dir.display()))?
+        {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if snapshot_time(&name).is_some() {
+                names.push(name);
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Copy the store into a new snapshot and drop the ones beyond `keep`.
+    pub fn take(&self, db_path: &Path, reason: &str) -> Result<PathBuf> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("fail to create backups dir {}", self.dir.display()))?;
+        let extension = db_path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("db");
+        // snapshots taken within the same microsecond would overwrite each other
+        let path = loop {
+            let now = Local::now();
+            let name = format!(
+                "{}{}{}-{}.{}",
+                SNAPSHOT_PREFIX,
+                now.format(SNAPSHOT_TIME_FORMAT),
+                now.format("%6f"),
+                reason,
+                extension
+            );
+            let path = self.dir.join(name);
+            if !path.exists() {
+                break path;
+            }
+        };
+        copy_store(db_path, &path)?;
+        self.prune()?;
+        Ok(path)
+    }
+
+    /// Take a snapshot unless one was already taken today.
+    pub fn take_daily(&self, db_path: &Path) -> Result<Option<PathBuf>> {
+        let today = Local::today().naive_local();
+        let taken_today = self
+            .list()?
+            .iter()
+            .filter_map(|name| snapshot_time(name))
+            .any(|t| t.date() == today);
+        if taken_today {
+            Ok(None)
+        } else {
+            self.take(db_path, "daily").map(Some)
+        }
+    }
+
+    /// Overwrite the store with the content of a snapshot.
+    pub fn restore(&self, name: &str, db_path: &Path) -> Result<()> {
+        if !self.list()?.iter().any(|n| n == name) {
+            bail!("no backup named {} in {}", name, self.dir.display());
+        }
+        copy_store(&self.dir.join(name), db_path)
+    }
+
+    fn prune(&self) -> Result<()> {
+        let names = self.list()?;
+        let excess = names.len().saturating_sub(self.keep);
+        let prunable = names
+            .into_iter()
+            .filter(|name| self.spared.as_ref() != Some(name));
+        for name in prunable.take(excess) {
+            fs::remove_file(self.dir.join(&name))
+                .with_context(|| format!("fail to remove old backup {}", name))?;
+        }
+        Ok(())
+    }
+}
+
+fn snapshot_time(name: &str) -> Option<NaiveDateTime> {
+    let (stem, _extension) = name.strip_prefix(SNAPSHOT_PREFIX)?.rsplit_once('.')?;
+    // yyyymmdd-hhmmss is 15 chars, the microseconds after it are left out
+    let time = stem.get(..15)?;
+    NaiveDateTime::parse_from_str(time, SNAPSHOT_TIME_FORMAT).ok()
+}
+
+// sqlite files go through the online backup api, plain file stores are
+// locked while open and copied as they are
+fn copy_store(src: &Path, dest: &Path) -> Result<()> {
+    if is_sqlite(src)? {
+        online_backup(src, dest)
+    } else {
+        fs::copy(src, dest)
+            .with_context(|| format!("fail to copy {} to {}", src.display(), dest.display()))?;
+        Ok(())
+    }
+}
+
+fn is_sqlite(path: &Path) -> Result<bool> {
+    let mut header = [0; SQLITE_HEADER.len()];
+    let mut file =
+        fs::File::open(path).with_context(|| format!("fail to open {}", path.display()))?;
+    Ok(file.read_exact(&mut header).is_ok() && header == SQLITE_HEADER)
+}
+
+// an open sqlite handle, closed on drop
+struct Handle(*mut ffi::sqlite3);
+
+impl Handle {
+    fn open(path: &Path, flags: c_int) -> Result<Self> {
+        let c_path = CString::new(path.to_string_lossy().as_bytes())?;
+        let mut db = ptr::null_mut();
+        let rc = unsafe { ffi::sqlite3_open_v2(c_path.as_ptr(), &mut db, flags, ptr::null()) };
+        let handle = Handle(db);
+        if rc != ffi::SQLITE_OK {
+            bail!("fail to open {}: {}", path.display(), handle.errmsg());
+        }
+        Ok(handle)
+    }
+
+    fn errmsg(&self) -> String {
+        if self.0.is_null() {
+            return "out of memory".to_owned();
+        }
+        unsafe { CStr::from_ptr(ffi::sqlite3_errmsg(self.0)) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        unsafe { ffi::sqlite3_close(self.0) };
+    }
+}
+
+/// Copy a sqlite database with sqlite's online backup api, which is safe
+/// to use while another connection has the source open.
+pub fn online_backup(src: &Path, dest: &Path) -> Result<()> {
+    let src_db = Handle::open(src, ffi::SQLITE_OPEN_READONLY)?;
+    let dest_db = Handle::open(dest, ffi::SQLITE_OPEN_READWRITE | ffi::SQLITE_OPEN_CREATE)?;
+    let main = CString::new("main")?;
+    let backup =
+        unsafe { ffi::sqlite3_backup_init(dest_db.0, main.as_ptr(), src_db.0, main.as_ptr()) };
+    if backup.is_null() {
+        bail!(
+            "fail to back up to {}: {}",
+            dest.display(),
+            dest_db.errmsg()
+        );
+    }
+    let rc = loop {
+        match unsafe { ffi::sqlite3_backup_step(backup, PAGES_PER_STEP) } {
+            ffi::SQLITE_OK => continue,
+            ffi::SQLITE_BUSY | ffi::SQLITE_LOCKED => unsafe {
+                ffi::sqlite3_sleep(10);
+            },
+            rc => break rc,
+        }
+    };
+    unsafe { ffi::sqlite3_backup_finish(backup) };
+    if rc != ffi::SQLITE_DONE {
+        bail!(
+            "fail to back up {} to {}: {}",
+            src.display(),
+            dest.display(),
+            dest_db.errmsg()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,229 @@
+use std::collections::{BTreeSet, HashMap};
+use std::str::FromStr;
+
+use chrono::{Datelike, Duration, NaiveDate, TimeZone};
+
+use crate::dates::local_timestamp;
+use crate::display::Theme;
+use crate::error::{TodoError, TodoResult};
+use crate::models::{tags, History};
+
+/// How finished tasks are counted together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Day,
+    /// Monday to Sunday.
+    Week,
+    Month,
+}
+
+impl Period {
+    /// The first day of the period `date` is in.
+    pub fn start(self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Day => date,
+            Period::Week => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+            Period::Month => NaiveDate::from_ymd(date.year(), date.month(), 1),
+        }
+    }
+
+    /// The first day of the period after the one starting on `start`, None
+    /// past the last date chrono has.
+    pub fn next(self, start: NaiveDate) -> Option<NaiveDate> {
+        match self {
+            Period::Day => start.succ_opt(),
+            Period::Week => start.checked_add_signed(Duration::days(7)),
+            Period::Month if start.month() == 12 => NaiveDate::from_ymd_opt(start.year() + 1, 1, 1),
+            Period::Month => NaiveDate::from_ymd_opt(start.year(), start.month() + 1, 1),
+        }
+    }
+
+    /// The first day of the period before the one starting on `start`, None
+    /// before the first date chrono has.
+    pub fn previous(self, start: NaiveDate) -> Option<NaiveDate> {
+        Some(self.start(start.checked_sub_signed(Duration::days(1))?))
+    }
+
+    /// How the period starting on `start` is shown: 2026-10-19, 2026-W43 or 2026-10.
+    pub fn label(self, start: NaiveDate) -> String {
+        match self {
+            Period::Day => start.format("%Y-%m-%d").to_string(),
+            Period::Week => start.format("%G-W%V").to_string(),
+            Period::Month => start.format("%Y-%m").to_string(),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Period::Day => "day",
+            Period::Week => "week",
+            Period::Month => "month",
+        }
+    }
+}
+
+impl FromStr for Period {
+    type Err = TodoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "day" => Ok(Period::Day),
+            "week" => Ok(Period::Week),
+            "month" => Ok(Period::Month),
+            _ => Err(TodoError::InvalidInput(format!(
+                "unknown period `{}`, expect day, week or month",
+                s
+            ))),
+        }
+    }
+}
+
+/// The day a unix time falls on in the timezone `tz`.
+pub fn local_day<Tz: TimeZone>(timestamp: i32, tz: &Tz) -> NaiveDate {
+    tz.timestamp(timestamp as i64, 0).naive_local().date()
+}
+
+/// The unix time `date` starts at in the timezone `tz`.
+pub fn day_start<Tz: TimeZone>(date: NaiveDate, tz: &Tz) -> i64 {
+    local_timestamp(date.and_hms(0, 0, 0), tz)
+}
+
+/// What was finished over the last few periods.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub period: Period,
+    /// The first day of each period and how many tasks were finished in it,
+    /// oldest first, the one holding today last.
+    pub counts: Vec<(NaiveDate, usize)>,
+    /// Days in a row with a finished task, up to today, or up to yesterday
+    /// while nothing is finished today yet.
+    pub current_streak: usize,
+    pub longest_streak: usize,
+    /// In seconds, over the tasks whose creation time is known.
+    pub average_lead_time: Option<i32>,
+    /// Per priority band, see `Theme::band`.
+    pub bands: [usize; 4],
+    /// Finished before the priority was kept in the history.
+    pub unknown_priority: usize,
+    /// Per `#tag`, the most used first.
+    pub tags: Vec<(String, usize)>,
+}
+
+impl Stats {
+    /// The stats of the `periods` periods up to the one holding `today`, days
+    /// being those of the timezone `tz`. The streaks count the whole history.
+    /// Fails with `InvalidInput` when the periods go past the dates chrono has.
+    pub fn new<Tz: TimeZone>(
+        histories: &[History],
+        tz: &Tz,
+        today: NaiveDate,
+        period: Period,
+        periods: usize,
+    ) -> TodoResult<Self> {
+        let out_of_range = || {
+            TodoError::InvalidInput(format!(
+                "{} {}s up to {} go past the dates there are",
+                periods,
+                period.name(),
+                today
+            ))
+        };
+        let mut starts = vec![period.start(today)];
+        while starts.len() < periods.max(1) {
+            let previous = period.previous(*starts.last().unwrap());
+            starts.push(previous.ok_or_else(out_of_range)?);
+        }
+        starts.reverse();
+        let end = period
+            .next(*starts.last().unwrap())
+            .ok_or_else(out_of_range)?;
+        let first = starts[0];
+
+        let days: Vec<(NaiveDate, &History)> = histories
+            .iter()
+            .map(|h| (local_day(h.finish_timestamp, tz), h))
+            .collect();
+        let shown: Vec<&(NaiveDate, &History)> = days
+            .iter()
+            .filter(|(day, _)| first <= *day && *day < end)
+            .collect();
+
+        let ends = starts.iter().skip(1).chain(std::iter::once(&end));
+        let counts = starts
+            .iter()
+            .zip(ends)
+            .map(|(start, end)| {
+                let count = shown
+                    .iter()
+                    .filter(|(day, _)| start <= day && day < end)
+                    .count();
+                (*start, count)
+            })
+            .collect();
+
+        let leads: Vec<i64> = shown
+            .iter()
+            .filter_map(|(_, h)| h.lead_time())
+            .map(i64::from)
+            .collect();
+        let average_lead_time =
+            (!leads.is_empty()).then(|| (leads.iter().sum::<i64>() / leads.len() as i64) as i32);
+
+        let mut bands = [0; 4];
+        let mut unknown_priority = 0;
+        let mut tag_counts: HashMap<String, usize> = HashMap::new();
+        for (_, h) in shown.iter() {
+            match h.priority {
+                Some(p) => bands[Theme::band(p)] += 1,
+                None => unknown_priority += 1,
+            }
+            for tag in tags(&h.what) {
+                *tag_counts.entry(tag).or_default() += 1;
+            }
+        }
+        let mut tags: Vec<(String, usize)> = tag_counts.into_iter().collect();
+        tags.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        let active: BTreeSet<NaiveDate> = days.iter().map(|(day, _)| *day).collect();
+        let (current_streak, longest_streak) = streaks(&active, today);
+        Ok(Stats {
+            period,
+            counts,
+            current_streak,
+            longest_streak,
+            average_lead_time,
+            bands,
+            unknown_priority,
+            tags,
+        })
+    }
+
+    /// How many tasks were finished in the periods shown.
+    pub fn total(&self) -> usize {
+        self.counts.iter().map(|(_, count)| count).sum()
+    }
+}
+
+// the current and the longest run of days in a row
+fn streaks(days: &BTreeSet<NaiveDate>, today: NaiveDate) -> (usize, usize) {
+    let run_back = |from: NaiveDate| {
+        let mut day = from;
+        while days.contains(&day) {
+            day -= Duration::days(1);
+        }
+        (from - day).num_days() as usize
+    };
+    let yesterday = today - Duration::days(1);
+    let current = if days.contains(&today) {
+        run_back(today)
+    } else {
+        run_back(yesterday)
+    };
+    let longest = days
+        .iter()
+        .filter(|day| !days.contains(&(**day + Duration::days(1))))
+        .map(|last| run_back(*last))
+        .max()
+        .unwrap_or(0);
+    (current, longest)
+}
@@ -0,0 +1,120 @@
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::error::{TodoError, TodoResult};
+use crate::filedb::{FileFormat, TaskFile};
+use crate::taskdb::{self, TaskDB, TaskSqlite};
+
+/// Where the tasks live, parsed from `TODO_DB`: `sqlite:///path/todo.db`,
+/// `json:///path/todo.json` or `toml:///path/todo.toml`.
+/// A value without a scheme is the path of a sqlite file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Store {
+    Sqlite(String),
+    File(FileFormat, String),
+}
+
+impl Store {
+    pub fn path(&self) -> &Path {
+        match self {
+            Store::Sqlite(path) | Store::File(_, path) => Path::new(path),
+        }
+    }
+
+    pub fn open(&self) -> TodoResult<Box<dyn TaskDB>> {
+        match self {
+            Store::Sqlite(path) => taskdb::open(path),
+            Store::File(format, path) => Ok(Box::new(TaskFile::open(Path::new(path), *format)?)),
+        }
+    }
+
+    /// Open the store as it is, to read another copy of the tasks: a missing
+    /// file isn't created and a sqlite file isn't migrated.
+    pub fn open_existing(&self) -> TodoResult<Box<dyn TaskDB>> {
+        let path = self.path();
+        fs::metadata(path).map_err(TodoError::io(path))?;
+        match self {
+            Store::Sqlite(path) => Ok(Box::new(TaskSqlite::connect(path)?)),
+            Store::File(..) => self.open(),
+        }
+    }
+}
+
+impl FromStr for Store {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, path) = match s.split_once("://") {
+            Some(parts) => parts,
+            None => return Ok(Store::Sqlite(s.to_owned())),
+        };
+        if path.is_empty() {
+            bail!("no path in store url {}", s);
+        }
+        let path = path.to_owned();
+        match scheme {
+            "sqlite" => Ok(Store::Sqlite(path)),
+            "json" => Ok(Store::File(FileFormat::Json, path)),
+            "toml" => Ok(Store::File(FileFormat::Toml, path)),
+            _ => Err(anyhow!(
+                "unknown store scheme {}, expected sqlite, json or toml",
+                scheme
+            )),
+        }
+    }
+}
+
+impl Display for Store {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Store::Sqlite(path) => write!(f, "sqlite://{}", path),
+            Store::File(FileFormat::Json, path) => write!(f, "json://{}", path),
+            Store::File(FileFormat::Toml, path) => write!(f, "toml://{}", path),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct MigrateSummary {
+    pub tasks: usize,
+    pub subtasks: usize,
+    pub histories: usize,
+}
+
+impl Display for MigrateSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} task(s), {} subtask(s), {} history record(s)",
+            self.tasks, self.subtasks, self.histories
+        )
+    }
+}
+
+/// Copy every record into an empty store, keeping ids and uids.
+pub fn migrate(from: &dyn TaskDB, to: &mut dyn TaskDB) -> TodoResult<MigrateSummary> {
+    if !to.get_tasks(None)?.is_empty() || !to.get_finished(1)?.is_empty() {
+        return Err(TodoError::InvalidInput(
+            "the target store is not empty".to_owned(),
+        ));
+    }
+    let tasks = from.get_tasks(None)?;
+    let subtasks = from.get_all_subtasks()?;
+    let histories = from.get_finished_within(0, i32::MAX as u32)?;
+    let field_times = from.get_field_times()?;
+    to.transaction(&mut |db| {
+        tasks.iter().try_for_each(|t| db.put_task(t))?;
+        subtasks.iter().try_for_each(|st| db.put_subtask(st))?;
+        histories.iter().try_for_each(|h| db.put_history(h))?;
+        field_times.iter().try_for_each(|ft| db.put_field_time(ft))
+    })?;
+    Ok(MigrateSummary {
+        tasks: tasks.len(),
+        subtasks: subtasks.len(),
+        histories: histories.len(),
+    })
+}
@@ -0,0 +1,658 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Display;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+
+use crate::error::TodoError;
+use crate::models::{History, SubTask, Task};
+use crate::taskdb::TaskDB;
+
+// uid -> the other fields of a record, in the order of `Table::columns`
+type Records = BTreeMap<String, Vec<String>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Table {
+    Tasks,
+    Subtasks,
+    Histories,
+}
+
+impl Table {
+    const ALL: [Table; 3] = [Table::Tasks, Table::Subtasks, Table::Histories];
+
+    fn file_name(self) -> &'static str {
+        match self {
+            Table::Tasks => "tasks.txt",
+            Table::Subtasks => "subtasks.txt",
+            Table::Histories => "histories.txt",
+        }
+    }
+
+    fn columns(self) -> &'static [&'static str] {
+        match self {
+            Table::Tasks => &[
+                "priority",
+                "what",
+                "link",
+                "created_at",
+                "due",
+                "updated_at",
+            ],
+            Table::Subtasks => &["task_uid", "subtask_rank", "what", "link", "done"],
+            Table::Histories => &["finish_timestamp", "what", "link", "created_at", "priority"],
+        }
+    }
+
+    // how many of the last columns were added after the first sync format
+    fn added_columns(self) -> usize {
+        match self {
+            Table::Tasks => 3,
+            Table::Subtasks => 1,
+            Table::Histories => 2,
+        }
+    }
+
+    // the timestamp columns of when a record last changed; changed on both
+    // sides, the later one is kept instead of reporting a conflict
+    fn latest_columns(self) -> &'static [usize] {
+        match self {
+            Table::Tasks => &[5],
+            Table::Subtasks | Table::Histories => &[],
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl Display for Table {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Table::Tasks => "task",
+            Table::Subtasks => "subtask",
+            Table::Histories => "history",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The store as text: one line per record, keyed and sorted by uid,
+/// so that the same data always renders to the same files.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TextStore {
+    tables: [Records; 3],
+}
+
+impl TextStore {
+    fn records(&self, table: Table) -> &Records {
+        &self.tables[table.index()]
+    }
+
+    fn records_mut(&mut self, table: Table) -> &mut Records {
+        &mut self.tables[table.index()]
+    }
+
+    pub fn export(db: &dyn TaskDB) -> Result<Self> {
+        let mut store = TextStore::default();
+        let tasks = db.get_tasks(None)?;
+        let task_uids: HashMap<i32, &str> = tasks.iter().map(|t| (t.id, t.uid.as_str())).collect();
+        for t in tasks.iter() {
+            store.records_mut(Table::Tasks).insert(
+                t.uid.clone(),
+                vec![
+                    t.priority.to_string(),
+                    t.what.clone(),
+                    link_field(&t.link),
+                    t.created_at.to_string(),
+                    link_field(&t.due),
+                    t.updated_at.to_string(),
+                ],
+            );
+        }
+        for st in db.get_all_subtasks()? {
+            // subtasks left behind by finished tasks are not shown anywhere
+            if let Some(task_uid) = task_uids.get(&st.task_id) {
+                store.records_mut(Table::Subtasks).insert(
+                    st.uid.clone(),
+                    vec![
+                        task_uid.to_string(),
+                        st.subtask_rank.to_string(),
+                        st.what,
+                        link_field(&st.link),
+                        st.done.to_string(),
+                    ],
+                );
+            }
+        }
+        for h in db.get_finished_within(0, i32::MAX as u32)? {
+            store.records_mut(Table::Histories).insert(
+                h.uid.clone(),
+                vec![
+                    h.finish_timestamp.to_string(),
+                    h.what,
+                    link_field(&h.link),
+                    h.created_at.to_string(),
+                    h.priority.map(|p| p.to_string()).unwrap_or_default(),
+                ],
+            );
+        }
+        Ok(store)
+    }
+
+    /// Make the store hold exactly these records. Records are matched by uid,
+    /// so local integer ids are kept and new records get fresh ones.
+    /// Histories are append-only: records missing from the text are kept.
+    /// Either all of the records are imported or, when one fails, none are.
+    pub fn import(&self, db: &mut dyn TaskDB) -> Result<()> {
+        db.transaction(&mut |db| {
+            self.import_records(db).map_err(|e| {
+                // fields that don't parse make a malformed store
+                e.downcast::<TodoError>()
+                    .unwrap_or_else(|e| TodoError::InvalidInput(format!("{:#}", e)))
+            })
+        })?;
+        Ok(())
+    }
+
+    fn import_records(&self, db: &mut dyn TaskDB) -> Result<()> {
+        let tasks = db.get_tasks(None)?;
+        let mut next_task_id = tasks.iter().map(|t| t.id).max().unwrap_or(0) + 1;
+        let mut task_ids: HashMap<String, i32> = HashMap::new();
+        for t in tasks.iter() {
+            if self.records(Table::Tasks).contains_key(&t.uid) {
+                task_ids.insert(t.uid.clone(), t.id);
+            } else {
+                db.remove_task(t.id)?;
+            }
+        }
+
+        // drop the subtasks that are gone before ranks are reassigned below
+        let subtasks = db.get_all_subtasks()?;
+        let mut subtask_ids: HashMap<String, i32> = HashMap::new();
+        let mut next_subtask_id = subtasks.iter().map(|st| st.id).max().unwrap_or(0) + 1;
+        for st in subtasks {
+            if self.records(Table::Subtasks).contains_key(&st.uid) {
+                subtask_ids.insert(st.uid, st.id);
+            } else {
+                db.remove_subtask(st.task_id, st.subtask_rank)?;
+            }
+        }
+
+        for (uid, fields) in self.records(Table::Tasks) {
+            let id = *task_ids.entry(uid.clone()).or_insert_with(|| {
+                next_task_id += 1;
+                next_task_id - 1
+            });
+            db.put_task(&Task {
+                id,
+                what: fields[1].clone(),
+                link: link_value(&fields[2]),
+                priority: fields[0].parse()?,
+                uid: uid.clone(),
+                created_at: timestamp(&fields[3])?,
+                due: link_value(&fields[4]),
+                // files from before it was kept count the task as changed when added
+                updated_at: match fields[5].as_str() {
+                    "" => timestamp(&fields[3])?,
+                    field => field.parse()?,
+                },
+            })?;
+        }
+
+        // subtasks merged from several devices may share a rank, renumber them per task
+        let mut by_task: BTreeMap<&str, Vec<(i32, &String)>> = BTreeMap::new();
+        for (uid, fields) in self.records(Table::Subtasks) {
+            by_task
+                .entry(fields[0].as_str())
+                .or_default()
+                .push((fields[1].parse()?, uid));
+        }
+        for (task_uid, mut subtasks) in by_task {
+            let task_id = match task_ids.get(task_uid) {
+                Some(id) => *id,
+                None => continue,
+            };
+            subtasks.sort();
+            for (rank, (_, uid)) in subtasks.into_iter().enumerate() {
+                let fields = &self.records(Table::Subtasks)[uid];
+                let id = *subtask_ids.entry(uid.clone()).or_insert_with(|| {
+                    next_subtask_id += 1;
+                    next_subtask_id - 1
+                });
+                db.put_subtask(&SubTask {
+                    id,
+                    what: fields[2].clone(),
+                    link: link_value(&fields[3]),
+                    subtask_rank: rank as i32 + 1,
+                    task_id,
+                    uid: uid.clone(),
+                    done: match fields[4].as_str() {
+                        "" => false,
+                        field => field.parse()?,
+                    },
+                })?;
+            }
+        }
+
+        let histories = db.get_finished_within(0, i32::MAX as u32)?;
+        let mut next_history_id = histories.iter().map(|h| h.id).max().unwrap_or(0) + 1;
+        let history_ids: HashMap<String, i32> =
+            histories.into_iter().map(|h| (h.uid, h.id)).collect();
+        for (uid, fields) in self.records(Table::Histories) {
+            let id = match history_ids.get(uid) {
+                Some(id) => *id,
+                None => {
+                    next_history_id += 1;
+                    next_history_id - 1
+                }
+            };
+            db.put_history(&History {
+                id,
+                what: fields[1].clone(),
+                link: link_value(&fields[2]),
+                finish_timestamp: fields[0].parse()?,
+                uid: uid.clone(),
+                created_at: timestamp(&fields[3])?,
+                priority: match fields[4].as_str() {
+                    "" => None,
+                    field => Some(field.parse()?),
+                },
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn render(&self, table: Table) -> String {
+        let mut text = format!("# uid\t{}\n", table.columns().join("\t"));
+        for (uid, fields) in self.records(table) {
+            text.push_str(&escape(uid));
+            for field in fields {
+                text.push('\t');
+                text.push_str(&escape(field));
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    fn parse(&mut self, table: Table, text: &str) -> Result<()> {
+        let records = self.records_mut(table);
+        for (n, line) in text.lines().enumerate() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields: Vec<String> = line.split('\t').map(unescape).collect();
+            // files written by older versions lack the columns added since
+            let expected = table.columns().len() + 1;
+            if fields.len() < expected && fields.len() + table.added_columns() >= expected {
+                fields.resize(expected, String::new());
+            }
+            if fields.len() != table.columns().len() + 1 {
+                bail!(
+                    "{}:{}: expect {} fields, found {}",
+                    table.file_name(),
+                    n + 1,
+                    table.columns().len() + 1,
+                    fields.len()
+                );
+            }
+            let uid = fields.remove(0);
+            records.insert(uid, fields);
+        }
+        Ok(())
+    }
+}
+
+fn link_field(link: &Option<String>) -> String {
+    link.clone().unwrap_or_default()
+}
+
+fn link_value(field: &str) -> Option<String> {
+    if field.is_empty() {
+        None
+    } else {
+        Some(field.to_owned())
+    }
+}
+
+// a unix time, 0 when the column was left empty by an older version
+fn timestamp(field: &str) -> Result<i32, std::num::ParseIntError> {
+    match field {
+        "" => Ok(0),
+        field => field.parse(),
+    }
+}
+
+fn escape(field: &str) -> String {
+    field
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn unescape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Which side wins when both changed the same field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Local,
+    Remote,
+}
+
+impl FromStr for Side {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "local" => Ok(Side::Local),
+            "remote" => Ok(Side::Remote),
+            _ => bail!("unknown side `{}`, expect local or remote", s),
+        }
+    }
+}
+
+/// A record changed in different ways on both sides since they last synced.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Conflict {
+    pub table: Table,
+    pub uid: String,
+    pub local: Option<Vec<String>>,
+    pub remote: Option<Vec<String>>,
+}
+
+impl Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let describe = |fields: &Option<Vec<String>>| match fields {
+            Some(fields) => self
+                .table
+                .columns()
+                .iter()
+                .zip(fields)
+                .map(|(c, v)| format!("{}={:?}", c, v))
+                .collect::<Vec<_>>()
+                .join(" "),
+            None => "deleted".to_owned(),
+        };
+        write!(
+            f,
+            "{} {}: local {} | remote {}",
+            self.table,
+            self.uid,
+            describe(&self.local),
+            describe(&self.remote)
+        )
+    }
+}
+
+/// Three-way merge of the records, field by field.
+pub fn merge(
+    base: &TextStore,
+    local: &TextStore,
+    remote: &TextStore,
+    prefer: Option<Side>,
+) -> (TextStore, Vec<Conflict>) {
+    let mut merged = TextStore::default();
+    let mut conflicts = vec![];
+    for table in Table::ALL {
+        let uids: BTreeSet<&String> = base
+            .records(table)
+            .keys()
+            .chain(local.records(table).keys())
+            .chain(remote.records(table).keys())
+            .collect();
+        for uid in uids {
+            let b = base.records(table).get(uid);
+            let l = local.records(table).get(uid);
+            let r = remote.records(table).get(uid);
+            match merge_record(b, l, r, table.latest_columns(), prefer) {
+                Ok(Some(fields)) => {
+                    merged.records_mut(table).insert(uid.clone(), fields);
+                }
+                Ok(None) => {}
+                Err(()) => conflicts.push(Conflict {
+                    table,
+                    uid: uid.clone(),
+                    local: l.cloned(),
+                    remote: r.cloned(),
+                }),
+            }
+        }
+    }
+    (merged, conflicts)
+}
+
+fn merge_record(
+    base: Option<&Vec<String>>,
+    local: Option<&Vec<String>>,
+    remote: Option<&Vec<String>>,
+    latest: &[usize],
+    prefer: Option<Side>,
+) -> Result<Option<Vec<String>>, ()> {
+    if local == remote || base == remote {
+        return Ok(local.cloned());
+    }
+    if base == local {
+        return Ok(remote.cloned());
+    }
+    match (base, local, remote) {
+        (Some(b), Some(l), Some(r)) => b
+            .iter()
+            .zip(l)
+            .zip(r)
+            .enumerate()
+            .map(|(i, ((b, l), r))| {
+                if l == r || b == r {
+                    Ok(l.clone())
+                } else if b == l {
+                    Ok(r.clone())
+                } else if latest.contains(&i) {
+                    let later = |f: &String| f.parse::<i64>().unwrap_or(0);
+                    Ok(std::cmp::max_by_key(l, r, |f| later(f)).clone())
+                } else {
+                    match prefer {
+                        Some(Side::Local) => Ok(l.clone()),
+                        Some(Side::Remote) => Ok(r.clone()),
+                        None => Err(()),
+                    }
+                }
+            })
+            .collect::<Result<Vec<_>, ()>>()
+            .map(Some),
+        // added differently on both sides, or edited on one and removed on the other
+        _ => match prefer {
+            Some(Side::Local) => Ok(local.cloned()),
+            Some(Side::Remote) => Ok(remote.cloned()),
+            None => Err(()),
+        },
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub committed: bool,
+    pub pulled: bool,
+    pub pushed: bool,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// A git working copy holding the text form of the store.
+pub struct SyncRepo {
+    dir: PathBuf,
+    // commits must not fail on machines without a git identity
+    has_identity: bool,
+}
+
+impl SyncRepo {
+    pub fn open(dir: PathBuf) -> Result<Self> {
+        let mut repo = SyncRepo {
+            dir,
+            has_identity: true,
+        };
+        if !repo.dir.join(".git").exists() {
+            fs::create_dir_all(&repo.dir)
+                .with_context(|| format!("fail to create sync dir {}", repo.dir.display()))?;
+            repo.git(&["init", "-q"])?;
+        }
+        repo.has_identity = ["user.name", "user.email"]
+            .iter()
+            .all(|key| repo.git(&["config", key]).is_ok());
+        Ok(repo)
+    }
+
+    pub fn set_remote(&self, url: &str) -> Result<()> {
+        if self.git(&["remote", "get-url", "origin"]).is_ok() {
+            self.git(&["remote", "set-url", "origin", url])?;
+        } else {
+            self.git(&["remote", "add", "origin", url])?;
+        }
+        Ok(())
+    }
+
+    /// Commit the local store, merge the remote one into it, load the result
+    /// back into the store and push. Nothing is changed locally on conflicts
+    /// unless `prefer` says which side wins.
+    pub fn sync(&self, db: &mut dyn TaskDB, prefer: Option<Side>) -> Result<SyncReport> {
+        let mut report = SyncReport {
+            committed: self.commit_store(&TextStore::export(db)?, "sync: local changes")?,
+            ..SyncReport::default()
+        };
+        if self.git(&["remote", "get-url", "origin"]).is_err() {
+            return Ok(report);
+        }
+
+        self.git(&["fetch", "-q", "origin"])?;
+        let branch = self.rev(&["symbolic-ref", "--short", "HEAD"])?;
+        let remote_ref = format!("refs/remotes/origin/{}", branch);
+        if self
+            .rev(&["rev-parse", "-q", "--verify", &remote_ref])
+            .is_ok()
+        {
+            let head = self.rev(&["rev-parse", "HEAD"])?;
+            let remote_head = self.rev(&["rev-parse", &remote_ref])?;
+            let base = self.rev(&["merge-base", "HEAD", &remote_ref]).ok();
+            if base.as_deref() != Some(remote_head.as_str()) {
+                let remote = self.read_store(&remote_head)?;
+                if base.as_deref() == Some(head.as_str()) {
+                    self.git(&["merge", "-q", "--ff-only", &remote_ref])?;
+                    self.import_pulled(&remote, db, &head)?;
+                } else {
+                    let base = match base {
+                        Some(rev) => self.read_store(&rev)?,
+                        None => TextStore::default(),
+                    };
+                    let local = self.read_store(&head)?;
+                    let (merged, conflicts) = merge(&base, &local, &remote, prefer);
+                    if !conflicts.is_empty() {
+                        report.conflicts = conflicts;
+                        return Ok(report);
+                    }
+                    self.git(&[
+                        "merge",
+                        "-q",
+                        "--no-ff",
+                        "--no-commit",
+                        "-s",
+                        "ours",
+                        "--allow-unrelated-histories",
+                        &remote_ref,
+                    ])?;
+                    self.commit_store(&merged, "sync: merge remote changes")?;
+                    self.import_pulled(&merged, db, &head)?;
+                }
+                report.pulled = true;
+                // ranks may have been renumbered on import
+                self.commit_store(&TextStore::export(db)?, "sync: local changes")?;
+            }
+        }
+        self.git(&["push", "-q", "origin", &format!("HEAD:{}", branch)])?;
+        report.pushed = true;
+        Ok(report)
+    }
+
+    // import `store`, which git has just moved to, into the db; when that
+    // fails git goes back to `head` so that it never gets ahead of the db
+    fn import_pulled(&self, store: &TextStore, db: &mut dyn TaskDB, head: &str) -> Result<()> {
+        if let Err(e) = store.import(db) {
+            self.git(&["reset", "-q", "--hard", head])?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // write the store into the working copy and commit it if anything changed
+    fn commit_store(&self, store: &TextStore, message: &str) -> Result<bool> {
+        for table in Table::ALL {
+            fs::write(self.dir.join(table.file_name()), store.render(table))
+                .with_context(|| format!("fail to write {}", table.file_name()))?;
+        }
+        self.git(&["add", "-A"])?;
+        let merging = self.dir.join(".git").join("MERGE_HEAD").exists();
+        if self.rev(&["status", "--porcelain"])?.is_empty() && !merging {
+            return Ok(false);
+        }
+        self.git(&["commit", "-q", "-m", message])?;
+        Ok(true)
+    }
+
+    fn read_store(&self, rev: &str) -> Result<TextStore> {
+        let mut store = TextStore::default();
+        // a table missing from a commit is empty; any other failure to read
+        // one is an error, not an empty table
+        let files = self.git(&["ls-tree", "--name-only", rev])?;
+        for table in Table::ALL {
+            if files.lines().any(|file| file == table.file_name()) {
+                let text = self.git(&["show", &format!("{}:{}", rev, table.file_name())])?;
+                store.parse(table, &text)?;
+            }
+        }
+        Ok(store)
+    }
+
+    // the output of a git command that prints a single value
+    fn rev(&self, args: &[&str]) -> Result<String> {
+        Ok(self.git(args)?.trim().to_owned())
+    }
+
+    fn git(&self, args: &[&str]) -> Result<String> {
+        let mut cmd = Command::new("git");
+        cmd.current_dir(&self.dir);
+        if !self.has_identity {
+            cmd.args(["-c", "user.name=todo", "-c", "user.email=todo@localhost"]);
+        }
+        let output = cmd
+            .args(args)
+            .output()
+            .context("fail to run git, is it installed?")?;
+        if !output.status.success() {
+            bail!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
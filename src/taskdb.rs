@@ -1,18 +1,26 @@
-use anyhow::{Context, Result};
 use diesel::associations::HasTable;
-use diesel::dsl::max;
+use diesel::connection::TransactionManager;
+use diesel::expression::dsl::max;
 use diesel::{prelude::*, sql_query};
-use diesel_migrations::embed_migrations;
+use diesel_migrations::{embed_migrations, MigrationConnection};
 
 use crate::create_connection;
-use crate::models::{History, NewHistory, NewSubTask, NewTask, SubTask, Task};
+use crate::error::{storage, TodoError, TodoResult};
+use crate::models::{new_uid, FieldTime, History, NewHistory, NewSubTask, NewTask, SubTask, Task};
+use crate::query::{Page, Sort, SortField, TaskFilter};
+use crate::schema::field_times;
 use crate::schema::histories;
 use crate::schema::subtasks::dsl::subtasks;
 use crate::schema::tasks::dsl::*;
+use sql_functions::last_insert_rowid;
 
-type TodoResult<T> = Result<T>;
 type IDType = i32;
 
+// the version of the latest migration, for stores that don't run migrations
+pub const SCHEMA_VERSION: &str = "20261020090000";
+
+/// A task store. Every method fails with a `TodoError`: `NotFound` when it
+/// needs a task that doesn't exist, `InvalidInput` for an empty description.
 pub trait TaskDB {
     fn add_task(&mut self, task: NewTask) -> TodoResult<IDType>;
     fn add_subtask(&mut self, id: IDType, what: String, link: Option<String>) -> TodoResult<()>;
@@ -21,31 +29,170 @@ pub trait TaskDB {
     fn get_subtasks(&self, id: IDType) -> TodoResult<Vec<SubTask>>;
     fn get_finished(&self, last_n: u32) -> TodoResult<Vec<History>>;
     fn get_finished_within(&self, start_ts: u32, end_ts: u32) -> TodoResult<Vec<History>>;
-    fn update_task_desc(&mut self, id: IDType, desc: String) -> TodoResult<()>;
-    fn update_task_priority(&mut self, task_id: IDType, pri: i32) -> TodoResult<()>;
-    fn remove_task(&mut self, id: IDType) -> TodoResult<()>;
-    fn update_subtask_belongings(&mut self, task_id: IDType, new_task_id: IDType)
-        -> TodoResult<()>;
-    fn remove_subtask(&mut self, id: IDType, subtask_rank: i32) -> TodoResult<()>;
-    fn finish_task(&mut self, id: IDType) -> TodoResult<()>;
+    // the updates and removals return how many rows they changed, 0 when
+    // there is no such task or subtask
+    fn update_task_desc(&mut self, id: IDType, desc: String) -> TodoResult<usize>;
+    fn update_task_priority(&mut self, task_id: IDType, pri: i32) -> TodoResult<usize>;
+    fn remove_task(&mut self, id: IDType) -> TodoResult<usize>;
+    fn update_subtask_belongings(
+        &mut self,
+        task_id: IDType,
+        new_task_id: IDType,
+    ) -> TodoResult<usize>;
+    fn remove_subtask(&mut self, id: IDType, subtask_rank: i32) -> TodoResult<usize>;
+    // mark the subtask done, it stays with its task to show the progress
+    fn finish_subtask(&mut self, id: IDType, subtask_rank: i32) -> TodoResult<usize>;
+    // move the task into history, returning the history record
+    fn finish_task(&mut self, id: IDType) -> TodoResult<History>;
+    fn get_all_subtasks(&self) -> TodoResult<Vec<SubTask>>;
+    // insert records keeping their ids, overwriting any row with the same id;
+    // they are kept as given, updated_at included
+    fn put_task(&mut self, task: &Task) -> TodoResult<()>;
+    fn put_subtask(&mut self, subtask: &SubTask) -> TodoResult<()>;
+    fn put_history(&mut self, history: &History) -> TodoResult<()>;
+    // when the fields of tasks and subtasks last changed, for those changed
+    // since the record was added; the updates stamp the fields they change,
+    // put_task with the task's updated_at and put_subtask with the time it is put
+    fn get_field_times(&self) -> TodoResult<Vec<FieldTime>>;
+    fn put_field_time(&mut self, time: &FieldTime) -> TodoResult<()>;
+    // the version of the latest migration applied to the store
+    fn schema_version(&self) -> TodoResult<Option<String>>;
+
+    /// Run `f` so that either all of its changes are kept or, when it fails, none are.
+    /// Transactions nest.
+    fn transaction(
+        &mut self,
+        f: &mut dyn FnMut(&mut dyn TaskDB) -> TodoResult<()>,
+    ) -> TodoResult<()>;
+
+    /// The tasks passing `filter`, ordered by `sort`, the `page` of them.
+    /// Fails with `InvalidInput` for a key tasks don't have.
+    fn query_tasks(&self, filter: &TaskFilter, sort: &Sort, page: Page) -> TodoResult<Vec<Task>> {
+        sort.check_tasks()?;
+        let mut found = self.get_tasks(filter.pattern.clone())?;
+        found.retain(|t| filter.matches_update(t));
+        found.sort_by(|a, b| sort.compare_tasks(a, b));
+        Ok(page.apply(found))
+    }
+
+    /// The history finished from `start_ts` until before `end_ts`, ordered by
+    /// `sort`, the `page` of it. Fails with `InvalidInput` for a key history
+    /// doesn't have.
+    fn query_finished(
+        &self,
+        start_ts: u32,
+        end_ts: u32,
+        sort: &Sort,
+        page: Page,
+    ) -> TodoResult<Vec<History>> {
+        sort.check_history()?;
+        let mut histories = self.get_finished_within(start_ts, end_ts)?;
+        histories.sort_by(|a, b| sort.compare_histories(a, b));
+        Ok(page.apply(histories))
+    }
+
+    /// Fail with `Missing` listing every id that has no task.
+    fn check_tasks(&self, ids: &[IDType]) -> TodoResult<()> {
+        let mut missing = vec![];
+        for &task_id in ids {
+            if self.get_task(task_id)?.is_none() && !missing.contains(&task_id) {
+                missing.push(task_id);
+            }
+        }
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(TodoError::Missing {
+                kind: "task",
+                ids: missing,
+            })
+        }
+    }
+
+    /// Finish all the tasks or, if any id is missing, none of them.
+    /// Returns the history records in the order of `ids`.
+    fn finish_tasks(&mut self, ids: &[IDType]) -> TodoResult<Vec<History>> {
+        self.check_tasks(ids)?;
+        let ids = dedup(ids);
+        let mut finished = vec![];
+        self.transaction(&mut |db| {
+            finished = ids
+                .iter()
+                .map(|&task_id| db.finish_task(task_id))
+                .collect::<TodoResult<_>>()?;
+            Ok(())
+        })?;
+        Ok(finished)
+    }
+
+    /// Remove all the tasks or, if any id is missing, none of them.
+    fn remove_tasks(&mut self, ids: &[IDType]) -> TodoResult<usize> {
+        self.check_tasks(ids)?;
+        let ids = dedup(ids);
+        let mut removed = 0;
+        self.transaction(&mut |db| {
+            removed = 0;
+            for &task_id in ids.iter() {
+                removed += db.remove_task(task_id)?;
+            }
+            Ok(())
+        })?;
+        Ok(removed)
+    }
+
+    /// Set the priority of each task or, if any id is missing, of none of them.
+    fn update_priorities(&mut self, priorities: &[(IDType, i32)]) -> TodoResult<usize> {
+        let ids: Vec<IDType> = priorities.iter().map(|&(task_id, _)| task_id).collect();
+        self.check_tasks(&ids)?;
+        let mut updated = 0;
+        self.transaction(&mut |db| {
+            updated = 0;
+            for &(task_id, pri) in priorities {
+                updated += db.update_task_priority(task_id, pri)?;
+            }
+            Ok(())
+        })?;
+        Ok(updated)
+    }
+}
+
+// ids in their first order, without repeats
+fn dedup(ids: &[IDType]) -> Vec<IDType> {
+    let mut unique = Vec::with_capacity(ids.len());
+    for &i in ids {
+        if !unique.contains(&i) {
+            unique.push(i);
+        }
+    }
+    unique
 }
 
 pub struct TaskSqlite {
     conn: SqliteConnection,
 }
 
-no_arg_sql_function!(
-    last_insert_rowid,
-    diesel::sql_types::Integer,
-    "Represents the SQL last_insert_row() function"
-);
+// the diesel 1.4 macro impls inside a const block
+#[allow(non_local_definitions)]
+mod sql_functions {
+    no_arg_sql_function!(
+        last_insert_rowid,
+        diesel::sql_types::Integer,
+        "Represents the SQL last_insert_row() function"
+    );
+}
 
 impl TaskDB for TaskSqlite {
     fn add_task(&mut self, new_task: NewTask) -> TodoResult<IDType> {
+        check_desc(&new_task.what)?;
         diesel::insert_into(tasks::table())
-            .values(&new_task)
+            .values((
+                &new_task,
+                uid.eq(new_uid()),
+                created_at.eq(now()),
+                updated_at.eq(now()),
+            ))
             .execute(&self.conn)
-            .context("fail to add new task")?;
+            .map_err(storage("fail to add new task"))?;
         // https://github.com/diesel-rs/diesel/issues/771
         let last_id = diesel::select(last_insert_rowid).get_result::<i32>(&self.conn)?;
         Ok(last_id)
@@ -57,6 +204,13 @@ impl TaskDB for TaskSqlite {
         st_what: String,
         st_link: Option<String>,
     ) -> TodoResult<()> {
+        check_desc(&st_what)?;
+        if self.get_task(input_task_id)?.is_none() {
+            return Err(TodoError::NotFound {
+                kind: "task",
+                id: input_task_id,
+            });
+        }
         // use i64 for count returned value
         let rank: i64 = subtasks
             .count()
@@ -67,31 +221,32 @@ impl TaskDB for TaskSqlite {
             link: st_link,
             task_id: input_task_id,
             subtask_rank: 1 + rank as i32,
+            uid: new_uid(),
         };
         diesel::insert_into(subtasks::table())
             .values(&new_subtask)
             .execute(&self.conn)
-            .expect("fail to add new subtask");
-        Ok(())
+            .map_err(storage("fail to add new subtask"))?;
+        self.touch(input_task_id)
     }
 
     fn get_task(&self, task_id: i32) -> TodoResult<Option<Task>> {
-        let task = tasks
+        tasks
             .find(task_id)
             .first::<Task>(&self.conn)
-            .context(format!("data store failed to find task {}", task_id))?;
-        Ok(Some(task))
+            .optional()
+            .map_err(storage(format!("fail to find task {}", task_id)))
     }
 
     fn get_subtasks(&self, input_task_id: IDType) -> TodoResult<Vec<SubTask>> {
         // return subtasks associated with a task
-        let task = tasks
-            .find(input_task_id)
-            .first::<Task>(&self.conn)
-            .expect("Task not found!");
+        let task = self.get_task(input_task_id)?.ok_or(TodoError::NotFound {
+            kind: "task",
+            id: input_task_id,
+        })?;
         let results = SubTask::belonging_to(&task)
             .load::<SubTask>(&self.conn)
-            .context("fail to find subtask")?;
+            .map_err(storage("fail to find subtask"))?;
         Ok(results)
     }
 
@@ -121,64 +276,165 @@ impl TaskDB for TaskSqlite {
             .load::<History>(&self.conn)?)
     }
 
-    fn remove_task(&mut self, task_id: IDType) -> TodoResult<()> {
+    fn query_tasks(&self, filter: &TaskFilter, sort: &Sort, page: Page) -> TodoResult<Vec<Task>> {
+        sort.check_tasks()?;
+        let mut query = tasks.into_boxed();
+        if let Some(pattern) = &filter.pattern {
+            query = query.filter(what.like(format!("%{}%", pattern)));
+        }
+        if let Some(before) = filter.updated_before {
+            query = query.filter(updated_at.lt(before));
+        }
+        for key in sort.keys() {
+            query = match (key.field, key.desc) {
+                (SortField::Priority, false) => query.then_order_by(priority.asc()),
+                (SortField::Priority, true) => query.then_order_by(priority.desc()),
+                (SortField::Id, false) => query.then_order_by(id.asc()),
+                (SortField::Id, true) => query.then_order_by(id.desc()),
+                // tasks without a due date go last in both directions
+                (SortField::Due, false) => query.then_order_by((due.is_null(), due.asc())),
+                (SortField::Due, true) => query.then_order_by((due.is_null(), due.desc())),
+                (SortField::Created, false) => query.then_order_by(created_at.asc()),
+                (SortField::Created, true) => query.then_order_by(created_at.desc()),
+                (SortField::Updated, false) => query.then_order_by(updated_at.asc()),
+                (SortField::Updated, true) => query.then_order_by(updated_at.desc()),
+                (SortField::Alphabetical, false) => query.then_order_by(what.asc()),
+                (SortField::Alphabetical, true) => query.then_order_by(what.desc()),
+                (SortField::Finished, _) => query,
+            };
+        }
+        // sqlite takes an offset only after a limit, -1 being none
+        if page.limit.is_some() || page.offset > 0 {
+            query = query
+                .limit(page.limit.map_or(-1, i64::from))
+                .offset(page.offset as i64);
+        }
+        Ok(query.load::<Task>(&self.conn)?)
+    }
+
+    fn query_finished(
+        &self,
+        start_ts: u32,
+        end_ts: u32,
+        sort: &Sort,
+        page: Page,
+    ) -> TodoResult<Vec<History>> {
+        use crate::schema::histories::dsl as h;
+
+        sort.check_history()?;
+        let mut query = h::histories
+            .filter(h::finish_timestamp.ge(start_ts as i32))
+            .filter(h::finish_timestamp.lt(end_ts as i32))
+            .into_boxed();
+        for key in sort.keys() {
+            query = match (key.field, key.desc) {
+                (SortField::Id, false) => query.then_order_by(h::id.asc()),
+                (SortField::Id, true) => query.then_order_by(h::id.desc()),
+                (SortField::Alphabetical, false) => query.then_order_by(h::what.asc()),
+                (SortField::Alphabetical, true) => query.then_order_by(h::what.desc()),
+                (SortField::Finished, false) => query.then_order_by(h::finish_timestamp.asc()),
+                (SortField::Finished, true) => query.then_order_by(h::finish_timestamp.desc()),
+                (
+                    SortField::Priority | SortField::Due | SortField::Created | SortField::Updated,
+                    _,
+                ) => query,
+            };
+        }
+        if page.limit.is_some() || page.offset > 0 {
+            query = query
+                .limit(page.limit.map_or(-1, i64::from))
+                .offset(page.offset as i64);
+        }
+        Ok(query.load::<History>(&self.conn)?)
+    }
+
+    fn remove_task(&mut self, task_id: IDType) -> TodoResult<usize> {
         let rows_affected = diesel::delete(tasks.filter(id.eq_all(task_id)))
             .execute(&self.conn)
-            .context(format!("data store failed to remove task {}", task_id))?;
-        if rows_affected == 0 {
-            println!("task {} not found!", task_id);
-        }
+            .map_err(storage(format!("fail to remove task {}", task_id)))?;
         self.try_reset_id("tasks")?;
-        Ok(())
+        Ok(rows_affected)
     }
 
-    fn finish_task(&mut self, task_id: IDType) -> TodoResult<()> {
-        let task = self
-            .get_task(task_id)
-            .context("finish task: fail to find task")?
-            .unwrap();
-        self.remove_task(task_id)?;
-        let new_history = NewHistory {
-            what: task.what,
-            link: task.link,
-            finish_timestamp: chrono::Utc::now().timestamp() as i32,
-        };
-        let rows_affected = diesel::insert_into(histories::dsl::histories::table())
-            .values(&new_history)
-            .execute(&self.conn)?;
-        if rows_affected == 0 {
-            println!("fail to finish task {}!", task_id);
-        }
-        Ok(())
+    fn finish_task(&mut self, task_id: IDType) -> TodoResult<History> {
+        let task = self.get_task(task_id)?.ok_or(TodoError::NotFound {
+            kind: "task",
+            id: task_id,
+        })?;
+        self.in_transaction(|db| {
+            db.remove_task(task_id)?;
+            let new_history = NewHistory {
+                what: task.what,
+                link: task.link,
+                finish_timestamp: now(),
+                uid: task.uid,
+                created_at: task.created_at,
+                priority: Some(task.priority),
+            };
+            diesel::insert_into(histories::dsl::histories::table())
+                .values(&new_history)
+                .execute(&db.conn)
+                .map_err(storage(format!("fail to finish task {}", task_id)))?;
+            let history_id = diesel::select(last_insert_rowid).get_result::<i32>(&db.conn)?;
+            Ok(History {
+                id: history_id,
+                what: new_history.what,
+                link: new_history.link,
+                finish_timestamp: new_history.finish_timestamp,
+                uid: new_history.uid,
+                created_at: new_history.created_at,
+                priority: new_history.priority,
+            })
+        })
     }
 
-    fn update_task_desc(&mut self, task_id: IDType, desc: String) -> TodoResult<()> {
-        diesel::update(tasks.filter(id.eq(task_id)))
-            .set(what.eq(desc))
-            .execute(&self.conn)?;
-        Ok(())
+    fn update_task_desc(&mut self, task_id: IDType, desc: String) -> TodoResult<usize> {
+        check_desc(&desc)?;
+        let task = match self.get_task(task_id)? {
+            Some(task) => task,
+            None => return Ok(0),
+        };
+        let at = now();
+        self.in_transaction(|db| {
+            let updated = diesel::update(tasks.filter(id.eq(task_id)))
+                .set((what.eq(desc), updated_at.eq(at)))
+                .execute(&db.conn)?;
+            db.stamp(&task.uid, &["what"], at)?;
+            Ok(updated)
+        })
     }
 
-    fn update_task_priority(&mut self, task_id: IDType, pri: i32) -> TodoResult<()> {
-        diesel::update(tasks.filter(id.eq(task_id)))
-            .set(priority.eq(pri))
-            .execute(&self.conn)?;
-        Ok(())
+    fn update_task_priority(&mut self, task_id: IDType, pri: i32) -> TodoResult<usize> {
+        let task = match self.get_task(task_id)? {
+            Some(task) => task,
+            None => return Ok(0),
+        };
+        let at = now();
+        self.in_transaction(|db| {
+            let updated = diesel::update(tasks.filter(id.eq(task_id)))
+                .set((priority.eq(pri), updated_at.eq(at)))
+                .execute(&db.conn)?;
+            db.stamp(&task.uid, &["priority"], at)?;
+            Ok(updated)
+        })
     }
 
     fn update_subtask_belongings(
         &mut self,
         old_task_id: IDType,
         new_task_id: IDType,
-    ) -> TodoResult<()> {
+    ) -> TodoResult<usize> {
         use crate::schema::subtasks::dsl::task_id;
-        diesel::update(subtasks.filter(task_id.eq_all(old_task_id)))
+        Ok(diesel::update(subtasks.filter(task_id.eq_all(old_task_id)))
             .set(task_id.eq(new_task_id))
-            .execute(&self.conn)?;
-        Ok(())
+            .execute(&self.conn)?)
     }
 
-    fn remove_subtask(&mut self, input_task_id: IDType, input_subtask_rank: i32) -> TodoResult<()> {
+    fn remove_subtask(
+        &mut self,
+        input_task_id: IDType,
+        input_subtask_rank: i32,
+    ) -> TodoResult<usize> {
         use crate::schema::subtasks::dsl::{subtask_rank, task_id};
         let rows_affected = diesel::delete(
             subtasks.filter(
@@ -188,18 +444,166 @@ impl TaskDB for TaskSqlite {
             ),
         )
         .execute(&self.conn)?;
+        self.try_reset_id("subtasks")?;
+        if rows_affected > 0 {
+            self.touch(input_task_id)?;
+        }
+        Ok(rows_affected)
+    }
+
+    fn finish_subtask(
+        &mut self,
+        input_task_id: IDType,
+        input_subtask_rank: i32,
+    ) -> TodoResult<usize> {
+        use crate::schema::subtasks::dsl::{done, subtask_rank, task_id};
+        let rows_affected = diesel::update(
+            subtasks.filter(
+                task_id
+                    .eq_all(input_task_id)
+                    .and(subtask_rank.eq_all(input_subtask_rank)),
+            ),
+        )
+        .set(done.eq(true))
+        .execute(&self.conn)?;
+        if rows_affected > 0 {
+            self.touch(input_task_id)?;
+        }
+        Ok(rows_affected)
+    }
+
+    fn get_all_subtasks(&self) -> TodoResult<Vec<SubTask>> {
+        use crate::schema::subtasks::dsl::{subtask_rank, task_id};
+        Ok(subtasks
+            .order_by((task_id, subtask_rank))
+            .load::<SubTask>(&self.conn)?)
+    }
+
+    fn put_task(&mut self, task: &Task) -> TodoResult<()> {
+        let old = self.get_task(task.id)?;
+        self.in_transaction(|db| {
+            let rows_affected = diesel::update(task).set(task).execute(&db.conn)?;
+            if rows_affected == 0 {
+                diesel::insert_into(tasks::table())
+                    .values(task)
+                    .execute(&db.conn)
+                    .map_err(storage(format!("fail to put task {}", task.id)))?;
+            }
+            match old {
+                Some(old) => db.stamp(&task.uid, &changed_task_fields(&old, task), task.updated_at),
+                None => Ok(()),
+            }
+        })
+    }
+
+    fn put_subtask(&mut self, subtask: &SubTask) -> TodoResult<()> {
+        let old = subtasks
+            .find(subtask.id)
+            .first::<SubTask>(&self.conn)
+            .optional()?;
+        self.in_transaction(|db| {
+            let rows_affected = diesel::update(subtask).set(subtask).execute(&db.conn)?;
+            if rows_affected == 0 {
+                diesel::insert_into(subtasks::table())
+                    .values(subtask)
+                    .execute(&db.conn)
+                    .map_err(storage(format!("fail to put subtask {}", subtask.id)))?;
+            }
+            match old {
+                Some(old) => db.stamp(&subtask.uid, &changed_subtask_fields(&old, subtask), now()),
+                None => Ok(()),
+            }
+        })
+    }
+
+    fn put_history(&mut self, history: &History) -> TodoResult<()> {
+        let rows_affected = diesel::update(history).set(history).execute(&self.conn)?;
         if rows_affected == 0 {
-            println!(
-                "subtask {} for task {} not found!",
-                input_subtask_rank, input_task_id
-            );
+            diesel::insert_into(histories::dsl::histories::table())
+                .values(history)
+                .execute(&self.conn)
+                .map_err(storage(format!("fail to put history {}", history.id)))?;
         }
-        self.try_reset_id("subtasks")?;
         Ok(())
     }
+
+    fn get_field_times(&self) -> TodoResult<Vec<FieldTime>> {
+        Ok(field_times::table.load::<FieldTime>(&self.conn)?)
+    }
+
+    fn put_field_time(&mut self, time: &FieldTime) -> TodoResult<()> {
+        diesel::replace_into(field_times::table)
+            .values(time)
+            .execute(&self.conn)
+            .map_err(storage(format!(
+                "fail to put the time {} of {} changed",
+                time.field, time.uid
+            )))?;
+        Ok(())
+    }
+
+    fn schema_version(&self) -> TodoResult<Option<String>> {
+        self.conn
+            .latest_run_migration_version()
+            .map_err(|e| TodoError::Migration(e.to_string()))
+    }
+
+    fn transaction(
+        &mut self,
+        f: &mut dyn FnMut(&mut dyn TaskDB) -> TodoResult<()>,
+    ) -> TodoResult<()> {
+        self.in_transaction(|db| f(db))
+    }
 }
 
 impl TaskSqlite {
+    // `fields` of the task or subtask `record_uid` changed `at` then
+    fn stamp(&mut self, record_uid: &str, fields: &[&str], at: i32) -> TodoResult<()> {
+        for field in fields {
+            self.put_field_time(&FieldTime {
+                uid: record_uid.to_owned(),
+                field: (*field).to_owned(),
+                changed_at: at,
+            })?;
+        }
+        Ok(())
+    }
+
+    // a change to the subtasks of a task counts as a change to the task
+    fn touch(&self, task_id: IDType) -> TodoResult<()> {
+        diesel::update(tasks.filter(id.eq(task_id)))
+            .set(updated_at.eq(now()))
+            .execute(&self.conn)
+            .map_err(storage(format!("fail to update task {}", task_id)))?;
+        Ok(())
+    }
+
+    // diesel's `Connection::transaction` borrows the connection for the whole
+    // closure, so drive the transaction manager by hand to hand `self` over;
+    // nested calls become savepoints
+    fn in_transaction<T>(&mut self, f: impl FnOnce(&mut Self) -> TodoResult<T>) -> TodoResult<T> {
+        let manager = self.conn.transaction_manager();
+        manager
+            .begin_transaction(&self.conn)
+            .map_err(storage("fail to begin a transaction"))?;
+        match f(self) {
+            Ok(value) => {
+                self.conn
+                    .transaction_manager()
+                    .commit_transaction(&self.conn)
+                    .map_err(storage("fail to commit"))?;
+                Ok(value)
+            }
+            Err(e) => {
+                self.conn
+                    .transaction_manager()
+                    .rollback_transaction(&self.conn)
+                    .map_err(storage("fail to roll back"))?;
+                Err(e)
+            }
+        }
+    }
+
     fn try_reset_id(&mut self, table_name: &str) -> TodoResult<()> {
         let count = if table_name == "tasks" {
             tasks.select(max(id)).first(&self.conn)?
@@ -213,17 +617,84 @@ impl TaskSqlite {
             "UPDATE `sqlite_sequence` SET `seq`={} WHERE `name`='{}'",
             count, table_name
         ));
-        query.execute(&self.conn).expect("fail to reset id");
+        query
+            .execute(&self.conn)
+            .map_err(storage(format!("fail to reset the id of {}", table_name)))?;
         Ok(())
     }
 }
 
-//use diesel_migrations::embed_migrations;
-
 embed_migrations!();
 
+impl TaskSqlite {
+    /// Open the sqlite db at `db_url`, bringing it up to the current schema.
+    pub fn open(db_url: &str) -> TodoResult<Self> {
+        let conn = create_connection(db_url.to_owned())?;
+        embedded_migrations::run(&conn).map_err(|e| TodoError::Migration(e.to_string()))?;
+        Ok(TaskSqlite { conn })
+    }
+
+    // open a db without migrating it
+    pub(crate) fn connect(db_url: &str) -> TodoResult<Self> {
+        Ok(TaskSqlite {
+            conn: create_connection(db_url.to_owned())?,
+        })
+    }
+
+    // run statements that return nothing useful, like pragmas
+    #[cfg(feature = "async")]
+    pub(crate) fn batch_execute(&self, sql: &str) -> TodoResult<()> {
+        use diesel::connection::SimpleConnection;
+        self.conn
+            .batch_execute(sql)
+            .map_err(storage(format!("fail to run {}", sql)))
+    }
+}
+
 pub fn open(db_url: &str) -> TodoResult<Box<dyn TaskDB>> {
-    let conn = create_connection(db_url.to_owned());
-    embedded_migrations::run(&conn)?;
-    Ok(Box::new(TaskSqlite { conn }))
+    Ok(Box::new(TaskSqlite::open(db_url)?))
+}
+
+/// The current unix time, as the stores keep it.
+pub fn now() -> i32 {
+    chrono::Utc::now().timestamp() as i32
+}
+
+/// The fields of a task that two copies of it may change independently.
+pub const TASK_FIELDS: [&str; 4] = ["what", "link", "priority", "due"];
+/// The same for subtasks; a subtask done in either copy is simply done.
+pub const SUBTASK_FIELDS: [&str; 2] = ["what", "link"];
+
+// the `TASK_FIELDS` that differ between two versions of a task
+pub(crate) fn changed_task_fields(old: &Task, new: &Task) -> Vec<&'static str> {
+    let changed = [
+        old.what != new.what,
+        old.link != new.link,
+        old.priority != new.priority,
+        old.due != new.due,
+    ];
+    TASK_FIELDS
+        .into_iter()
+        .zip(changed)
+        .filter_map(|(field, changed)| changed.then_some(field))
+        .collect()
+}
+
+// the `SUBTASK_FIELDS` that differ between two versions of a subtask
+pub(crate) fn changed_subtask_fields(old: &SubTask, new: &SubTask) -> Vec<&'static str> {
+    let changed = [old.what != new.what, old.link != new.link];
+    SUBTASK_FIELDS
+        .into_iter()
+        .zip(changed)
+        .filter_map(|(field, changed)| changed.then_some(field))
+        .collect()
+}
+
+pub(crate) fn check_desc(desc: &str) -> TodoResult<()> {
+    if desc.trim().is_empty() {
+        return Err(TodoError::InvalidInput(
+            "the description is empty".to_owned(),
+        ));
+    }
+    Ok(())
 }
@@ -0,0 +1,154 @@
+use std::fmt::Display;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
+
+use crate::models::NewTask;
+
+/// Which csv column feeds which task field, e.g. `what=Title,link=URL,priority=Prio`.
+/// Fields left out of the spec are read from a column with the same name, if any.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ColumnMap {
+    pub what: String,
+    pub link: Option<String>,
+    pub priority: Option<String>,
+}
+
+impl Default for ColumnMap {
+    fn default() -> Self {
+        ColumnMap {
+            what: "what".to_owned(),
+            link: None,
+            priority: None,
+        }
+    }
+}
+
+impl FromStr for ColumnMap {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let mut map = ColumnMap::default();
+        for pair in spec.split(',').filter(|p| !p.trim().is_empty()) {
+            let (field, column) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("invalid mapping `{}`, expect <field>=<column>", pair))?;
+            let column = column.trim().to_owned();
+            if column.is_empty() {
+                bail!("empty column name for field `{}`", field.trim());
+            }
+            match field.trim() {
+                "what" => map.what = column,
+                "link" => map.link = Some(column),
+                "priority" => map.priority = Some(column),
+                other => bail!(
+                    "unknown task field `{}`, expect one of what, link, priority",
+                    other
+                ),
+            }
+        }
+        Ok(map)
+    }
+}
+
+/// A csv record that could not be turned into a task.
+#[derive(Debug, PartialEq, Eq)]
+pub struct LineError {
+    pub line: u64,
+    pub message: String,
+}
+
+impl Display for LineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Tasks read from a csv file; nothing should be imported unless `errors` is empty.
+#[derive(Default)]
+pub struct ParsedTasks {
+    pub tasks: Vec<NewTask>,
+    pub errors: Vec<LineError>,
+}
+
+pub fn write_csv<T: Serialize, W: Write>(out: W, records: &[T]) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(out);
+    for record in records {
+        writer.serialize(record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn read_tasks_csv<R: Read>(input: R, map: &ColumnMap) -> Result<ParsedTasks> {
+    let mut reader = csv::Reader::from_reader(input);
+    let headers = reader.headers().context("fail to read csv header")?.clone();
+    let column = |name: &str| headers.iter().position(|h| h.trim() == name);
+    // an explicitly mapped column must exist, an implicit one is optional
+    let optional_column = |mapped: &Option<String>, default: &str| match mapped {
+        Some(name) => column(name)
+            .map(Some)
+            .ok_or_else(|| anyhow!("csv has no column named `{}`", name)),
+        None => Ok(column(default)),
+    };
+
+    let what_idx =
+        column(&map.what).ok_or_else(|| anyhow!("csv has no column named `{}`", map.what))?;
+    let link_idx = optional_column(&map.link, "link")?;
+    let priority_idx = optional_column(&map.priority, "priority")?;
+
+    let mut parsed = ParsedTasks::default();
+    for record in reader.records() {
+        let record = match record {
+            Ok(r) => r,
+            Err(e) => {
+                let line = e.position().map(|p| p.line()).unwrap_or(0);
+                parsed.errors.push(LineError {
+                    line,
+                    message: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let line = record.position().map(|p| p.line()).unwrap_or(0);
+        let field = |idx: Option<usize>| {
+            idx.and_then(|i| record.get(i))
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+        };
+
+        let what = match field(Some(what_idx)) {
+            Some(w) => w.to_owned(),
+            None => {
+                parsed.errors.push(LineError {
+                    line,
+                    message: format!("empty description in column `{}`", map.what),
+                });
+                continue;
+            }
+        };
+        let priority = match field(priority_idx).map(str::parse::<u32>) {
+            None => 5,
+            Some(Ok(p)) => p as i32,
+            Some(Err(_)) => {
+                parsed.errors.push(LineError {
+                    line,
+                    message: format!(
+                        "priority `{}` is not a non-negative integer",
+                        field(priority_idx).unwrap_or_default()
+                    ),
+                });
+                continue;
+            }
+        };
+        parsed.tasks.push(NewTask {
+            what,
+            link: field(link_idx).map(str::to_owned),
+            priority,
+            due: None,
+        });
+    }
+    Ok(parsed)
+}
@@ -0,0 +1,313 @@
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::Frame;
+
+use crate::error::{TodoError, TodoResult};
+use crate::models::{History, NewTask, SubTask, Task};
+use crate::taskdb::TaskDB;
+
+// how many finished tasks the history pane shows
+const HISTORY_LEN: u32 = 20;
+
+const HELP: &str =
+    "j/k move  a add  e edit  p priority  +/- bump  f finish  d delete  / filter  r reload  q quit";
+
+/// What the keys are typed into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mode {
+    Normal,
+    // the filter narrows the list while it is typed
+    Filter,
+    Add(String),
+    Edit(String),
+    Priority(String),
+    ConfirmDelete,
+}
+
+/// The state of the terminal UI. Every change goes through the `TaskDB`,
+/// after which the panes are reloaded from it.
+pub struct App<'a> {
+    db: &'a mut dyn TaskDB,
+    pub tasks: Vec<Task>,
+    pub subtasks: Vec<SubTask>,
+    pub history: Vec<History>,
+    pub selected: usize,
+    pub filter: String,
+    pub mode: Mode,
+    // the outcome of the last action, shown at the bottom
+    pub status: String,
+    pub quit: bool,
+}
+
+impl<'a> App<'a> {
+    pub fn new(db: &'a mut dyn TaskDB) -> TodoResult<Self> {
+        let mut app = App {
+            db,
+            tasks: vec![],
+            subtasks: vec![],
+            history: vec![],
+            selected: 0,
+            filter: String::new(),
+            mode: Mode::Normal,
+            status: String::new(),
+            quit: false,
+        };
+        app.reload()?;
+        Ok(app)
+    }
+
+    pub fn selected_task(&self) -> Option<&Task> {
+        self.tasks.get(self.selected)
+    }
+
+    fn reload(&mut self) -> TodoResult<()> {
+        let pattern = (!self.filter.is_empty()).then(|| self.filter.clone());
+        let mut tasks = self.db.get_tasks(pattern)?;
+        tasks.sort_by_key(|t| t.priority);
+        self.tasks = tasks;
+        self.selected = self.selected.min(self.tasks.len().saturating_sub(1));
+        self.subtasks = match self.selected_task() {
+            Some(task) => self.db.get_subtasks(task.id)?,
+            None => vec![],
+        };
+        self.history = self.db.get_finished(HISTORY_LEN)?;
+        Ok(())
+    }
+
+    /// Act on a key; a failed store call is reported in `status`.
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return;
+        }
+        let result = match self.mode.clone() {
+            Mode::Normal => self.normal_key(key.code),
+            Mode::Filter => self.filter_key(key.code),
+            Mode::ConfirmDelete => {
+                self.mode = Mode::Normal;
+                match key.code {
+                    KeyCode::Char('y') => {
+                        self.on_selected("deleted", |db, id| db.remove_task(id).map(|_| ()))
+                    }
+                    _ => Ok(()),
+                }
+            }
+            Mode::Add(input) | Mode::Edit(input) | Mode::Priority(input) => {
+                self.input_key(key.code, input)
+            }
+        };
+        if let Err(e) = result.and_then(|_| self.reload()) {
+            self.status = e.to_string();
+        }
+    }
+
+    fn normal_key(&mut self, code: KeyCode) -> TodoResult<()> {
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Char('j') | KeyCode::Down if self.selected + 1 < self.tasks.len() => {
+                self.selected += 1
+            }
+            KeyCode::Char('k') | KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Char('g') | KeyCode::Home => self.selected = 0,
+            KeyCode::Char('G') | KeyCode::End => self.selected = self.tasks.len().saturating_sub(1),
+            KeyCode::Char('/') => self.mode = Mode::Filter,
+            KeyCode::Char('a') => self.mode = Mode::Add(String::new()),
+            KeyCode::Char('e') => {
+                if let Some(task) = self.selected_task() {
+                    self.mode = Mode::Edit(task.what.clone());
+                }
+            }
+            KeyCode::Char('p') => {
+                if let Some(task) = self.selected_task() {
+                    self.mode = Mode::Priority(task.priority.to_string());
+                }
+            }
+            KeyCode::Char('+') => self.bump_priority(1)?,
+            KeyCode::Char('-') => self.bump_priority(-1)?,
+            KeyCode::Char('f') => {
+                self.on_selected("finished", |db, id| db.finish_task(id).map(|_| ()))?
+            }
+            KeyCode::Char('d') if self.selected_task().is_some() => self.mode = Mode::ConfirmDelete,
+            KeyCode::Char('r') => self.status.clear(),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn filter_key(&mut self, code: KeyCode) -> TodoResult<()> {
+        match code {
+            KeyCode::Enter => self.mode = Mode::Normal,
+            KeyCode::Esc => {
+                self.filter.clear();
+                self.mode = Mode::Normal;
+            }
+            KeyCode::Backspace => {
+                self.filter.pop();
+            }
+            KeyCode::Char(c) => self.filter.push(c),
+            _ => {}
+        }
+        self.selected = 0;
+        Ok(())
+    }
+
+    fn input_key(&mut self, code: KeyCode, mut input: String) -> TodoResult<()> {
+        match code {
+            KeyCode::Esc => {
+                self.mode = Mode::Normal;
+                return Ok(());
+            }
+            KeyCode::Enter => {
+                let mode = std::mem::replace(&mut self.mode, Mode::Normal);
+                return self.submit(mode, input);
+            }
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Char(c) => input.push(c),
+            _ => {}
+        }
+        self.mode = match self.mode {
+            Mode::Add(_) => Mode::Add(input),
+            Mode::Edit(_) => Mode::Edit(input),
+            _ => Mode::Priority(input),
+        };
+        Ok(())
+    }
+
+    fn submit(&mut self, mode: Mode, input: String) -> TodoResult<()> {
+        match mode {
+            Mode::Add(_) => {
+                let id = self.db.add_task(NewTask {
+                    what: input,
+                    link: None,
+                    priority: 5,
+                    due: None,
+                })?;
+                self.status = format!("added task {}", id);
+            }
+            Mode::Edit(_) => self.on_selected("edited", |db, id| {
+                db.update_task_desc(id, input).map(|_| ())
+            })?,
+            Mode::Priority(_) => {
+                let priority = input
+                    .trim()
+                    .parse()
+                    .map_err(|_| TodoError::InvalidInput(format!("{} is not a priority", input)))?;
+                self.set_priority(priority)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn bump_priority(&mut self, by: i32) -> TodoResult<()> {
+        match self.selected_task() {
+            Some(task) => self.set_priority(task.priority + by),
+            None => Ok(()),
+        }
+    }
+
+    fn set_priority(&mut self, priority: i32) -> TodoResult<()> {
+        self.on_selected("reprioritized", |db, id| {
+            db.update_task_priority(id, priority).map(|_| ())
+        })?;
+        // keep the cursor on the task as it moves in the list
+        if let Some(id) = self.selected_task().map(|t| t.id) {
+            self.reload()?;
+            self.selected = self.tasks.iter().position(|t| t.id == id).unwrap_or(0);
+        }
+        Ok(())
+    }
+
+    fn on_selected(
+        &mut self,
+        done: &str,
+        f: impl FnOnce(&mut dyn TaskDB, i32) -> TodoResult<()>,
+    ) -> TodoResult<()> {
+        if let Some(id) = self.selected_task().map(|t| t.id) {
+            f(self.db, id)?;
+            self.status = format!("{} task {}", done, id);
+        }
+        Ok(())
+    }
+
+    pub fn draw(&self, frame: &mut Frame) {
+        let [panes, history, footer] = Layout::vertical([
+            Constraint::Min(5),
+            Constraint::Length(8),
+            Constraint::Length(2),
+        ])
+        .areas(frame.area());
+        let [tasks, subtasks] =
+            Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+                .areas(panes);
+
+        let title = if self.filter.is_empty() {
+            "tasks".to_owned()
+        } else {
+            format!("tasks matching {}", self.filter)
+        };
+        let items: Vec<ListItem> = self
+            .tasks
+            .iter()
+            .map(|t| ListItem::new(format!("{:>3} [{}] {}", t.id, t.priority, t.what)))
+            .collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut state = ListState::default();
+        state.select((!self.tasks.is_empty()).then_some(self.selected));
+        frame.render_stateful_widget(list, tasks, &mut state);
+
+        let items: Vec<ListItem> = self
+            .subtasks
+            .iter()
+            .map(|st| ListItem::new(format!("{:>3} {}", st.subtask_rank, st.what)))
+            .collect();
+        let list = List::new(items).block(Block::default().borders(Borders::ALL).title("subtasks"));
+        frame.render_widget(list, subtasks);
+
+        let items: Vec<ListItem> = self
+            .history
+            .iter()
+            .map(|h| ListItem::new(h.to_string()))
+            .collect();
+        let list = List::new(items).block(Block::default().borders(Borders::ALL).title("history"));
+        frame.render_widget(list, history);
+
+        let prompt = match &self.mode {
+            Mode::Normal => self.status.clone(),
+            Mode::Filter => format!("/{}", self.filter),
+            Mode::Add(input) => format!("new task: {}", input),
+            Mode::Edit(input) => format!("description: {}", input),
+            Mode::Priority(input) => format!("priority: {}", input),
+            Mode::ConfirmDelete => "delete the task? y/n".to_owned(),
+        };
+        let footer_text = vec![Line::from(prompt), Line::from(HELP)];
+        frame.render_widget(Paragraph::new(footer_text), footer);
+    }
+}
+
+/// Run the terminal UI on `db` until it is quit.
+pub fn run(db: &mut dyn TaskDB) -> Result<()> {
+    let mut app = App::new(db)?;
+    let mut terminal = ratatui::init();
+    let result = (|| -> Result<()> {
+        while !app.quit {
+            terminal.draw(|frame| app.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    app.handle_key(key);
+                }
+            }
+        }
+        Ok(())
+    })();
+    ratatui::restore();
+    result
+}
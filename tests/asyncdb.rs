@@ -0,0 +1,90 @@
+#![cfg(feature = "async")]
+use tempfile::TempDir;
+use todo::asyncdb::{AsyncTaskDB, AsyncTaskSqlite};
+use todo::error::TodoError;
+use todo::models::NewTask;
+
+fn open(dir: &TempDir) -> AsyncTaskSqlite {
+    let path = dir.path().join("todo.db");
+    AsyncTaskSqlite::open(path.to_str().unwrap(), 4).unwrap()
+}
+
+fn task(what: &str) -> NewTask {
+    NewTask {
+        what: what.to_owned(),
+        link: None,
+        priority: 5,
+        due: None,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_requests_share_the_store() {
+    let dir = TempDir::new().unwrap();
+    let db = open(&dir);
+    let writes: Vec<_> = (0..16)
+        .map(|i| {
+            let db = db.clone();
+            tokio::spawn(async move { db.add_task(task(&format!("task {}", i))).await })
+        })
+        .collect();
+    let reads: Vec<_> = (0..16)
+        .map(|_| {
+            let db = db.clone();
+            tokio::spawn(async move { db.get_tasks(None).await })
+        })
+        .collect();
+    let mut ids = vec![];
+    for write in writes {
+        ids.push(write.await.unwrap().unwrap());
+    }
+    // writes are serialized, so each read sees the first n of them, whole
+    for read in reads {
+        let seen = read.await.unwrap().unwrap();
+        let mut seen_ids: Vec<i32> = seen.iter().map(|t| t.id).collect();
+        seen_ids.sort_unstable();
+        assert_eq!(seen_ids, (1..=seen.len() as i32).collect::<Vec<_>>());
+    }
+    ids.sort_unstable();
+    assert_eq!(ids, (1..=16).collect::<Vec<_>>());
+    // each writer's task is there, once
+    let mut whats: Vec<String> = db
+        .get_tasks(None)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|t| t.what)
+        .collect();
+    whats.sort_unstable();
+    let mut written: Vec<String> = (0..16).map(|i| format!("task {}", i)).collect();
+    written.sort_unstable();
+    assert_eq!(whats, written);
+}
+
+#[tokio::test]
+async fn batches_and_transactions_are_all_or_nothing() {
+    let dir = TempDir::new().unwrap();
+    let db = open(&dir);
+    db.add_task(task("aria")).await.unwrap();
+    db.add_task(task("amanchu")).await.unwrap();
+    assert!(matches!(
+        db.finish_tasks(vec![1, 9]).await,
+        Err(TodoError::Missing { ids, .. }) if ids == [9]
+    ));
+    let result = db
+        .transaction(Box::new(|db| {
+            db.remove_task(2)?;
+            db.add_task(task("")).map(|_| ())
+        }))
+        .await;
+    assert!(matches!(result, Err(TodoError::InvalidInput(_))));
+    assert_eq!(db.get_tasks(None).await.unwrap().len(), 2);
+
+    let history = db.finish_task(1).await.unwrap();
+    assert_eq!(history.what, "aria");
+    assert_eq!(db.get_finished(5).await.unwrap(), [history]);
+    assert!(matches!(
+        db.get_subtasks(1).await,
+        Err(TodoError::NotFound { id: 1, .. })
+    ));
+}
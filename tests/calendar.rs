@@ -0,0 +1,112 @@
+use chrono::{FixedOffset, NaiveDate, TimeZone};
+use todo::calendar::{Agenda, Calendar, Month};
+use todo::display::{print_agenda, Theme};
+use todo::models::{History, Task};
+
+fn task(id: i32, priority: i32, due: Option<&str>) -> Task {
+    Task {
+        id,
+        what: format!("task {}", id),
+        link: None,
+        priority,
+        uid: format!("task-{}", id),
+        created_at: 0,
+        due: due.map(str::to_owned),
+        updated_at: 0,
+    }
+}
+
+fn day(y: i32, m: u32, d: u32) -> NaiveDate {
+    NaiveDate::from_ymd(y, m, d)
+}
+
+#[test]
+fn agenda_groups_by_day() {
+    let tasks = [
+        task(1, 5, Some("2026-10-21")),
+        task(2, 9, Some("2026-10-21")),
+        task(3, 5, Some("2026-10-10")),
+        task(4, 5, Some("2026-10-19")),
+        task(5, 5, Some("2026-10-26")),
+        task(6, 5, None),
+        task(7, 5, Some("2026-09-01")),
+    ];
+    let agenda = Agenda::new(&tasks, day(2026, 10, 19), 7).unwrap();
+    let ids = |tasks: &[Task]| tasks.iter().map(|t| t.id).collect::<Vec<_>>();
+    assert_eq!(ids(&agenda.overdue), [7, 3]);
+    let days: Vec<(NaiveDate, Vec<i32>)> = agenda
+        .days
+        .iter()
+        .map(|(day, tasks)| (*day, ids(tasks)))
+        .collect();
+    assert_eq!(
+        days,
+        [
+            (day(2026, 10, 19), vec![4]),
+            (day(2026, 10, 21), vec![2, 1])
+        ]
+    );
+}
+
+#[test]
+fn agenda_past_the_last_date() {
+    let err = Agenda::new(&[], day(2026, 10, 19), u32::MAX).unwrap_err();
+    assert_eq!(err.exit_code(), 4);
+}
+
+#[test]
+fn agenda_lines_are_cut_before_they_are_painted() {
+    let tasks = [task(3, 5, Some("2026-10-10"))];
+    let agenda = Agenda::new(&tasks, day(2026, 10, 19), 7).unwrap();
+    let theme = Theme::default();
+    let label = theme.priority_label(5);
+    assert_eq!(
+        print_agenda(&agenda, None, &theme),
+        format!(
+            "overdue\n  3 {} task 3, {}\n",
+            theme.paint(label, theme.priority_color(5)),
+            theme.paint("due 2026-10-10", theme.overdue_color())
+        )
+    );
+    // the escapes take no room, and the cut due date is still closed
+    assert_eq!(
+        print_agenda(&agenda, Some(20), &theme),
+        format!(
+            "overdue\n  3 {} task 3, {}…\n",
+            theme.paint(label, theme.priority_color(5)),
+            theme.paint("due ", theme.overdue_color())
+        )
+    );
+}
+
+#[test]
+fn calendar_counts_per_day() {
+    let month: Month = "2026-10".parse().unwrap();
+    assert_eq!(month.days().len(), 31);
+    assert!("2026-13".parse::<Month>().is_err());
+
+    let tokyo = FixedOffset::east(9 * 3600);
+    let finished = |id: i32, d: u32, h: u32| History {
+        id,
+        what: "done".to_owned(),
+        link: None,
+        finish_timestamp: tokyo.ymd(2026, 10, d).and_hms(h, 0, 0).timestamp() as i32,
+        uid: format!("history-{}", id),
+        created_at: 0,
+        priority: None,
+    };
+    let tasks = [
+        task(1, 5, Some("2026-10-31")),
+        task(2, 5, Some("2026-11-01")),
+    ];
+    let histories = [finished(1, 1, 1), finished(2, 1, 12)];
+    let calendar = Calendar::new(month, &tasks, &histories, &tokyo);
+    assert_eq!(
+        (calendar.due[30], calendar.due.iter().sum::<usize>()),
+        (1, 1)
+    );
+    assert_eq!(calendar.finished[0], 2);
+    // an hour past midnight in Tokyo is still september in UTC
+    let calendar = Calendar::new(month, &tasks, &histories, &FixedOffset::east(0));
+    assert_eq!(calendar.finished[0], 1);
+}
@@ -1,9 +1,37 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{Child, Stdio};
+
 use assert_cmd::assert::Assert;
 use assert_cmd::Command;
+use predicates::prelude::*;
 use predicates::str::contains;
+use tempfile::TempDir;
+
+const BINARY_NAME: &str = "todo";
+
+// every test gets its own sqlite file so that they can run concurrently
+struct TestDb {
+    dir: TempDir,
+}
+
+impl TestDb {
+    fn new() -> Self {
+        TestDb {
+            dir: TempDir::new().unwrap(),
+        }
+    }
 
-const BINARY_NAME: &'static str = "todo";
-const TODO_DB: &'static str = "./todo.db";
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    fn url(&self) -> PathBuf {
+        self.path("todo.db")
+    }
+}
 
 #[test]
 fn cli_no_args() {
@@ -12,62 +40,1149 @@ fn cli_no_args() {
 
 #[test]
 fn cli_list() {
-    add_task("aria", None);
-    list_tasks_contains("aria");
-    add_task("yokohama kaidashi kikou", Some("test"));
-    list_tasks_contains("aria");
-    list_tasks_contains("kaidashi");
-    list_tasks_contains("test");
-    finish_task(1, true);
-    finish_task(2, true);
+    let db = TestDb::new();
+    add_task(&db, "aria", None);
+    list_tasks_contains(&db, "aria");
+    add_task(&db, "yokohama kaidashi kikou", Some("test"));
+    list_tasks_contains(&db, "aria");
+    list_tasks_contains(&db, "kaidashi");
+    list_tasks_contains(&db, "test");
+    finish_task(&db, 1, true);
+    finish_task(&db, 2, true);
+}
+
+#[test]
+fn cli_sort_and_page() {
+    let db = TestDb::new();
+    command_assert(&db, &["add", "-p", "7", "aria"]).success();
+    command_assert(&db, &["add", "-p", "2", "undine"]).success();
+    command_assert(&db, &["add", "-p", "7", "amanchu"]).success();
+    command_assert(&db, &["list", "--picker"])
+        .success()
+        .stdout("2\t2\tundine\t\n1\t7\taria\t\n3\t7\tamanchu\t\n");
+    command_assert(&db, &["list", "--picker", "--sort", "priority:desc,alpha"])
+        .success()
+        .stdout("3\t7\tamanchu\t\n1\t7\taria\t\n2\t2\tundine\t\n");
+    command_assert(
+        &db,
+        &[
+            "list", "--picker", "--sort", "id", "--limit", "1", "--offset", "1",
+        ],
+    )
+    .success()
+    .stdout("2\t2\tundine\t\n");
+    command_assert(&db, &["list", "--sort", "finished"]).code(4);
+    command_assert(&db, &["list", "--sort", "size"]).code(2);
+
+    command_assert(&db, &["fin", "1", "2", "3"]).success();
+    command_assert(&db, &["history", "--sort", "alpha", "--limit", "2"])
+        .success()
+        .stdout(
+            contains("amanchu")
+                .and(contains("aria"))
+                .and(contains("undine").not()),
+        );
+    command_assert(&db, &["history", "--sort", "alpha", "--offset", "2"])
+        .success()
+        .stdout(
+            contains("2          ")
+                .and(contains("undine"))
+                .and(contains("aria").not()),
+        );
+    command_assert(&db, &["history", "--sort", "due"]).code(4);
+    command_assert(&db, &["history", "-n", "1"])
+        .success()
+        .stdout(contains("lead").and(contains(" 0m ")));
+}
+
+#[test]
+fn cli_stats() {
+    let db = TestDb::new();
+    command_assert(&db, &["add", "-p", "9", "plan the #work week"]).success();
+    command_assert(&db, &["add", "water the #garden"]).success();
+    command_assert(&db, &["fin", "1", "2"]).success();
+    command_assert(
+        &db,
+        &["--theme", "plain", "stats", "--by", "week", "--last", "2"],
+    )
+    .success()
+    .stdout(contains("finished per week"))
+    .stdout(contains("total: 2 tasks in 2 weeks"))
+    .stdout(contains("streak: 1 day, longest 1 day"))
+    .stdout(contains("urgent 1 #"))
+    .stdout(contains("#work   1 #"));
+    command_assert(&db, &["stats", "--by", "year"]).code(2);
+}
+
+#[test]
+fn cli_report() {
+    let db = TestDb::new();
+    command_assert(&db, &["add", "ship the #work release"]).success();
+    command_assert(&db, &["add", "water the plants"]).success();
+    command_assert(&db, &["add", "--due", "2020-01-01", "renew the passport"]).success();
+    command_assert(&db, &["add", "-p", "9", "move house"]).success();
+    command_assert(&db, &["fin", "1", "2"]).success();
+    command_assert(&db, &["-t", "4", "add", "pack the books"]).success();
+    command_assert(&db, &["-t", "4", "add", "pack the kitchen"]).success();
+    command_assert(&db, &["-t", "4", "fin", "1"]).success();
+    command_assert(&db, &["report"])
+        .success()
+        .stdout(contains(
+            "## Finished (2)\n### #work\n- ship the #work release\n",
+        ))
+        .stdout(contains("### untagged\n- water the plants\n"))
+        .stdout(contains("## In progress (1)\n- 4 move house (1/2)\n"))
+        .stdout(contains(
+            "## Overdue (1)\n- 3 renew the passport (priority 5, due 2020-01-01)\n",
+        ))
+        .stdout(contains("## Up next\n- 4 move house (priority 9)\n"));
+    command_assert(
+        &db,
+        &["report", "--format", "text", "--until", "2020-01-07"],
+    )
+    .success()
+    .stdout(contains(
+        "Report 2020-01-01 to 2020-01-07\n\nFinished (0)\n  - nothing\n",
+    ));
+    // days before 1970 cover all of the history rather than wrap around
+    command_assert(&db, &["report", "--since", "1900-01-01"])
+        .success()
+        .stdout(contains("## Finished (2)\n"));
+    command_assert(&db, &["report", "--format", "html"]).code(2);
+}
+
+#[test]
+fn cli_agenda_and_calendar() {
+    let db = TestDb::new();
+    command_assert(&db, &["add", "--due", "2020-01-01", "renew the passport"]).success();
+    command_assert(&db, &["add", "--due", "2020-01-15", "see the dentist"]).success();
+    command_assert(&db, &["--theme", "plain", "agenda"])
+        .success()
+        .stdout(contains(
+            "overdue\n  1 mid renew the passport, due 2020-01-01\n",
+        ));
+    command_assert(&db, &["calendar", "--month", "2020-01"])
+        .success()
+        .stdout(contains("January 2020\n    Mon    Tue    Wed"))
+        .stdout(contains("\n     13     14     15"))
+        .stdout(contains("2 due, 0 finished this month"));
+    command_assert(&db, &["calendar", "--month", "jan"]).code(2);
+}
+
+#[test]
+fn cli_stale() {
+    let db = TestDb::new();
+    add_task(&db, "aria", None);
+    command_assert(&db, &["list", "--picker", "--stale", "1d"])
+        .success()
+        .stdout("");
+    command_assert(&db, &["list", "--stale", "soon"]).code(2);
 }
 
 #[test]
 fn cli_tidy() {
-    add_task("a", None);
-    add_task("b", None);
-    add_task("c", None);
-    finish_task(2, true);
+    let db = TestDb::new();
+    add_task(&db, "a", None);
+    add_task(&db, "b", None);
+    add_task(&db, "c", None);
+    finish_task(&db, 2, true);
     // after finish 2, 1 and 3 left
-    tidy();
+    tidy(&db);
     // after tidy, only 1 and 2! no 3
-    finish_task(3, false);
-    finish_task(1, true);
-    finish_task(2, true);
+    finish_task(&db, 3, false);
+    finish_task(&db, 1, true);
+    finish_task(&db, 2, true);
+}
+
+#[test]
+fn cli_tidy_keeps_uids() {
+    let db = TestDb::new();
+    add_task(&db, "a", None);
+    add_task(&db, "b", None);
+    finish_task(&db, 1, true);
+    let export = command_assert(&db, &["export"]).success();
+    let csv = String::from_utf8(export.get_output().stdout.clone()).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    let column = lines[0].split(',').position(|c| c == "uid").unwrap();
+    let uid = lines[1].split(',').nth(column).unwrap().to_owned();
+    tidy(&db);
+    // sync matches tasks by uid, renumbering must not change it
+    command_assert(&db, &["export"])
+        .success()
+        .stdout(contains(format!("1,b,,5,{}", uid)));
+}
+
+#[test]
+fn cli_export_csv() {
+    let db = TestDb::new();
+    add_task(&db, "aria", Some("https://aria.example"));
+    command_assert(&db, &["-t", "1", "add", "undine"]).success();
+    command_assert(&db, &["export"])
+        .success()
+        .stdout(contains("id,what,link,priority"))
+        .stdout(contains("1,aria,https://aria.example,5"));
+    command_assert(&db, &["export", "--table", "subtasks"])
+        .success()
+        .stdout(contains("id,what,link,subtask_rank,task_id"))
+        .stdout(contains("1,undine,,1,1"));
+    finish_task(&db, 1, true);
+    command_assert(&db, &["export", "--table", "history"])
+        .success()
+        .stdout(contains("id,what,link,finish_timestamp"))
+        .stdout(contains("1,aria,https://aria.example,"));
+}
+
+#[test]
+fn cli_import_csv() {
+    let db = TestDb::new();
+    let csv = db.path("tasks.csv");
+    fs::write(
+        &csv,
+        "Title,URL,Prio\nwater the plants,,3\nread,https://read.example,\n",
+    )
+    .unwrap();
+    command_assert(
+        &db,
+        &[
+            "import",
+            csv.to_str().unwrap(),
+            "--map",
+            "what=Title,link=URL,priority=Prio",
+        ],
+    )
+    .success()
+    .stdout(contains("imported 2 task(s)"));
+    list_tasks_contains(&db, "water the plants");
+    list_tasks_contains(&db, "https://read.example");
+}
+
+#[test]
+fn cli_import_csv_reports_invalid_lines() {
+    let db = TestDb::new();
+    let csv = db.path("tasks.csv");
+    fs::write(&csv, "what,priority\nok,1\n,2\nbad,high\n").unwrap();
+    command_assert(&db, &["import", csv.to_str().unwrap()])
+        .failure()
+        .stderr(contains("line 3: empty description"))
+        .stderr(contains("line 4: priority `high`"));
+    // nothing is imported when any line is invalid
+    command_assert(&db, &["list"])
+        .success()
+        .stdout(contains("ok").not());
+}
+
+#[test]
+fn cli_backup_restore() {
+    let db = TestDb::new();
+    let backup = db.path("backup.json");
+    let backup = backup.to_str().unwrap();
+    add_task(&db, "aria", Some("https://aria.example"));
+    add_task(&db, "amanchu", None);
+    command_assert(&db, &["-t", "1", "add", "undine"]).success();
+    finish_task(&db, 2, true);
+    command_assert(&db, &["backup", backup])
+        .success()
+        .stdout(contains("1 task(s), 1 subtask(s), 1 history record(s)"));
+
+    // into an empty store
+    let other = TestDb::new();
+    command_assert(&other, &["restore", backup])
+        .success()
+        .stdout(contains("3 inserted"));
+    list_tasks_contains(&other, "aria");
+    command_assert(&other, &["-t", "1", "list"])
+        .success()
+        .stdout(contains("undine"));
+    command_assert(&other, &["history", "-n", "10"])
+        .success()
+        .stdout(contains("amanchu"));
+
+    // restoring the same backup again changes nothing
+    command_assert(&other, &["restore", backup])
+        .success()
+        .stdout(contains("3 unchanged"));
+
+    // a different task with the same id is a conflict
+    command_assert(&other, &["fin", "1"]).success();
+    add_task(&other, "arietta", None);
+    command_assert(&other, &["restore", backup])
+        .failure()
+        .stderr(contains("task 1"));
+    command_assert(&other, &["restore", backup, "--on-conflict", "skip"])
+        .success()
+        .stdout(contains("1 skipped"));
+    list_tasks_contains(&other, "arietta");
+    command_assert(&other, &["restore", backup, "--on-conflict", "replace"])
+        .success()
+        .stdout(contains("1 replaced"));
+    list_tasks_contains(&other, "aria");
+}
+
+#[test]
+fn cli_restore_rejects_malformed_backup() {
+    let db = TestDb::new();
+    let backup = db.path("backup.json");
+    fs::write(&backup, r#"{"format": "something-else"}"#).unwrap();
+    command_assert(&db, &["restore", backup.to_str().unwrap()])
+        .failure()
+        .stderr(contains("malformed backup file"));
+}
+
+#[test]
+fn cli_backups_before_destructive_commands() {
+    let db = TestDb::new();
+    add_task(&db, "aria", None);
+    add_task(&db, "amanchu", None);
+    command_assert(&db, &["del", "1"]).success();
+    let listed = command_assert(&db, &["backups", "list"])
+        .success()
+        .stdout(contains("-daily.db"))
+        .stdout(contains("-del.db"));
+    let names = String::from_utf8(listed.get_output().stdout.clone()).unwrap();
+    let before_del = names.lines().find(|n| n.ends_with("-del.db")).unwrap();
+
+    command_assert(&db, &["backups", "restore", before_del]).success();
+    list_tasks_contains(&db, "aria");
+    command_assert(&db, &["backups", "restore", "todo-19700101-000000-nope.db"])
+        .failure()
+        .stderr(contains("no backup named"));
+}
+
+#[test]
+fn cli_backups_keep_last_n() {
+    let db = TestDb::new();
+    for _ in 0..3 {
+        add_task(&db, "aria", None);
+        Command::cargo_bin(BINARY_NAME)
+            .unwrap()
+            .env("TODO_DB", db.url())
+            .env("TODO_BACKUP_KEEP", "2")
+            .args(["tidy"])
+            .assert()
+            .success();
+    }
+    let listed = command_assert(&db, &["backups", "list"]).success();
+    let names = String::from_utf8(listed.get_output().stdout.clone()).unwrap();
+    assert_eq!(names.lines().count(), 2);
+    // two snapshots taken in the same second both stay
+    assert!(names.lines().all(|name| name.ends_with("-tidy.db")));
+
+    // restoring the oldest snapshot at the limit doesn't prune it first
+    let oldest = names.lines().next().unwrap();
+    Command::cargo_bin(BINARY_NAME)
+        .unwrap()
+        .env("TODO_DB", db.url())
+        .env("TODO_BACKUP_KEEP", "2")
+        .args(["backups", "restore", oldest])
+        .assert()
+        .success();
+    list_tasks_contains(&db, "aria");
+}
+
+#[test]
+fn cli_sync_merges_two_devices() {
+    let remote = TestDb::new();
+    let url = remote.path("remote.git");
+    std::process::Command::new("git")
+        .args(["init", "-q", "--bare"])
+        .arg(&url)
+        .status()
+        .unwrap();
+    let url = url.to_str().unwrap();
+
+    let laptop = TestDb::new();
+    add_task(&laptop, "aria", None);
+    command_assert(&laptop, &["sync", "--remote", url])
+        .success()
+        .stdout(contains("pushed"));
+
+    let desktop = TestDb::new();
+    command_assert(&desktop, &["sync", "--remote", url])
+        .success()
+        .stdout(contains("merged remote changes"));
+    list_tasks_contains(&desktop, "aria");
+
+    // edits to different records merge cleanly
+    command_assert(&desktop, &["update", "1", "-p", "1"]).success();
+    add_task(&desktop, "tari tari", None);
+    add_task(&laptop, "amanchu", None);
+    command_assert(&laptop, &["fin", "2"]).success();
+    add_task(&laptop, "hanasaku iroha", None);
+    command_assert(&laptop, &["sync"]).success();
+    command_assert(&desktop, &["sync"]).success();
+    command_assert(&laptop, &["sync"]).success();
+    for db in [&laptop, &desktop] {
+        command_assert(db, &["list"])
+            .success()
+            .stdout(contains("aria"))
+            .stdout(contains("🥶"))
+            .stdout(contains("tari tari"))
+            .stdout(contains("hanasaku iroha"))
+            .stdout(contains("amanchu").not());
+        command_assert(db, &["history", "-n", "10"])
+            .success()
+            .stdout(contains("amanchu"));
+    }
+}
+
+#[test]
+fn cli_sync_keeps_db_on_bad_remote() {
+    let remote = TestDb::new();
+    let url = remote.path("remote.git");
+    let git = |dir: &std::path::Path, args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .current_dir(dir)
+            .args(["-c", "user.name=test", "-c", "user.email=test@localhost"])
+            .args(args)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+    git(remote.dir.path(), &["init", "-q", "--bare", "remote.git"]);
+    let url = url.to_str().unwrap();
+
+    let laptop = TestDb::new();
+    add_task(&laptop, "aria", None);
+    command_assert(&laptop, &["sync", "--remote", url]).success();
+
+    // another device pushes a good task and one whose priority isn't a number
+    git(remote.dir.path(), &["clone", "-q", url, "edit"]);
+    let edit = remote.path("edit");
+    let mut tasks = fs::read_to_string(edit.join("tasks.txt")).unwrap();
+    tasks.push_str("0000\t5\tamanchu\t\t0\t\t0\nffff\tx\tundine\t\t0\t\t0\n");
+    fs::write(edit.join("tasks.txt"), tasks).unwrap();
+    git(&edit, &["commit", "-qam", "bad priority"]);
+    git(&edit, &["push", "-q"]);
+
+    for _ in 0..2 {
+        command_assert(&laptop, &["sync"])
+            .code(4)
+            .stderr(contains("invalid digit"));
+        command_assert(&laptop, &["list"])
+            .success()
+            .stdout(contains("aria"))
+            .stdout(contains("amanchu").not());
+    }
+}
+
+#[test]
+fn cli_sync_fails_on_unreadable_table() {
+    let remote = TestDb::new();
+    let url = remote.path("remote.git");
+    std::process::Command::new("git")
+        .args(["init", "-q", "--bare"])
+        .arg(&url)
+        .status()
+        .unwrap();
+    let url = url.to_str().unwrap();
+
+    let laptop = TestDb::new();
+    add_task(&laptop, "aria", None);
+    command_assert(&laptop, &["sync", "--remote", url]).success();
+    let desktop = TestDb::new();
+    command_assert(&desktop, &["sync", "--remote", url]).success();
+
+    // the tasks both devices started from can't be read any more
+    let sync_dir = desktop.path("sync");
+    let output = std::process::Command::new("git")
+        .current_dir(&sync_dir)
+        .args(["rev-parse", "HEAD:tasks.txt"])
+        .output()
+        .unwrap();
+    let blob = String::from_utf8(output.stdout).unwrap();
+    let blob = blob.trim();
+    fs::remove_file(
+        sync_dir
+            .join(".git/objects")
+            .join(&blob[..2])
+            .join(&blob[2..]),
+    )
+    .unwrap();
+
+    add_task(&laptop, "amanchu", None);
+    command_assert(&laptop, &["sync"]).success();
+    add_task(&desktop, "tari tari", None);
+    // which is an error, not a table with no tasks
+    command_assert(&desktop, &["sync"])
+        .failure()
+        .stderr(contains("tasks.txt"));
+    command_assert(&desktop, &["list"])
+        .success()
+        .stdout(contains("aria"))
+        .stdout(contains("tari tari"))
+        .stdout(contains("amanchu").not());
+}
+
+#[test]
+fn cli_sync_reports_conflicts() {
+    let remote = TestDb::new();
+    let url = remote.path("remote.git");
+    std::process::Command::new("git")
+        .args(["init", "-q", "--bare"])
+        .arg(&url)
+        .status()
+        .unwrap();
+    let url = url.to_str().unwrap();
+
+    let laptop = TestDb::new();
+    add_task(&laptop, "aria", None);
+    command_assert(&laptop, &["sync", "--remote", url]).success();
+    let desktop = TestDb::new();
+    command_assert(&desktop, &["sync", "--remote", url]).success();
+
+    command_assert(&laptop, &["update", "1", "-p", "1"]).success();
+    command_assert(&laptop, &["sync"]).success();
+    command_assert(&desktop, &["update", "1", "-p", "9"]).success();
+    command_assert(&desktop, &["sync"])
+        .failure()
+        .stderr(contains("local priority=\"9\""))
+        .stderr(contains("remote priority=\"1\""));
+    // nothing changed locally
+    list_tasks_contains(&desktop, "🥵");
+    command_assert(&desktop, &["sync", "--prefer", "remote"]).success();
+    list_tasks_contains(&desktop, "🥶");
+}
+
+#[test]
+fn cli_merge_conflict_copy() {
+    let db = TestDb::new();
+    add_task(&db, "aria", None);
+    add_task(&db, "amanchu", None);
+    let copy = TestDb::new();
+    fs::copy(db.url(), copy.url()).unwrap();
+
+    command_assert(&db, &["fin", "1"]).success();
+    add_task(&db, "tari tari", None);
+    command_assert(&db, &["update", "2", "-p", "9"]).success();
+    // times are in seconds, the copy changes task 2 after the db did
+    std::thread::sleep(std::time::Duration::from_secs(1));
+    command_assert(&copy, &["update", "2", "-p", "1"]).success();
+    command_assert(&copy, &["-t", "2", "add", "kodama"]).success();
+    add_task(&copy, "hanasaku iroha", None);
+
+    let other = copy.url();
+    let other = other.to_str().unwrap();
+    command_assert(&db, &["merge", other, "--dry-run"])
+        .success()
+        .stdout(contains("~ task 2: priority 9 -> 1"))
+        .stdout(contains("+ subtask \"kodama\" of task 2"))
+        .stdout(contains("+ task \"hanasaku iroha\""))
+        .stdout(contains("dry run"));
+    list_tasks_contains(&db, "tari tari");
+    command_assert(&db, &["list"])
+        .success()
+        .stdout(contains("hanasaku").not());
+
+    command_assert(&db, &["merge", other])
+        .success()
+        .stdout(contains(
+            "tasks: 1 added, 1 edited, 0 finished; subtasks: 1 added, 0 edited; history: 0 added",
+        ));
+    command_assert(&db, &["list"])
+        .success()
+        .stdout(contains("aria").not())
+        .stdout(contains("🥶"))
+        .stdout(contains("tari tari"))
+        .stdout(contains("hanasaku iroha"));
+    command_assert(&db, &["-t", "2", "list"])
+        .success()
+        .stdout(contains("kodama"));
+
+    // the other way round, the finished task is finished in the copy too
+    let ours = db.url();
+    command_assert(&copy, &["merge", ours.to_str().unwrap()])
+        .success()
+        .stdout(contains("✓ task 1 \"aria\""))
+        .stdout(contains("+ history \"aria\""))
+        .stdout(contains("~ task 2").not());
+}
+
+#[test]
+fn cli_merge_keeps_edits_to_different_fields() {
+    let db = TestDb::new();
+    add_task(&db, "aria", None);
+    let copy = TestDb::new();
+    fs::copy(db.url(), copy.url()).unwrap();
+
+    command_assert(&copy, &["update", "1", "--due", "2030-01-01"]).success();
+    command_assert(&db, &["update", "1", "-p", "9"]).success();
+
+    let other = copy.url();
+    command_assert(&db, &["merge", other.to_str().unwrap()])
+        .success()
+        .stdout(contains("~ task 1: due None -> Some(\"2030-01-01\")"));
+    command_assert(&db, &["list"])
+        .success()
+        .stdout(contains("🥵"))
+        .stdout(contains("2030-01-01"));
+}
+
+#[test]
+fn cli_merge_matches_each_task_once() {
+    let db = TestDb::new();
+    add_task(&db, "aria", None);
+    let other = TestDb::new();
+    add_task(&other, "aria", None);
+    add_task(&other, "aria", None);
+
+    let url = other.url();
+    command_assert(&db, &["merge", url.to_str().unwrap()])
+        .success()
+        .stdout(contains("tasks: 1 added, 0 edited"));
+    let out = command_assert(&db, &["list"])
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(String::from_utf8(out).unwrap().matches("aria").count(), 2);
+}
+
+#[test]
+fn cli_merge_leaves_other_store_alone() {
+    let db = TestDb::new();
+    add_task(&db, "aria", None);
+    // not a todo db, and it must not be made into one
+    let other = db.path("other.db");
+    fs::write(&other, "").unwrap();
+    command_assert(&db, &["merge", other.to_str().unwrap(), "--dry-run"]).code(6);
+    assert_eq!(fs::metadata(&other).unwrap().len(), 0);
+
+    let missing = db.path("missing.db");
+    command_assert(&db, &["merge", missing.to_str().unwrap()]).code(7);
+    assert!(!missing.exists());
+}
+
+#[test]
+fn cli_migrate_store() {
+    let db = TestDb::new();
+    add_task(&db, "aria", Some("https://aria.example"));
+    add_task(&db, "amanchu", None);
+    command_assert(&db, &["-t", "2", "add", "kodama"]).success();
+    command_assert(&db, &["fin", "1"]).success();
+
+    let json = format!("json://{}", db.path("todo.json").display());
+    command_assert(&db, &["migrate-store", &json])
+        .success()
+        .stdout(contains(
+            "migrated 1 task(s), 1 subtask(s), 1 history record(s)",
+        ));
+    let text = fs::read_to_string(db.path("todo.json")).unwrap();
+    let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(value["tasks"][0]["what"], "amanchu");
+    assert_eq!(value["subtasks"][0]["what"], "kodama");
+    assert_eq!(value["histories"][0]["link"], "https://aria.example");
+    // the target has to be empty
+    command_assert(&db, &["migrate-store", &json])
+        .failure()
+        .stderr(contains("not empty"));
+
+    store_assert(&json, &["list"])
+        .success()
+        .stdout(contains("amanchu"));
+    store_assert(&json, &["add", "tari tari"]).success();
+    store_assert(&json, &["fin", "3"]).success();
+    store_assert(&json, &["history", "-n", "5"])
+        .success()
+        .stdout(contains("tari tari"))
+        .stdout(contains("aria"));
+
+    let toml = format!("toml://{}", db.path("todo.toml").display());
+    store_assert(&json, &["migrate-store", &toml]).success();
+    store_assert(&toml, &["-t", "2", "list"])
+        .success()
+        .stdout(contains("kodama"));
+    let text = fs::read_to_string(db.path("todo.toml")).unwrap();
+    assert!(text.contains("[[tasks]]"));
+    assert!(text.starts_with("schema_version = "));
+
+    // rewriting the file keeps its permissions
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let path = db.path("todo.toml");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        store_assert(&toml, &["add", "tari tari"]).success();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o644);
+    }
+
+    // a file from a later version is refused rather than rewritten without what it added
+    let newer = text.replacen("schema_version = \"", "schema_version = \"9", 1);
+    fs::write(db.path("todo.toml"), &newer).unwrap();
+    store_assert(&toml, &["list"])
+        .code(6)
+        .stderr(contains("newer than"));
+}
+
+#[test]
+fn cli_fin_is_all_or_nothing() {
+    let db = TestDb::new();
+    add_task(&db, "aria", None);
+    add_task(&db, "amanchu", None);
+    command_assert(&db, &["fin", "1", "5", "2", "9"])
+        .code(3)
+        .stderr(contains("no task with id 5, 9, nothing changed"));
+    list_tasks_contains(&db, "aria");
+    list_tasks_contains(&db, "amanchu");
+    command_assert(&db, &["del", "2", "3"]).code(3);
+    list_tasks_contains(&db, "amanchu");
+    command_assert(&db, &["fin", "1", "2"]).success();
+    command_assert(&db, &["history", "-n", "5"])
+        .success()
+        .stdout(contains("aria"))
+        .stdout(contains("amanchu"));
+}
+
+#[test]
+fn cli_exit_codes() {
+    let db = TestDb::new();
+    add_task(&db, "aria", None);
+    command_assert(&db, &["fin", "9"])
+        .code(3)
+        .stderr(contains("no task with id 9"));
+    command_assert(&db, &["-t", "9", "list"]).code(3);
+    command_assert(&db, &["update", "9", "-p", "1"])
+        .code(3)
+        .stderr(contains("task 9 not found"));
+    command_assert(&db, &["-t", "1", "add", "undine"]).success();
+    command_assert(&db, &["-t", "1", "del", "1", "5"])
+        .code(3)
+        .stderr(contains("no subtask with id 5"));
+    command_assert(&db, &["-t", "1", "list"])
+        .success()
+        .stdout(contains("undine"));
+    command_assert(&db, &["add", ""]).code(4);
+    command_assert(&db, &["history", "-s", "2022-13-01"])
+        .code(4)
+        .stderr(contains("expected yyyy-mm-dd"));
+    let broken = db.path("broken.json");
+    fs::write(&broken, "{").unwrap();
+    store_assert(&format!("json://{}", broken.display()), &["list"])
+        .code(5)
+        .stderr(contains("fail to parse"));
+    store_assert("json:///nonexistent/dir/todo.json", &["list"]).code(7);
+    command_assert(&db, &["fin", "1"]).code(0);
+}
+
+#[test]
+fn cli_picker() {
+    let db = TestDb::new();
+    add_task(&db, "aria\tthe animation", Some("https://aria.example"));
+    command_assert(&db, &["add", "amanchu", "-p", "1"]).success();
+    command_assert(&db, &["add", "undine"]).success();
+    command_assert(&db, &["-t", "1", "add", "kodama"]).success();
+    command_assert(&db, &["list", "--picker"]).success().stdout(
+        "2\t1\tamanchu\t\n1\t5\taria the animation\thttps://aria.example\n3\t5\tundine\t\n",
+    );
+    command_assert(&db, &["show", "1"])
+        .success()
+        .stdout(contains("link: https://aria.example"))
+        .stdout(contains("aria\tthe animation"))
+        .stdout(contains("kodama"));
+    command_assert(&db, &["show", "9"]).code(3);
+
+    // the lines picked in fzf are piped back
+    Command::cargo_bin(BINARY_NAME)
+        .unwrap()
+        .env("TODO_DB", db.url())
+        .args(["fin", "-"])
+        .write_stdin("2\t1\tamanchu\t\n3\t5\tundine\t\n")
+        .assert()
+        .success();
+    command_assert(&db, &["list", "--picker"])
+        .success()
+        .stdout(predicate::str::starts_with("1\t").and(contains("undine").not()));
+    Command::cargo_bin(BINARY_NAME)
+        .unwrap()
+        .env("TODO_DB", db.url())
+        .args(["update", "-", "-p", "2"])
+        .write_stdin("1\t5\taria\n")
+        .assert()
+        .success();
+    command_assert(&db, &["list", "--picker"])
+        .success()
+        .stdout(contains("1\t2\taria"));
+    Command::cargo_bin(BINARY_NAME)
+        .unwrap()
+        .env("TODO_DB", db.url())
+        .args(["del", "-"])
+        .write_stdin("aria\n")
+        .assert()
+        .code(4);
+}
+
+#[test]
+fn cli_show() {
+    let db = TestDb::new();
+    command_assert(&db, &["note", "water the plants"]).success();
+    add_task(&db, "water the plants", Some("https://plants.example"));
+    command_assert(&db, &["-t", "1", "add", "the cactus too"]).success();
+    command_assert(&db, &["show", "1"])
+        .success()
+        .stdout(contains("task 1, priority 5"))
+        .stdout(contains("created: "))
+        .stdout(contains("1          the cactus too"))
+        .stdout(contains("finished before:"));
+
+    let output = command_assert(&db, &["show", "1", "--json"])
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let details: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(details["task"]["link"], "https://plants.example");
+    assert!(details["task"]["created_at"].as_i64().unwrap() > 0);
+    assert_eq!(details["subtasks"][0]["what"], "the cactus too");
+    assert_eq!(details["history"][0]["what"], "water the plants");
+}
+
+#[test]
+fn cli_tree() {
+    let db = TestDb::new();
+    add_task(&db, "aria", None);
+    command_assert(&db, &["add", "amanchu", "-p", "1"]).success();
+    for subtask in ["undine", "kodama", "sylph"] {
+        command_assert(&db, &["-t", "1", "add", subtask]).success();
+    }
+    command_assert(&db, &["-t", "1", "fin", "1", "3"]).success();
+    command_assert(&db, &["-t", "1", "fin", "4"]).code(3);
+
+    let tree = command_assert(&db, &["list", "--tree"])
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let tree = String::from_utf8(tree).unwrap();
+    let lines: Vec<&str> = tree.lines().collect();
+    assert_eq!(lines.len(), 5);
+    assert!(lines[0].starts_with("2 ") && lines[0].ends_with("amanchu"));
+    assert!(lines[1].starts_with("1 ") && lines[1].ends_with("2/3"));
+    assert!(lines[2].starts_with("  1 ") && lines[2].ends_with("[x] undine"));
+    assert!(lines[3].ends_with(" kodama"));
+    command_assert(&db, &["list", "--tree", "--hide-done"])
+        .success()
+        .stdout(contains("kodama").and(contains("undine").not()));
+    command_assert(&db, &["list", "--tree", "--collapse"])
+        .success()
+        .stdout(contains("2/3").and(contains("kodama").not()));
+    command_assert(&db, &["list", "--collapse"]).failure();
+}
+
+#[test]
+fn cli_due_and_colors() {
+    let db = TestDb::new();
+    command_assert(&db, &["add", "aria", "-p", "9", "--due", "2020-1-3"]).success();
+    command_assert(&db, &["add", "amanchu", "--due", "someday"])
+        .code(4)
+        .stderr(contains("`someday` is not a date"));
+    command_assert(&db, &["add", "amanchu", "--due", "tomorrow 5pm"])
+        .code(4)
+        .stderr(contains("has a time of day"));
+    command_assert(&db, &["add", "amanchu", "--due", "+99999999d"])
+        .code(4)
+        .stderr(contains("`+99999999d` is not a date"));
+    command_assert(&db, &["history", "-s", "4000000000w"]).code(4);
+    command_assert(&db, &["add", "himeya", "--due", "+0d"]).success();
+    command_assert(&db, &["fin", "2"]).success();
+    command_assert(&db, &["history", "-s", "today", "-e", "+1h"])
+        .success()
+        .stdout(contains("himeya"));
+    // before 1970 is from the start
+    command_assert(&db, &["history", "-s", "100000w"])
+        .success()
+        .stdout(contains("himeya"));
+    command_assert(&db, &["history", "-s", "last week", "-e", "yesterday"])
+        .success()
+        .stdout(contains("himeya").not());
+    command_assert(&db, &["add", "amanchu"]).success();
+    command_assert(&db, &["update", "2", "--due", "2099-12-31"]).success();
+    command_assert(&db, &["update", "9", "--due", "2099-12-31"]).code(3);
+
+    // piped output has no colors unless asked for
+    command_assert(&db, &["list"])
+        .success()
+        .stdout(contains("2020-01-03").and(contains("\x1b[").not()));
+    command_assert(&db, &["--color", "always", "list"])
+        .success()
+        .stdout(contains("\x1b[31m2020-01-03\x1b[0m"))
+        .stdout(contains("\x1b[31m🥵\x1b[0m"));
+    command_assert(&db, &["--color", "always", "--theme", "plain", "list"])
+        .success()
+        .stdout(contains("urgent").and(contains("\x1b[").not()));
+    Command::cargo_bin(BINARY_NAME)
+        .unwrap()
+        .env("TODO_DB", db.url())
+        .env("TODO_THEME", "ascii")
+        .env("TODO_PRIORITY_LABELS", ".,-,+,!")
+        .args(["show", "1"])
+        .assert()
+        .success()
+        .stdout(contains("priority 9 !"))
+        .stdout(contains("due: 2020-01-03 (overdue)"));
+
+    command_assert(&db, &["update", "1", "--due", "none"]).success();
+    command_assert(&db, &["show", "1"])
+        .success()
+        .stdout(contains("due:").not());
+}
+
+#[test]
+fn cli_serve() {
+    let db = TestDb::new();
+    let server = Served::start(&db);
+    assert_eq!(server.send("GET", "/tasks", &[], "").0, 401);
+
+    let (status, etag, body) = server.call("POST", "/tasks", None, r#"{"what": "aria"}"#);
+    assert_eq!(status, 201);
+    assert!(body.contains(r#""what":"aria""#));
+    assert!(body.contains(r#""priority":5"#));
+    let etag = etag.unwrap();
+    assert_eq!(
+        server.call("GET", "/tasks/1", None, "").1,
+        Some(etag.clone())
+    );
+    let cached = Some(("If-None-Match", etag.as_str()));
+    assert_eq!(server.call("GET", "/tasks/1", cached, "").0, 304);
+    let patch = r#"{"priority": 1, "link": "https://aria.example", "due": "2026-11-01"}"#;
+    let (status, new_etag, body) =
+        server.call("PATCH", "/tasks/1", Some(("If-Match", &etag)), patch);
+    assert_eq!(status, 200);
+    assert!(body.contains(r#""priority":1"#));
+    assert!(body.contains(r#""due":"2026-11-01""#));
+    assert_ne!(new_etag, Some(etag.clone()));
+    // a client still holding the old tag is refused
+    let (status, _, _) = server.call("POST", "/tasks/1/finish", Some(("If-Match", &etag)), "");
+    assert_eq!(status, 412);
+    list_tasks_contains(&db, "https://aria.example");
+    let (status, _, body) = server.call("PATCH", "/tasks/1", None, r#"{"due": null}"#);
+    assert_eq!(status, 200);
+    assert!(body.contains(r#""due":null"#));
+    assert_eq!(
+        server
+            .call("PATCH", "/tasks/1", None, r#"{"due": "soon"}"#)
+            .0,
+        400
+    );
+
+    let (status, _, body) = server.call("POST", "/tasks/1/subtasks", None, r#"{"what": "undine"}"#);
+    assert_eq!(status, 201);
+    assert!(body.contains(r#""subtask_rank":1"#));
+    assert_eq!(
+        server.call("DELETE", "/tasks/1/subtasks/4", None, "").0,
+        404
+    );
+    assert_eq!(server.call("POST", "/tasks/1/finish", None, "").0, 200);
+    assert!(server.call("GET", "/history", None, "").2.contains("aria"));
+    // a range without an end takes in what was finished this very second
+    assert!(server
+        .call("GET", "/history?start=0", None, "")
+        .2
+        .contains("aria"));
+    assert_eq!(server.call("GET", "/history?offset=1", None, "").2, "[]");
+    assert_eq!(server.call("GET", "/tasks?sort=nonsense", None, "").0, 400);
+    assert_eq!(server.call("GET", "/tasks/1", None, "").0, 404);
+    assert_eq!(
+        server.call("POST", "/tasks", None, r#"{"what": ""}"#).0,
+        400
+    );
+    assert_eq!(server.call("POST", "/tasks", None, "aria").0, 400);
+    assert_eq!(server.call("GET", "/nowhere", None, "").0, 404);
+}
+
+#[test]
+fn cli_rpc() {
+    let db = TestDb::new();
+    add_task(&db, "aria", None);
+    let mut rpc = std::process::Command::new(assert_cmd::cargo::cargo_bin(BINARY_NAME))
+        .env("TODO_DB", db.url())
+        .arg("rpc")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = rpc.stdin.take().unwrap();
+    let mut stdout = BufReader::new(rpc.stdout.take().unwrap());
+    let mut call = |request: &str| {
+        writeln!(stdin, "{}", request).unwrap();
+        let mut line = String::new();
+        stdout.read_line(&mut line).unwrap();
+        line
+    };
+
+    let added =
+        call(r#"{"jsonrpc": "2.0", "method": "add_task", "params": {"what": "amanchu"}, "id": 1}"#);
+    assert_eq!(added.trim(), r#"{"id":1,"jsonrpc":"2.0","result":2}"#);
+    let tasks =
+        call(r#"{"jsonrpc": "2.0", "method": "get_tasks", "params": {"pattern": "ama"}, "id": 2}"#);
+    assert!(tasks.contains(r#""what":"amanchu""#));
+    assert!(!tasks.contains("aria"));
+    let missing =
+        call(r#"{"jsonrpc": "2.0", "method": "finish_tasks", "params": [[1, 9]], "id": 3}"#);
+    assert!(missing.contains(r#""code":-32003"#));
+    // the queries take their arguments by name or by position alike
+    let by_name = call(
+        r#"{"jsonrpc": "2.0", "method": "query_tasks", "params": {"pattern": "ama", "limit": 1}, "id": 6}"#,
+    );
+    let by_position = call(
+        r#"{"jsonrpc": "2.0", "method": "query_tasks", "params": [{"pattern": "ama"}, "priority", {"limit": 1}], "id": 6}"#,
+    );
+    assert!(by_name.contains(r#""what":"amanchu""#));
+    assert_eq!(by_name, by_position);
+    let finished = call(
+        r#"{"jsonrpc": "2.0", "method": "query_finished", "params": [0, 2147483647], "id": 7}"#,
+    );
+    assert!(finished.contains(r#""result":[]"#));
+    assert!(call(r#"{"jsonrpc": "2.0", "method": "fly", "id": 4}"#).contains("-32601"));
+    assert!(call("{").contains("-32700"));
+    let batch = call(
+        r#"[{"jsonrpc": "2.0", "method": "finish_task", "params": {"id": 1}},
+            {"jsonrpc": "2.0", "method": "get_finished", "params": {"last_n": 5}, "id": 5}]"#
+            .replace('\n', "")
+            .as_str(),
+    );
+    assert!(batch.starts_with("[") && batch.contains("aria"));
+
+    // a change by another invocation is pushed to the client
+    add_task(&db, "undine", None);
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    assert!(line.contains(r#""method":"changed""#));
+    // and so is one made just before a request, whichever comes first
+    add_task(&db, "gondola", None);
+    writeln!(
+        stdin,
+        r#"{{"jsonrpc": "2.0", "method": "schema_version", "id": 8}}"#
+    )
+    .unwrap();
+    let mut lines = [String::new(), String::new()];
+    for line in lines.iter_mut() {
+        stdout.read_line(line).unwrap();
+    }
+    assert!(lines.iter().any(|l| l.contains(r#""method":"changed""#)));
+    assert!(lines.iter().any(|l| l.contains(r#""id":8"#)));
+
+    drop(stdin);
+    assert!(rpc.wait().unwrap().success());
+}
+
+// a `todo serve` on a free local port, killed when dropped
+struct Served {
+    child: Child,
+    addr: String,
+    token: String,
+}
+
+impl Served {
+    fn start(db: &TestDb) -> Self {
+        let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin(BINARY_NAME))
+            .env("TODO_DB", db.url())
+            .args(["serve", "--bind", "127.0.0.1:0"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let addr = line
+            .split("http://")
+            .nth(1)
+            .and_then(|rest| rest.split(',').next())
+            .unwrap()
+            .to_owned();
+        let token = fs::read_to_string(db.path("serve.token")).unwrap();
+        Served {
+            child,
+            addr,
+            token: token.trim().to_owned(),
+        }
+    }
+
+    // an authorized request: the status, the etag and the body of the reply
+    fn call(
+        &self,
+        method: &str,
+        path: &str,
+        header: Option<(&str, &str)>,
+        body: &str,
+    ) -> (u16, Option<String>, String) {
+        let authorization = format!("Bearer {}", self.token);
+        let mut headers = vec![("Authorization", authorization.as_str())];
+        headers.extend(header);
+        self.send(method, path, &headers, body)
+    }
+
+    fn send(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &str,
+    ) -> (u16, Option<String>, String) {
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+            method,
+            path,
+            self.addr,
+            body.len()
+        );
+        for (field, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", field, value));
+        }
+        request.push_str("\r\n");
+        request.push_str(body);
+        let mut stream = TcpStream::connect(&self.addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        let etag = head
+            .lines()
+            .filter_map(|line| line.split_once(": "))
+            .find(|(field, _)| field.eq_ignore_ascii_case("etag"))
+            .map(|(_, value)| value.to_owned());
+        (status, etag, body.to_owned())
+    }
+}
+
+impl Drop for Served {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn command_assert(db: &TestDb, args: &[&str]) -> Assert {
+    Command::cargo_bin(BINARY_NAME)
+        .unwrap()
+        .env("TODO_DB", db.url())
+        .args(args)
+        .assert()
 }
 
-fn command_assert(args: &[&str]) -> Assert {
+fn store_assert(url: &str, args: &[&str]) -> Assert {
     Command::cargo_bin(BINARY_NAME)
         .unwrap()
-        .env("TODO_DB", TODO_DB)
+        .env("TODO_DB", url)
         .args(args)
         .assert()
 }
 
-fn list_tasks_contains(pattern: &str) {
-    command_assert(&["list"]).stdout(contains(pattern));
+fn list_tasks_contains(db: &TestDb, pattern: &str) {
+    command_assert(db, &["list"]).stdout(contains(pattern));
 }
 
-fn add_task(what: &str, link: Option<&str>) {
+fn add_task(db: &TestDb, what: &str, link: Option<&str>) {
     if let Some(l) = link {
-        Command::cargo_bin(BINARY_NAME)
-            .unwrap()
-            .args(&["add", what, "-l", l])
-            .env("TODO_DB", TODO_DB)
-            .assert()
-            .success();
+        command_assert(db, &["add", what, "-l", l]).success();
     } else {
-        Command::cargo_bin(BINARY_NAME)
-            .unwrap()
-            .env("TODO_DB", TODO_DB)
-            .args(&["add", what])
-            .assert()
-            .success();
+        command_assert(db, &["add", what]).success();
     }
 }
 
-fn finish_task(id: i32, success: bool) {
-    let a = command_assert(&["fin", &id.to_string()]);
+fn finish_task(db: &TestDb, id: i32, success: bool) {
+    let a = command_assert(db, &["fin", &id.to_string()]);
     if success {
         a.success();
     } else {
@@ -75,6 +1190,6 @@ fn finish_task(id: i32, success: bool) {
     }
 }
 
-fn tidy() {
-    command_assert(&["tidy"]).success();
+fn tidy(db: &TestDb) {
+    command_assert(db, &["tidy"]).success();
 }
@@ -0,0 +1,579 @@
+// every TaskDB backend must pass the same checks
+use tempfile::TempDir;
+use todo::error::TodoError;
+use todo::filedb::{FileFormat, TaskFile};
+use todo::memdb::TaskMemory;
+use todo::models::{FieldTime, History, NewTask, SubTask, Task};
+use todo::query::{Page, Sort, TaskFilter};
+use todo::taskdb::{open, TaskDB, SCHEMA_VERSION};
+
+// a store under test, with the directory holding its files if it has any
+struct Backend {
+    db: Box<dyn TaskDB>,
+    _dir: Option<TempDir>,
+}
+
+fn sqlite() -> Backend {
+    Backend {
+        db: open(":memory:").unwrap(),
+        _dir: None,
+    }
+}
+
+fn memory() -> Backend {
+    Backend {
+        db: Box::new(TaskMemory::new()),
+        _dir: None,
+    }
+}
+
+fn json() -> Backend {
+    let dir = TempDir::new().unwrap();
+    let db = TaskFile::open(&dir.path().join("todo.json"), FileFormat::Json).unwrap();
+    Backend {
+        db: Box::new(db),
+        _dir: Some(dir),
+    }
+}
+
+macro_rules! backend {
+    ($backend:ident, $($check:ident),*) => {
+        mod $backend {
+            $(
+                #[test]
+                fn $check() {
+                    super::$check(super::$backend().db.as_mut());
+                }
+            )*
+        }
+    };
+}
+
+macro_rules! conformance {
+    ($($check:ident),* $(,)?) => {
+        backend!(sqlite, $($check),*);
+        backend!(memory, $($check),*);
+        backend!(json, $($check),*);
+    };
+}
+
+conformance!(
+    ids_are_sequential,
+    last_id_is_reused_after_removal,
+    subtask_rank_counts_subtasks,
+    finished_subtasks_stay_done,
+    pattern_is_case_insensitive_like,
+    finish_moves_task_into_history,
+    history_is_newest_first,
+    queries_sort_and_page,
+    writes_keep_timestamps,
+    put_inserts_or_overwrites,
+    edits_stamp_field_times,
+    subtasks_follow_their_task,
+    schema_version_is_latest,
+    missing_tasks_are_not_found,
+    failed_transaction_changes_nothing,
+    batches_are_all_or_nothing,
+);
+
+fn add(db: &mut dyn TaskDB, what: &str) -> i32 {
+    db.add_task(NewTask {
+        what: what.to_owned(),
+        link: None,
+        priority: 5,
+        due: None,
+    })
+    .unwrap()
+}
+
+fn whats(tasks: Vec<Task>) -> Vec<String> {
+    tasks.into_iter().map(|t| t.what).collect()
+}
+
+fn ranks(subtasks: Vec<SubTask>) -> Vec<(String, i32)> {
+    subtasks
+        .into_iter()
+        .map(|st| (st.what, st.subtask_rank))
+        .collect()
+}
+
+fn history(id: i32, what: &str, finish_timestamp: i32) -> History {
+    History {
+        id,
+        what: what.to_owned(),
+        link: None,
+        finish_timestamp,
+        uid: format!("history-{}", id),
+        created_at: 0,
+        priority: None,
+    }
+}
+
+fn ids_are_sequential(db: &mut dyn TaskDB) {
+    assert_eq!(add(db, "a"), 1);
+    assert_eq!(add(db, "b"), 2);
+    assert_eq!(add(db, "c"), 3);
+    assert_eq!(whats(db.get_tasks(None).unwrap()), ["a", "b", "c"]);
+    assert_eq!(db.get_task(2).unwrap().unwrap().what, "b");
+}
+
+fn last_id_is_reused_after_removal(db: &mut dyn TaskDB) {
+    add(db, "a");
+    add(db, "b");
+    add(db, "c");
+    // removing a task in the middle leaves a hole
+    assert_eq!(db.remove_task(2).unwrap(), 1);
+    assert_eq!(add(db, "d"), 4);
+    // removing the last task hands its id out again
+    db.remove_task(4).unwrap();
+    assert_eq!(add(db, "e"), 4);
+    db.finish_task(4).unwrap();
+    assert_eq!(add(db, "f"), 4);
+    // removing a missing task is not an error, it just removes nothing
+    assert_eq!(db.remove_task(42).unwrap(), 0);
+    assert_eq!(whats(db.get_tasks(None).unwrap()), ["a", "c", "f"]);
+}
+
+fn subtask_rank_counts_subtasks(db: &mut dyn TaskDB) {
+    let id = add(db, "a");
+    db.add_subtask(id, "x".to_owned(), None).unwrap();
+    db.add_subtask(id, "y".to_owned(), Some("link".to_owned()))
+        .unwrap();
+    db.add_subtask(id, "z".to_owned(), None).unwrap();
+    assert_eq!(
+        ranks(db.get_subtasks(id).unwrap()),
+        [
+            ("x".to_owned(), 1),
+            ("y".to_owned(), 2),
+            ("z".to_owned(), 3)
+        ]
+    );
+    // ranks are not compacted, the next one is one more than the count
+    assert_eq!(db.remove_subtask(id, 1).unwrap(), 1);
+    assert_eq!(db.remove_subtask(id, 1).unwrap(), 0);
+    db.add_subtask(id, "w".to_owned(), None).unwrap();
+    assert_eq!(
+        ranks(db.get_subtasks(id).unwrap()),
+        [
+            ("y".to_owned(), 2),
+            ("z".to_owned(), 3),
+            ("w".to_owned(), 3)
+        ]
+    );
+    let other = add(db, "b");
+    db.add_subtask(other, "v".to_owned(), None).unwrap();
+    assert_eq!(
+        ranks(db.get_subtasks(other).unwrap()),
+        [("v".to_owned(), 1)]
+    );
+}
+
+fn finished_subtasks_stay_done(db: &mut dyn TaskDB) {
+    let id = add(db, "a");
+    db.add_subtask(id, "x".to_owned(), None).unwrap();
+    db.add_subtask(id, "y".to_owned(), None).unwrap();
+    assert_eq!(db.finish_subtask(id, 2).unwrap(), 1);
+    assert_eq!(db.finish_subtask(id, 3).unwrap(), 0);
+    let done: Vec<bool> = db
+        .get_subtasks(id)
+        .unwrap()
+        .iter()
+        .map(|st| st.done)
+        .collect();
+    assert_eq!(done, [false, true]);
+    // done subtasks still count for the rank of the next one
+    db.add_subtask(id, "z".to_owned(), None).unwrap();
+    assert_eq!(ranks(db.get_subtasks(id).unwrap())[2], ("z".to_owned(), 3));
+}
+
+fn pattern_is_case_insensitive_like(db: &mut dyn TaskDB) {
+    add(db, "Aria the Animation");
+    add(db, "aria the natural");
+    add(db, "amanchu");
+    let found = |db: &dyn TaskDB, p: &str| whats(db.get_tasks(Some(p.to_owned())).unwrap());
+    assert_eq!(
+        found(db, "ARIA"),
+        ["Aria the Animation", "aria the natural"]
+    );
+    assert_eq!(found(db, "THE a"), ["Aria the Animation"]);
+    assert_eq!(found(db, "a_a"), ["amanchu"]);
+    assert_eq!(found(db, "the%nat"), ["aria the natural"]);
+    assert!(found(db, "undine").is_empty());
+}
+
+fn finish_moves_task_into_history(db: &mut dyn TaskDB) {
+    let id = db
+        .add_task(NewTask {
+            what: "aria".to_owned(),
+            link: Some("https://aria.example".to_owned()),
+            priority: 1,
+            due: None,
+        })
+        .unwrap();
+    let uid = db.get_task(id).unwrap().unwrap().uid;
+    let history = db.finish_task(id).unwrap();
+    assert!(db.get_tasks(None).unwrap().is_empty());
+    assert_eq!(history.what, "aria");
+    assert_eq!(history.link.as_deref(), Some("https://aria.example"));
+    assert_eq!(history.uid, uid);
+    assert_eq!(db.get_finished(10).unwrap(), [history]);
+    assert!(db.finish_task(id).is_err());
+}
+
+fn history_is_newest_first(db: &mut dyn TaskDB) {
+    db.put_history(&history(1, "a", 100)).unwrap();
+    db.put_history(&history(2, "b", 300)).unwrap();
+    db.put_history(&history(3, "c", 200)).unwrap();
+    db.put_history(&history(4, "d", 300)).unwrap();
+    let whats = |hs: Vec<History>| hs.into_iter().map(|h| h.what).collect::<Vec<_>>();
+    assert_eq!(whats(db.get_finished(10).unwrap()), ["d", "b", "c", "a"]);
+    assert_eq!(whats(db.get_finished(2).unwrap()), ["d", "b"]);
+    // the window includes its start and excludes its end
+    assert_eq!(whats(db.get_finished_within(100, 300).unwrap()), ["c", "a"]);
+    assert_eq!(
+        whats(db.get_finished_within(0, i32::MAX as u32).unwrap()),
+        ["d", "b", "c", "a"]
+    );
+}
+
+fn queries_sort_and_page(db: &mut dyn TaskDB) {
+    let tasks = [
+        ("b", 5, 300, Some("2026-11-02")),
+        ("a", 5, 100, None),
+        ("d", 1, 200, Some("2026-11-01")),
+        ("c", 9, 400, None),
+    ];
+    for (id, (what, priority, created_at, due)) in (1..).zip(tasks) {
+        db.put_task(&Task {
+            id,
+            what: what.to_owned(),
+            link: None,
+            priority,
+            uid: what.to_owned(),
+            created_at,
+            due: due.map(str::to_owned),
+            updated_at: created_at,
+        })
+        .unwrap();
+    }
+    let query = |db: &dyn TaskDB, sort: &str, limit: Option<u32>, offset: u32| {
+        let sort: Sort = sort.parse().unwrap();
+        whats(
+            db.query_tasks(&TaskFilter::default(), &sort, Page { limit, offset })
+                .unwrap(),
+        )
+    };
+    assert_eq!(query(db, "priority", None, 0), ["d", "b", "a", "c"]);
+    assert_eq!(query(db, "priority:desc", None, 0), ["c", "a", "b", "d"]);
+    assert_eq!(query(db, "priority,alpha", None, 0), ["d", "a", "b", "c"]);
+    assert_eq!(query(db, "created:desc", None, 0), ["c", "b", "d", "a"]);
+    assert_eq!(
+        query(db, "alphabetical:desc", None, 0),
+        ["d", "c", "b", "a"]
+    );
+    // no due date goes last either way
+    assert_eq!(query(db, "due", None, 0), ["d", "b", "a", "c"]);
+    assert_eq!(query(db, "due:desc", None, 0), ["b", "d", "c", "a"]);
+    assert_eq!(query(db, "id", Some(2), 1), ["a", "d"]);
+    assert_eq!(query(db, "id", None, 3), ["c"]);
+    assert!(query(db, "id", Some(2), 9).is_empty());
+    let sort: Sort = "finished".parse().unwrap();
+    assert!(matches!(
+        db.query_tasks(&TaskFilter::default(), &sort, Page::default()),
+        Err(TodoError::InvalidInput(_))
+    ));
+
+    db.put_history(&history(1, "a", 100)).unwrap();
+    db.put_history(&history(2, "c", 300)).unwrap();
+    db.put_history(&history(3, "b", 200)).unwrap();
+    let finished = |db: &dyn TaskDB, sort: &str, page: Page| {
+        let sort: Sort = sort.parse().unwrap();
+        let histories = db.query_finished(0, 300, &sort, page).unwrap();
+        histories.into_iter().map(|h| h.what).collect::<Vec<_>>()
+    };
+    let all = Page::default();
+    assert_eq!(finished(db, "finished:desc", all), ["b", "a"]);
+    assert_eq!(finished(db, "alpha:desc", all), ["b", "a"]);
+    assert_eq!(
+        finished(
+            db,
+            "id",
+            Page {
+                limit: Some(1),
+                offset: 1
+            }
+        ),
+        ["b"]
+    );
+    let sort: Sort = "due".parse().unwrap();
+    assert!(matches!(
+        db.query_finished(0, 300, &sort, all),
+        Err(TodoError::InvalidInput(_))
+    ));
+}
+
+fn writes_keep_timestamps(db: &mut dyn TaskDB) {
+    add(db, "a");
+    let added = db.get_task(1).unwrap().unwrap();
+    assert!(added.created_at > 0);
+    assert_eq!(added.updated_at, added.created_at);
+
+    let old = |db: &mut dyn TaskDB| {
+        let task = db.get_task(1).unwrap().unwrap();
+        db.put_task(&Task {
+            created_at: 100,
+            updated_at: 200,
+            ..task
+        })
+        .unwrap();
+    };
+    let updated_at = |db: &dyn TaskDB| db.get_task(1).unwrap().unwrap().updated_at;
+    let stale = |db: &dyn TaskDB, before: i32| {
+        let filter = TaskFilter {
+            pattern: None,
+            updated_before: Some(before),
+        };
+        whats(
+            db.query_tasks(&filter, &Sort::default(), Page::default())
+                .unwrap(),
+        )
+    };
+    old(db);
+    assert_eq!(updated_at(db), 200);
+    assert_eq!(stale(db, 201), ["a"]);
+    assert!(stale(db, 200).is_empty());
+    db.update_task_priority(1, 3).unwrap();
+    assert!(updated_at(db) >= added.created_at);
+    old(db);
+    db.update_task_desc(1, "b".to_owned()).unwrap();
+    assert!(updated_at(db) >= added.created_at);
+    old(db);
+    db.add_subtask(1, "c".to_owned(), None).unwrap();
+    assert!(updated_at(db) >= added.created_at);
+    old(db);
+    db.finish_subtask(1, 1).unwrap();
+    assert!(updated_at(db) >= added.created_at);
+    old(db);
+    db.remove_subtask(1, 1).unwrap();
+    assert!(updated_at(db) >= added.created_at);
+
+    let finished = db.finish_task(1).unwrap();
+    assert_eq!(finished.created_at, 100);
+    assert_eq!(finished.priority, Some(3));
+    assert_eq!(finished.lead_time(), Some(finished.finish_timestamp - 100));
+    assert_eq!(db.get_finished(1).unwrap(), [finished]);
+}
+
+fn put_inserts_or_overwrites(db: &mut dyn TaskDB) {
+    add(db, "a");
+    let mut task = db.get_task(1).unwrap().unwrap();
+    task.what = "b".to_owned();
+    task.priority = 9;
+    db.put_task(&task).unwrap();
+    assert_eq!(db.get_task(1).unwrap().unwrap(), task);
+
+    let far = Task {
+        id: 7,
+        what: "c".to_owned(),
+        link: None,
+        priority: 5,
+        uid: "far".to_owned(),
+        created_at: 0,
+        due: None,
+        updated_at: 0,
+    };
+    db.put_task(&far).unwrap();
+    // ids continue after the largest one put
+    assert_eq!(add(db, "d"), 8);
+
+    let subtask = SubTask {
+        id: 3,
+        what: "x".to_owned(),
+        link: None,
+        subtask_rank: 1,
+        task_id: 7,
+        uid: "sub".to_owned(),
+        done: false,
+    };
+    db.put_subtask(&subtask).unwrap();
+    assert_eq!(db.get_subtasks(7).unwrap(), [subtask]);
+
+    db.put_history(&history(5, "e", 100)).unwrap();
+    db.put_history(&history(5, "f", 100)).unwrap();
+    assert_eq!(db.get_finished(10).unwrap(), [history(5, "f", 100)]);
+}
+
+fn edits_stamp_field_times(db: &mut dyn TaskDB) {
+    add(db, "a");
+    db.add_subtask(1, "x".to_owned(), None).unwrap();
+    // nothing has changed since it was added
+    assert!(db.get_field_times().unwrap().is_empty());
+
+    let task = db.get_task(1).unwrap().unwrap();
+    db.update_task_priority(1, 3).unwrap();
+    let priority = db.get_field_times().unwrap();
+    assert_eq!(priority.len(), 1);
+    assert_eq!(
+        (priority[0].uid.as_str(), priority[0].field.as_str()),
+        (task.uid.as_str(), "priority")
+    );
+    assert!(priority[0].changed_at >= task.created_at);
+
+    // put_task stamps what it changes with the task's updated_at
+    let task = db.get_task(1).unwrap().unwrap();
+    db.put_task(&Task {
+        due: Some("2030-01-01".to_owned()),
+        link: Some("https://a.example".to_owned()),
+        updated_at: 12345,
+        ..task.clone()
+    })
+    .unwrap();
+    let mut times = db.get_field_times().unwrap();
+    times.sort_by(|a, b| a.field.cmp(&b.field));
+    let stamp = |field: &str, changed_at: i32| FieldTime {
+        uid: task.uid.clone(),
+        field: field.to_owned(),
+        changed_at,
+    };
+    assert_eq!(
+        times,
+        [
+            stamp("due", 12345),
+            stamp("link", 12345),
+            priority[0].clone()
+        ]
+    );
+
+    let subtask = db.get_subtasks(1).unwrap().remove(0);
+    db.put_subtask(&SubTask {
+        what: "y".to_owned(),
+        ..subtask.clone()
+    })
+    .unwrap();
+    assert!(db
+        .get_field_times()
+        .unwrap()
+        .iter()
+        .any(|ft| ft.uid == subtask.uid && ft.field == "what"));
+
+    db.put_field_time(&stamp("due", 7)).unwrap();
+    assert!(db.get_field_times().unwrap().contains(&stamp("due", 7)));
+}
+
+fn subtasks_follow_their_task(db: &mut dyn TaskDB) {
+    let a = add(db, "a");
+    let b = add(db, "b");
+    db.add_subtask(b, "y".to_owned(), None).unwrap();
+    db.add_subtask(a, "x".to_owned(), None).unwrap();
+    db.add_subtask(b, "z".to_owned(), None).unwrap();
+    let all: Vec<(i32, String)> = db
+        .get_all_subtasks()
+        .unwrap()
+        .into_iter()
+        .map(|st| (st.task_id, st.what))
+        .collect();
+    assert_eq!(
+        all,
+        [
+            (a, "x".to_owned()),
+            (b, "y".to_owned()),
+            (b, "z".to_owned())
+        ]
+    );
+
+    assert_eq!(db.update_subtask_belongings(b, a).unwrap(), 2);
+    assert_eq!(db.get_subtasks(a).unwrap().len(), 3);
+    assert!(db.get_subtasks(b).unwrap().is_empty());
+}
+
+fn schema_version_is_latest(db: &mut dyn TaskDB) {
+    assert_eq!(
+        db.schema_version().unwrap().as_deref(),
+        Some(SCHEMA_VERSION)
+    );
+}
+
+fn missing_tasks_are_not_found(db: &mut dyn TaskDB) {
+    let not_found = |r: Result<_, TodoError>| {
+        matches!(
+            r,
+            Err(TodoError::NotFound {
+                kind: "task",
+                id: 9
+            })
+        )
+    };
+    assert_eq!(db.get_task(9).unwrap(), None);
+    assert!(not_found(db.get_subtasks(9).map(|_| ())));
+    assert!(not_found(db.add_subtask(9, "x".to_owned(), None)));
+    assert!(not_found(db.finish_task(9).map(|_| ())));
+    assert_eq!(db.update_task_desc(9, "x".to_owned()).unwrap(), 0);
+    assert_eq!(db.update_task_priority(9, 1).unwrap(), 0);
+    assert!(matches!(
+        db.add_task(NewTask {
+            what: " ".to_owned(),
+            link: None,
+            priority: 5,
+            due: None,
+        }),
+        Err(TodoError::InvalidInput(_))
+    ));
+}
+
+fn failed_transaction_changes_nothing(db: &mut dyn TaskDB) {
+    add(db, "a");
+    add(db, "b");
+    let result = db.transaction(&mut |db| {
+        db.finish_task(1)?;
+        db.update_task_priority(2, 1)?;
+        db.add_task(NewTask {
+            what: "".to_owned(),
+            link: None,
+            priority: 5,
+            due: None,
+        })
+        .map(|_| ())
+    });
+    assert!(matches!(result, Err(TodoError::InvalidInput(_))));
+    assert_eq!(whats(db.get_tasks(None).unwrap()), ["a", "b"]);
+    assert_eq!(db.get_task(2).unwrap().unwrap().priority, 5);
+    assert!(db.get_finished(10).unwrap().is_empty());
+
+    // nested transactions commit with the outer one
+    db.transaction(&mut |db| {
+        db.finish_task(1)?;
+        let inner = db.transaction(&mut |db| {
+            db.remove_task(2)?;
+            db.finish_task(1).map(|_| ())
+        });
+        assert!(inner.is_err());
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(whats(db.get_tasks(None).unwrap()), ["b"]);
+    assert_eq!(db.get_finished(10).unwrap().len(), 1);
+}
+
+fn batches_are_all_or_nothing(db: &mut dyn TaskDB) {
+    add(db, "a");
+    add(db, "b");
+    add(db, "c");
+    assert!(matches!(
+        db.finish_tasks(&[1, 7, 3, 9, 7]),
+        Err(TodoError::Missing { kind: "task", ids }) if ids == [7, 9]
+    ));
+    assert!(db.remove_tasks(&[2, 8]).is_err());
+    assert!(db.update_priorities(&[(1, 1), (8, 1)]).is_err());
+    assert_eq!(whats(db.get_tasks(None).unwrap()), ["a", "b", "c"]);
+    assert_eq!(db.get_task(1).unwrap().unwrap().priority, 5);
+    assert!(db.get_finished(10).unwrap().is_empty());
+
+    assert_eq!(db.update_priorities(&[(1, 1), (3, 9)]).unwrap(), 2);
+    assert_eq!(db.get_task(3).unwrap().unwrap().priority, 9);
+    // a repeated id is finished once
+    let finished = db.finish_tasks(&[1, 3, 1]).unwrap();
+    assert_eq!(finished.len(), 2);
+    assert_eq!(db.get_finished(10).unwrap().len(), 2);
+    assert_eq!(db.remove_tasks(&[2]).unwrap(), 1);
+    assert!(db.get_tasks(None).unwrap().is_empty());
+}
@@ -0,0 +1,80 @@
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use todo::dates::{Tense, When};
+
+fn day(y: i32, m: u32, d: u32) -> NaiveDate {
+    NaiveDate::from_ymd(y, m, d)
+}
+
+// a monday afternoon
+fn now() -> NaiveDateTime {
+    day(2026, 10, 19).and_hms(15, 20, 0)
+}
+
+fn parse(input: &str, tense: Tense) -> When {
+    When::parse(input, now(), tense).unwrap()
+}
+
+#[test]
+fn days_by_name_and_offset() {
+    let days = [
+        ("2026-1-3", Tense::Past, day(2026, 1, 3)),
+        ("today", Tense::Past, day(2026, 10, 19)),
+        ("Yesterday", Tense::Past, day(2026, 10, 18)),
+        ("tomorrow", Tense::Past, day(2026, 10, 20)),
+        ("monday", Tense::Future, day(2026, 10, 19)),
+        ("fri", Tense::Future, day(2026, 10, 23)),
+        ("friday", Tense::Past, day(2026, 10, 16)),
+        ("next monday", Tense::Past, day(2026, 10, 26)),
+        ("last monday", Tense::Future, day(2026, 10, 12)),
+        ("last week", Tense::Past, day(2026, 10, 12)),
+        ("this month", Tense::Past, day(2026, 10, 1)),
+        ("next month", Tense::Past, day(2026, 11, 1)),
+        ("+3d", Tense::Past, day(2026, 10, 22)),
+        ("-3d", Tense::Future, day(2026, 10, 16)),
+        ("2w", Tense::Future, day(2026, 11, 2)),
+        ("2w", Tense::Past, day(2026, 10, 5)),
+        ("eow", Tense::Past, day(2026, 10, 25)),
+        ("eom", Tense::Past, day(2026, 10, 31)),
+        ("eoy", Tense::Past, day(2026, 12, 31)),
+    ];
+    for (input, tense, date) in days {
+        assert_eq!(parse(input, tense), When { date, time: None }, "{}", input);
+    }
+}
+
+#[test]
+fn times_of_day() {
+    let at = |date: NaiveDate, h: u32, m: u32| When {
+        date,
+        time: Some(NaiveTime::from_hms(h, m, 0)),
+    };
+    assert_eq!(parse("14:30", Tense::Past), at(day(2026, 10, 19), 14, 30));
+    assert_eq!(
+        parse("tomorrow at 9am", Tense::Future),
+        at(day(2026, 10, 20), 9, 0)
+    );
+    assert_eq!(
+        parse("2026-10-01 12:15pm", Tense::Past),
+        at(day(2026, 10, 1), 12, 15)
+    );
+    assert_eq!(parse("12am", Tense::Past), at(day(2026, 10, 19), 0, 0));
+    assert_eq!(parse("now", Tense::Past), at(day(2026, 10, 19), 15, 20));
+    assert_eq!(parse("+9h", Tense::Past), at(day(2026, 10, 20), 0, 20));
+    assert_eq!(parse("16h", Tense::Past), at(day(2026, 10, 18), 23, 20));
+
+    for input in [
+        "someday",
+        "2026-13-01",
+        "13pm",
+        "next year",
+        "now 14:00",
+        "",
+        "3x",
+        "+99999999d",
+        "4000000000w",
+        "-4000000000h",
+    ] {
+        let e = When::parse(input, now(), Tense::Past).unwrap_err();
+        assert!(e.to_string().contains(&format!("`{}`", input)), "{}", e);
+    }
+}
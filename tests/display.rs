@@ -0,0 +1,105 @@
+use todo::display::{ellipsize, text_width, wrap, TaskColumns, Theme};
+use todo::models::Task;
+
+fn task(id: i32, what: &str, link: Option<&str>) -> Task {
+    Task {
+        id,
+        what: what.to_owned(),
+        link: link.map(str::to_owned),
+        priority: 5,
+        uid: format!("task-{}", id),
+        created_at: 0,
+        due: None,
+        updated_at: 0,
+    }
+}
+
+#[test]
+fn wide_characters_take_two_columns() {
+    assert_eq!(text_width("aria"), 4);
+    assert_eq!(text_width("🥵"), 2);
+    assert_eq!(text_width("日本語"), 6);
+    assert_eq!(text_width("short\na longer line"), 13);
+
+    assert_eq!(ellipsize("aria", 4), "aria");
+    assert_eq!(ellipsize("https://aria.example", 10), "https://a…");
+    // a wide character that doesn't fit whole is left out
+    assert_eq!(ellipsize("日本語", 5), "日本…");
+
+    // the escapes of painted text take no room and are closed when cut
+    let theme = Theme::default();
+    let link = theme.paint("https://aria.example", theme.link_color());
+    assert_eq!(ellipsize(&link, 20), link);
+    assert_eq!(ellipsize(&link, 10), "\x1b[36mhttps://a\x1b[0m…");
+}
+
+#[test]
+fn wrap_breaks_between_words() {
+    assert_eq!(
+        wrap("the quick brown fox jumps", 10),
+        ["the quick", "brown fox", "jumps"]
+    );
+    // line breaks are kept and overlong words are split anywhere
+    assert_eq!(
+        wrap("links:\nhttps://aria.example", 8),
+        ["links:", "https://", "aria.exa", "mple"]
+    );
+    assert_eq!(wrap("日本語の説明", 5), ["日本", "語の", "説明"]);
+    assert_eq!(wrap("", 5), [""]);
+}
+
+#[test]
+fn columns_fit_the_terminal() {
+    let mut tasks = vec![
+        task(
+            1,
+            "aria",
+            Some("https://example.com/a/long/link/to/somewhere"),
+        ),
+        task(12, "amanchu", None),
+    ];
+    let theme = Theme::default();
+    // 13 for the borders, 2 for the ids, 3 for the priority;
+    // short descriptions leave the rest to the links
+    let columns = TaskColumns::fit(&tasks, &theme, 60);
+    assert_eq!(columns, TaskColumns { what: 11, link: 31 });
+    // too narrow a terminal still leaves room for the headers
+    let columns = TaskColumns::fit(&tasks, &theme, 20);
+    assert_eq!(columns, TaskColumns { what: 11, link: 4 });
+
+    // long descriptions take the room back, down to a third for the links
+    tasks[1].what = "amanchu ".repeat(10);
+    let columns = TaskColumns::fit(&tasks, &theme, 60);
+    assert_eq!(columns, TaskColumns { what: 28, link: 14 });
+    // short links keep their width
+    let columns = TaskColumns::fit(&tasks[1..], &theme, 60);
+    assert_eq!(columns, TaskColumns { what: 38, link: 4 });
+    // due dates and wider priority labels take their share
+    tasks[1].due = Some("2026-10-19".to_owned());
+    tasks[1].priority = 9;
+    let ascii = Theme::named("ascii").unwrap();
+    let columns = TaskColumns::fit(&tasks[1..], &ascii, 60);
+    assert_eq!(columns, TaskColumns { what: 22, link: 4 });
+}
+
+#[test]
+fn themes_label_and_color_priorities() {
+    let emoji = Theme::default();
+    assert_eq!(emoji.priority_label(0), "🥶");
+    assert_eq!(emoji.priority_label(12), "🥵");
+    assert_eq!(
+        emoji.paint("aria", emoji.link_color()),
+        "\x1b[36maria\x1b[0m"
+    );
+
+    let plain: Theme = "plain".parse().unwrap();
+    assert_eq!(plain.priority_label(5), "mid");
+    assert_eq!(plain.paint("aria", plain.link_color()), "aria");
+    assert!("neon".parse::<Theme>().is_err());
+
+    let mut labels = emoji.with_labels(".,-,+,!").unwrap();
+    assert_eq!(labels.priority_label(8), "+");
+    labels.color = false;
+    assert_eq!(labels.priority_color(8), None);
+    assert!(Theme::default().with_labels("low,high").is_err());
+}
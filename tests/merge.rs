@@ -0,0 +1,101 @@
+use todo::memdb::TaskMemory;
+use todo::merge::{apply, plan, Change};
+use todo::models::{FieldTime, NewTask, SubTask};
+use todo::taskdb::TaskDB;
+
+fn stamp(db: &mut TaskMemory, uid: &str, field: &str, changed_at: i32) {
+    db.put_field_time(&FieldTime {
+        uid: uid.to_owned(),
+        field: field.to_owned(),
+        changed_at,
+    })
+    .unwrap();
+}
+
+// a store with one task holding one subtask, and its copy; with the uid of the
+// task and the time it was added
+fn copies() -> (TaskMemory, TaskMemory, String, i32) {
+    let mut ours = TaskMemory::new();
+    ours.add_task(NewTask {
+        what: "aria".to_owned(),
+        link: None,
+        priority: 5,
+        due: None,
+    })
+    .unwrap();
+    ours.add_subtask(1, "undine".to_owned(), None).unwrap();
+    let task = ours.get_task(1).unwrap().unwrap();
+    let theirs = ours.clone();
+    (ours, theirs, task.uid, task.created_at)
+}
+
+#[test]
+fn edits_to_different_fields_are_all_kept() {
+    let (mut ours, mut theirs, uid, added) = copies();
+    ours.update_task_priority(1, 9).unwrap();
+    stamp(&mut ours, &uid, "priority", added + 10);
+    // their edit is the later one, but only of the description
+    theirs
+        .update_task_desc(1, "aria company".to_owned())
+        .unwrap();
+    stamp(&mut theirs, &uid, "what", added + 20);
+
+    let changes = plan(&ours, &theirs).unwrap();
+    assert_eq!(changes.len(), 1);
+    assert!(matches!(&changes[0], Change::EditTask { .. }));
+    apply(&mut ours, changes).unwrap();
+    let task = ours.get_task(1).unwrap().unwrap();
+    assert_eq!((task.what.as_str(), task.priority), ("aria company", 9));
+    // what they changed keeps the time they changed it
+    assert!(ours.get_field_times().unwrap().contains(&FieldTime {
+        uid: uid.clone(),
+        field: "what".to_owned(),
+        changed_at: added + 20,
+    }));
+
+    // and the other way round nothing is left to take
+    theirs.update_task_priority(1, 9).unwrap();
+    stamp(&mut theirs, &uid, "priority", added + 10);
+    assert!(plan(&theirs, &ours).unwrap().is_empty());
+}
+
+#[test]
+fn the_later_edit_of_a_field_wins() {
+    let (mut ours, mut theirs, uid, added) = copies();
+    ours.update_task_priority(1, 9).unwrap();
+    stamp(&mut ours, &uid, "priority", added + 20);
+    theirs.update_task_priority(1, 1).unwrap();
+    stamp(&mut theirs, &uid, "priority", added + 10);
+
+    assert!(plan(&ours, &theirs).unwrap().is_empty());
+    let changes = plan(&theirs, &ours).unwrap();
+    apply(&mut theirs, changes).unwrap();
+    assert_eq!(theirs.get_task(1).unwrap().unwrap().priority, 9);
+}
+
+#[test]
+fn subtasks_merge_field_by_field() {
+    let (mut ours, mut theirs, _, added) = copies();
+    let subtask = ours.get_subtasks(1).unwrap().remove(0);
+    ours.put_subtask(&SubTask {
+        link: Some("https://undine.example".to_owned()),
+        ..subtask.clone()
+    })
+    .unwrap();
+    stamp(&mut ours, &subtask.uid, "link", added + 10);
+    theirs
+        .put_subtask(&SubTask {
+            what: "gondola".to_owned(),
+            ..subtask.clone()
+        })
+        .unwrap();
+    stamp(&mut theirs, &subtask.uid, "what", added + 20);
+    theirs.finish_subtask(1, 1).unwrap();
+
+    let changes = plan(&ours, &theirs).unwrap();
+    apply(&mut ours, changes).unwrap();
+    let merged = ours.get_subtasks(1).unwrap().remove(0);
+    assert_eq!(merged.what, "gondola");
+    assert_eq!(merged.link.as_deref(), Some("https://undine.example"));
+    assert!(merged.done);
+}
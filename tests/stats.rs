@@ -0,0 +1,84 @@
+use chrono::{FixedOffset, NaiveDate, TimeZone};
+use todo::display::sparkline;
+use todo::models::History;
+use todo::stats::{Period, Stats};
+
+fn history(id: i32, what: &str, finish_timestamp: i64, priority: Option<i32>) -> History {
+    History {
+        id,
+        what: what.to_owned(),
+        link: None,
+        finish_timestamp: finish_timestamp as i32,
+        uid: format!("history-{}", id),
+        created_at: finish_timestamp as i32 - 2 * 3600,
+        priority,
+    }
+}
+
+fn day(y: i32, m: u32, d: u32) -> NaiveDate {
+    NaiveDate::from_ymd(y, m, d)
+}
+
+#[test]
+fn days_are_those_of_the_timezone() {
+    let tokyo = FixedOffset::east(9 * 3600);
+    let at = |d: u32, h: u32| tokyo.ymd(2026, 10, d).and_hms(h, 0, 0).timestamp();
+    let histories = [
+        // late at night in Tokyo, still the day before in UTC
+        history(1, "a #work", at(17, 1), Some(9)),
+        history(2, "b #work #home", at(18, 23), Some(5)),
+        history(3, "c", at(19, 8), None),
+        history(4, "d #home", at(14, 12), Some(1)),
+    ];
+    let stats = Stats::new(&histories, &tokyo, day(2026, 10, 19), Period::Day, 3).unwrap();
+    assert_eq!(
+        stats.counts,
+        [
+            (day(2026, 10, 17), 1),
+            (day(2026, 10, 18), 1),
+            (day(2026, 10, 19), 1)
+        ]
+    );
+    assert_eq!(stats.total(), 3);
+    assert_eq!((stats.current_streak, stats.longest_streak), (3, 3));
+    assert_eq!(stats.average_lead_time, Some(2 * 3600));
+    assert_eq!(stats.bands, [0, 1, 0, 1]);
+    assert_eq!(stats.unknown_priority, 1);
+    assert_eq!(stats.tags, [("work".to_owned(), 2), ("home".to_owned(), 1)]);
+
+    let utc = FixedOffset::east(0);
+    let stats = Stats::new(&histories, &utc, day(2026, 10, 19), Period::Day, 3).unwrap();
+    assert_eq!(stats.counts[0], (day(2026, 10, 17), 0));
+
+    // nothing finished yet today keeps the streak of yesterday
+    let stats = Stats::new(&histories, &tokyo, day(2026, 10, 20), Period::Week, 2).unwrap();
+    assert_eq!(stats.current_streak, 3);
+    assert_eq!(
+        stats.counts,
+        [(day(2026, 10, 12), 3), (day(2026, 10, 19), 1)]
+    );
+    assert_eq!(Period::Week.label(day(2026, 10, 19)), "2026-W43");
+    let stats = Stats::new(&histories, &tokyo, day(2026, 10, 22), Period::Month, 2).unwrap();
+    assert_eq!(stats.current_streak, 0);
+    assert_eq!(stats.counts, [(day(2026, 9, 1), 0), (day(2026, 10, 1), 4)]);
+    assert_eq!(
+        Period::Month.previous(day(2026, 1, 1)),
+        Some(day(2025, 12, 1))
+    );
+
+    // more periods than there are dates before today
+    let err = Stats::new(
+        &histories,
+        &tokyo,
+        day(2026, 10, 22),
+        Period::Day,
+        100_000_000,
+    );
+    assert_eq!(err.unwrap_err().exit_code(), 4);
+}
+
+#[test]
+fn sparkline_scales_to_the_largest() {
+    assert_eq!(sparkline(&[0, 1, 3, 6]), "▁▃▅█");
+    assert_eq!(sparkline(&[0, 0]), "▁▁");
+}
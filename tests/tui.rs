@@ -0,0 +1,80 @@
+#![cfg(feature = "tui")]
+use ratatui::backend::TestBackend;
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::Terminal;
+use todo::memdb::TaskMemory;
+use todo::taskdb::TaskDB;
+use todo::tui::{App, Mode};
+
+fn keys(app: &mut App, keys: &str) {
+    for c in keys.chars() {
+        let code = match c {
+            '\n' => KeyCode::Enter,
+            '\x1b' => KeyCode::Esc,
+            '\x08' => KeyCode::Backspace,
+            c => KeyCode::Char(c),
+        };
+        app.handle_key(KeyEvent::new(code, KeyModifiers::NONE));
+    }
+}
+
+fn whats(app: &App) -> Vec<String> {
+    app.tasks.iter().map(|t| t.what.clone()).collect()
+}
+
+#[test]
+fn keys_change_the_store() {
+    let mut db = TaskMemory::new();
+    let mut app = App::new(&mut db).unwrap();
+    keys(&mut app, "aaria\naamanchu\naundine\n");
+    assert_eq!(whats(&app), ["aria", "amanchu", "undine"]);
+
+    // the priority moves amanchu to the top, and the cursor with it
+    keys(&mut app, "jp\x081\n");
+    assert_eq!(whats(&app), ["amanchu", "aria", "undine"]);
+    assert_eq!(app.selected_task().unwrap().what, "amanchu");
+    keys(&mut app, "e\x08\x08\x08\x08\x08\x08\x08kodama\n");
+    assert_eq!(app.selected_task().unwrap().what, "kodama");
+
+    keys(&mut app, "f");
+    assert_eq!(app.history[0].what, "kodama");
+    keys(&mut app, "Gdn");
+    assert_eq!(app.tasks.len(), 2);
+    keys(&mut app, "dy");
+    assert_eq!(whats(&app), ["aria"]);
+
+    keys(&mut app, "p\x08x\n");
+    assert!(app.status.contains("not a priority"));
+    assert_eq!(app.mode, Mode::Normal);
+    keys(&mut app, "q");
+    assert!(app.quit);
+    drop(app);
+    assert_eq!(db.get_tasks(None).unwrap().len(), 1);
+}
+
+#[test]
+fn filter_narrows_the_list_while_typing() {
+    let mut db = TaskMemory::new();
+    let mut app = App::new(&mut db).unwrap();
+    keys(&mut app, "aaria\naamanchu\naaria the natural\n");
+    keys(&mut app, "/ari");
+    assert_eq!(app.mode, Mode::Filter);
+    assert_eq!(whats(&app), ["aria", "aria the natural"]);
+    keys(&mut app, "a t\n");
+    assert_eq!(whats(&app), ["aria the natural"]);
+    keys(&mut app, "/\x1b");
+    assert_eq!(app.tasks.len(), 3);
+
+    let mut terminal = Terminal::new(TestBackend::new(100, 30)).unwrap();
+    terminal.draw(|frame| app.draw(frame)).unwrap();
+    let screen: String = terminal
+        .backend()
+        .buffer()
+        .content()
+        .iter()
+        .map(|cell| cell.symbol())
+        .collect();
+    for pane in ["tasks", "subtasks", "history", "amanchu"] {
+        assert!(screen.contains(pane));
+    }
+}